@@ -23,6 +23,16 @@ fn main() {
         (97, 9),
         (106, 9),
         (115, 9),
+        (124, 1),
+        (125, 8),
+        (133, 9),
+        (142, 1),
+        (143, 1),
+        (144, 1),
+        (145, 1),
+        (146, 1),
+        (147, 9),
+        (156, 8),
     ];
 
     let mut code = String::new();
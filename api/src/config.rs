@@ -26,8 +26,116 @@ pub struct BootloaderConfig {
     ///
     /// The stack is created with a additional guard page, so a stack overflow will lead to
     /// a page fault.
+    ///
+    /// Defaults to 80 KiB. Every page of the stack is backed by a physical frame before the
+    /// kernel is started, so the size is only limited by the available memory. Sizes above a
+    /// few MiB are rarely useful, since the frames can't be reclaimed by the kernel. If the
+    /// bootloader runs out of memory while mapping the stack, it reports the failing page
+    /// and stops the boot.
     pub kernel_stack_size: u64,
 
+    /// The size of the unmapped guard region below the kernel stack (in bytes).
+    ///
+    /// A stack overflow runs into this region and leads to a page fault. The bootloader
+    /// also ensures that there are at least this many unmapped bytes between the end of the
+    /// kernel image and the bottom of the stack. The bootloader panics if a
+    /// [fixed stack address](Mappings::kernel_stack) would violate this. Dynamically placed
+    /// stacks always satisfy it, as they are mapped in unused level 4 entries.
+    ///
+    /// The size is rounded up to the next multiple of the page size and is always at least
+    /// one page. Defaults to one page (4KiB).
+    pub kernel_stack_guard_size: u64,
+
+    /// Whether the bootloader should enable process-context identifiers (PCIDs) by setting
+    /// `CR4.PCIDE` before jumping to the kernel.
+    ///
+    /// If enabled, the lower 12 bits of the `CR3` register are interpreted as the PCID of
+    /// the current address space, so the kernel becomes responsible for managing PCIDs and
+    /// invalidating stale TLB entries. This option is ignored if the CPU does not support
+    /// PCIDs. The [`cpu_features`][crate::info::BootInfo::cpu_features] field of the boot info
+    /// reports whether the bit was set.
+    ///
+    /// Defaults to `false`.
+    pub enable_pcid: bool,
+
+    /// Instructs the bootloader to reserve a physically contiguous buffer of the given size
+    /// (in bytes) below 4GiB, e.g. for early DMA transfers.
+    ///
+    /// The buffer is identity-mapped with caching disabled and reported as
+    /// [`DmaBuffer`][crate::info::MemoryRegionKind::DmaBuffer] memory in the memory map. Its
+    /// address is reported in the [`dma_buffer_addr`][crate::info::BootInfo::dma_buffer_addr]
+    /// field of the boot info. The size is rounded up to the page size. The bootloader only
+    /// uses frames whose identity mapping doesn't overlap the kernel segments or other
+    /// existing mappings, and panics if no such memory is available.
+    ///
+    /// Defaults to `None`, i.e. no buffer is reserved.
+    pub dma_buffer_size: Option<u64>,
+
+    /// Whether the bootloader should allocate a separate kernel stack for every CPU.
+    ///
+    /// If enabled, the bootloader allocates an additional stack of `kernel_stack_size` bytes
+    /// (with a guard region of `kernel_stack_guard_size` bytes below it) for every application
+    /// processor. The bounds of all stacks are reported in the
+    /// [`cpu_stacks`][crate::info::BootInfo::cpu_stacks] field of the boot info. The CPU count
+    /// is queried from the UEFI firmware. On BIOS systems, only a stack for the bootstrap
+    /// processor is reported.
+    ///
+    /// Defaults to `false`.
+    pub smp_kernel_stacks: bool,
+
+    /// Whether the bootloader should allocate a back buffer for the framebuffer.
+    ///
+    /// If enabled, the bootloader allocates a physically contiguous, zeroed buffer of the
+    /// same size as the framebuffer and maps it at a dynamic address. The kernel can draw
+    /// to this buffer and copy it to the framebuffer at once, which avoids tearing and slow
+    /// reads from framebuffer memory. The address is reported in the
+    /// [`back_buffer_addr`][crate::info::BootInfo::back_buffer_addr] field of the boot info,
+    /// and the buffer is marked as [`Bootloader`][crate::info::MemoryRegionKind::Bootloader]
+    /// memory in the memory map. If there is no framebuffer or not enough contiguous memory,
+    /// no back buffer is allocated and the bootloader logs a warning.
+    ///
+    /// Defaults to `false`.
+    pub framebuffer_back_buffer: bool,
+
+    /// Whether all loadable kernel segments should be mapped writable and executable.
+    ///
+    /// By default, the bootloader maps each segment with the permissions from its program
+    /// header, so that no page is both writable and executable. Bring-up kernels that modify
+    /// their own code or execute code from data pages can enable this option to map all
+    /// segments as `PRESENT | WRITABLE` without `NO_EXECUTE` instead. The bootloader logs a
+    /// warning at boot when this option is enabled. RELRO ranges stay writable as well. Other
+    /// mappings created by the bootloader, such as the kernel stack, are not affected.
+    ///
+    /// This weakens the memory protection of the kernel and should not be enabled in
+    /// production.
+    ///
+    /// Defaults to `false`.
+    pub disable_nx: bool,
+
+    /// Whether the bootloader should report the graphics modes supported by the display.
+    ///
+    /// If enabled, the UEFI bootloader queries all graphics modes from the Graphics Output
+    /// Protocol before exiting the boot services and reports them in the
+    /// [`graphics_modes`][crate::info::BootInfo::graphics_modes] field of the boot info. This
+    /// has no effect on BIOS systems.
+    ///
+    /// Defaults to `false`.
+    pub report_graphics_modes: bool,
+
+    /// The lowest physical address at which the bootloader allocates frames.
+    ///
+    /// The bootloader never allocates frames below this address, e.g. for page tables, the
+    /// boot info, or the kernel stack, so that kernels can rely on the memory below it to be
+    /// untouched. This memory is still reported as usable in the memory map. The frame at
+    /// physical address zero is never allocated. Memory that the UEFI firmware allocates for
+    /// the bootloader, e.g. for the kernel file, is not affected.
+    ///
+    /// Must be page-aligned.
+    ///
+    /// Defaults to `0x10_0000` (1MiB), which keeps the real-mode IVT, the BIOS data area, the
+    /// VGA memory, and the lower memory used by AP startup code (SIPI) free.
+    pub min_frame_address: u64,
+
     /// Configuration for the frame buffer that can be used by the kernel to display pixels
     /// on the screen.
     #[deprecated(
@@ -43,21 +151,66 @@ impl BootloaderConfig {
         0x3D,
     ];
     #[doc(hidden)]
-    pub const SERIALIZED_LEN: usize = 124;
+    pub const SERIALIZED_LEN: usize = 164;
 
     /// Creates a new default configuration with the following values:
     ///
     /// - `kernel_stack_size`: 80kiB
+    /// - `kernel_stack_guard_size`: 4kiB
+    /// - `enable_pcid`: `false`
+    /// - `dma_buffer_size`: `None`
+    /// - `smp_kernel_stacks`: `false`
+    /// - `framebuffer_back_buffer`: `false`
+    /// - `disable_nx`: `false`
+    /// - `report_graphics_modes`: `false`
+    /// - `min_frame_address`: `0x10_0000`
     /// - `mappings`: See [`Mappings::new_default()`]
     pub const fn new_default() -> Self {
         Self {
             kernel_stack_size: 80 * 1024,
+            kernel_stack_guard_size: 4 * 1024,
+            enable_pcid: false,
+            dma_buffer_size: Option::None,
+            smp_kernel_stacks: false,
+            framebuffer_back_buffer: false,
+            disable_nx: false,
+            report_graphics_modes: false,
+            min_frame_address: 0x10_0000,
             version: ApiVersion::new_default(),
             mappings: Mappings::new_default(),
             frame_buffer: FrameBuffer::new_default(),
         }
     }
 
+    /// Checks the configuration for values that the bootloader would reject at boot time.
+    ///
+    /// The [`crate::entry_point`] macro calls this in a const context, so an invalid
+    /// configuration results in a compile error of the kernel instead of a boot failure.
+    #[doc(hidden)]
+    pub const fn validate(&self) {
+        if let Option::Some(Mapping::FixedAddress(offset)) = self.mappings.physical_memory {
+            // bits 48 to 63 must be copies of bit 47
+            let upper_bits = offset >> 47;
+            if upper_bits != 0 && upper_bits != 0x1ffff {
+                panic!("the physical memory offset must be a canonical address");
+            }
+            if offset % (2 * 1024 * 1024) != 0 {
+                panic!("the physical memory offset must be aligned to 2MiB");
+            }
+        }
+        if let Mapping::FixedAddress(base) = self.mappings.kernel_base {
+            if self.mappings.aslr {
+                panic!("a fixed kernel base address can't be combined with ASLR");
+            }
+            if base % 4096 != 0 {
+                panic!("the kernel base address must be page-aligned");
+            }
+        }
+        if self.min_frame_address % 4096 != 0 {
+            panic!("the minimum frame address must be page-aligned");
+        }
+    }
+
     /// Serializes the configuration to a byte array.
     ///
     /// This is used by the [`crate::entry_point`] macro to store the configuration in a
@@ -67,6 +220,14 @@ impl BootloaderConfig {
             version,
             mappings,
             kernel_stack_size,
+            kernel_stack_guard_size,
+            enable_pcid,
+            dma_buffer_size,
+            smp_kernel_stacks,
+            framebuffer_back_buffer,
+            disable_nx,
+            report_graphics_modes,
+            min_frame_address,
             frame_buffer,
         } = self;
         let ApiVersion {
@@ -85,6 +246,8 @@ impl BootloaderConfig {
             dynamic_range_start,
             dynamic_range_end,
             ramdisk_memory,
+            higher_half_boot_data,
+            kernel_base,
         } = mappings;
         let FrameBuffer {
             minimum_framebuffer_height,
@@ -143,13 +306,39 @@ impl BootloaderConfig {
             },
         );
 
-        concat_115_9(
+        let buf = concat_115_9(
             buf,
             match minimum_framebuffer_width {
                 Option::None => [0; 9],
                 Option::Some(addr) => concat_1_8([1], addr.to_le_bytes()),
             },
-        )
+        );
+
+        let buf = concat_124_1(buf, [(*enable_pcid) as u8]);
+
+        let buf = concat_125_8(buf, kernel_stack_guard_size.to_le_bytes());
+
+        let buf = concat_133_9(
+            buf,
+            match dma_buffer_size {
+                Option::None => [0; 9],
+                Option::Some(size) => concat_1_8([1], size.to_le_bytes()),
+            },
+        );
+
+        let buf = concat_142_1(buf, [(*smp_kernel_stacks) as u8]);
+
+        let buf = concat_143_1(buf, [(*higher_half_boot_data) as u8]);
+
+        let buf = concat_144_1(buf, [(*framebuffer_back_buffer) as u8]);
+
+        let buf = concat_145_1(buf, [(*disable_nx) as u8]);
+
+        let buf = concat_146_1(buf, [(*report_graphics_modes) as u8]);
+
+        let buf = concat_147_9(buf, kernel_base.serialize());
+
+        concat_156_8(buf, min_frame_address.to_le_bytes())
     }
 
     /// Tries to deserialize a config byte array that was created using [`Self::serialize`].
@@ -239,6 +428,9 @@ impl BootloaderConfig {
                     _ => return Err("invalid dynamic range end value"),
                 },
                 ramdisk_memory: Mapping::deserialize(&ramdisk_memory)?,
+                // stored at the end of the serialized config, see below
+                higher_half_boot_data: false,
+                kernel_base: Mapping::Dynamic,
             };
             (mappings, s)
         };
@@ -264,6 +456,69 @@ impl BootloaderConfig {
             (frame_buffer, s)
         };
 
+        let (&[enable_pcid], s) = split_array_ref(s);
+        let enable_pcid = match enable_pcid {
+            1 => true,
+            0 => false,
+            _ => return Err("invalid enable_pcid value"),
+        };
+
+        let (&kernel_stack_guard_size, s) = split_array_ref(s);
+
+        let (&dma_buffer_size_some, s) = split_array_ref(s);
+        let (&dma_buffer_size, s) = split_array_ref(s);
+        let dma_buffer_size = match dma_buffer_size_some {
+            [0] if dma_buffer_size == [0; 8] => Option::None,
+            [1] => Option::Some(u64::from_le_bytes(dma_buffer_size)),
+            _ => return Err("invalid dma_buffer_size value"),
+        };
+
+        let (&[smp_kernel_stacks], s) = split_array_ref(s);
+        let smp_kernel_stacks = match smp_kernel_stacks {
+            1 => true,
+            0 => false,
+            _ => return Err("invalid smp_kernel_stacks value"),
+        };
+
+        let (&[higher_half_boot_data], s) = split_array_ref(s);
+        let mappings = Mappings {
+            higher_half_boot_data: match higher_half_boot_data {
+                1 => true,
+                0 => false,
+                _ => return Err("invalid higher_half_boot_data value"),
+            },
+            ..mappings
+        };
+
+        let (&[framebuffer_back_buffer], s) = split_array_ref(s);
+        let framebuffer_back_buffer = match framebuffer_back_buffer {
+            1 => true,
+            0 => false,
+            _ => return Err("invalid framebuffer_back_buffer value"),
+        };
+
+        let (&[disable_nx], s) = split_array_ref(s);
+        let disable_nx = match disable_nx {
+            1 => true,
+            0 => false,
+            _ => return Err("invalid disable_nx value"),
+        };
+
+        let (&[report_graphics_modes], s) = split_array_ref(s);
+        let report_graphics_modes = match report_graphics_modes {
+            1 => true,
+            0 => false,
+            _ => return Err("invalid report_graphics_modes value"),
+        };
+
+        let (kernel_base, s) = split_array_ref(s);
+        let mappings = Mappings {
+            kernel_base: Mapping::deserialize(kernel_base)?,
+            ..mappings
+        };
+
+        let (&min_frame_address, s) = split_array_ref(s);
+
         if !s.is_empty() {
             return Err("unexpected rest");
         }
@@ -271,6 +526,14 @@ impl BootloaderConfig {
         Ok(Self {
             version,
             kernel_stack_size: u64::from_le_bytes(kernel_stack_size),
+            kernel_stack_guard_size: u64::from_le_bytes(kernel_stack_guard_size),
+            enable_pcid,
+            dma_buffer_size,
+            smp_kernel_stacks,
+            framebuffer_back_buffer,
+            disable_nx,
+            report_graphics_modes,
+            min_frame_address: u64::from_le_bytes(min_frame_address),
             mappings,
             frame_buffer,
         })
@@ -282,6 +545,18 @@ impl BootloaderConfig {
             version: ApiVersion::random(),
             mappings: Mappings::random(),
             kernel_stack_size: rand::random(),
+            kernel_stack_guard_size: rand::random(),
+            enable_pcid: rand::random(),
+            dma_buffer_size: if rand::random() {
+                Option::Some(rand::random())
+            } else {
+                Option::None
+            },
+            smp_kernel_stacks: rand::random(),
+            framebuffer_back_buffer: rand::random(),
+            disable_nx: rand::random(),
+            report_graphics_modes: rand::random(),
+            min_frame_address: rand::random(),
             frame_buffer: FrameBuffer::random(),
         }
     }
@@ -311,6 +586,12 @@ pub struct ApiVersion {
 }
 
 impl ApiVersion {
+    /// The version of this `bootloader_api` crate.
+    ///
+    /// Kernels can compare [`crate::BootInfo::api_version`] against this constant to detect
+    /// that they were booted by a bootloader with a different, possibly incompatible, version.
+    pub const CURRENT: Self = Self::new_default();
+
     pub(crate) const fn new_default() -> Self {
         Self {
             version_major: version_info::VERSION_MAJOR,
@@ -358,6 +639,33 @@ impl Default for ApiVersion {
 }
 
 /// Allows to configure the virtual memory mappings created by the bootloader.
+///
+/// ## Address space of the kernel
+///
+/// The kernel is started with a new level 4 page table that only contains the following
+/// mappings:
+///
+/// - the loadable segments of the kernel ELF file,
+/// - the kernel stack (see [`Self::kernel_stack`]) and the stacks of the application
+///   processors, if they are configured,
+/// - the [`crate::BootInfo`] struct together with the memory map and the kernel command line
+///   (see [`Self::boot_info`]),
+/// - the framebuffer (see [`Self::framebuffer`]), the ramdisk (see [`Self::ramdisk_memory`]),
+///   and the DMA buffer, if they exist,
+/// - the complete physical memory (see [`Self::physical_memory`]) and the recursive level 4
+///   entry (see [`Self::page_table_recursive`]), if they are enabled,
+/// - an identity mapping of the GDT and of the few pages that contain the code that switches
+///   to the kernel page table.
+///
+/// No other memory is mapped. In particular, low physical memory is _not_ identity-mapped,
+/// so the kernel must not access physical addresses directly. Kernels that need access to
+/// physical memory should enable [`Self::physical_memory`] and access it through the offset
+/// reported in [`crate::BootInfo::physical_memory_offset`].
+///
+/// The kernel segments, the framebuffer, and the physical memory mapping are mapped with the
+/// `GLOBAL` flag and `CR4.PGE` is enabled, so their TLB entries are kept when `CR3` is
+/// reloaded. Kernels that remove one of these mappings from an address space must flush it
+/// explicitly, e.g. with `invlpg` or by toggling `CR4.PGE`.
 #[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
 #[non_exhaustive]
 pub struct Mappings {
@@ -374,11 +682,21 @@ pub struct Mappings {
     /// Specifies where the [`crate::BootInfo`] struct should be placed in virtual memory.
     pub boot_info: Mapping,
     /// Specifies the mapping of the frame buffer memory region.
+    ///
+    /// If the CPU supports the page attribute table (PAT), the bootloader sets entry 1 of the
+    /// `IA32_PAT` register to write-combining and maps the framebuffer with the
+    /// `WRITE_THROUGH` flag, which selects this entry. Kernels that remap the framebuffer or
+    /// reprogram the PAT should keep the write-combining memory type for it. The register is
+    /// only changed on the bootstrap processor, so kernels must apply the same change on
+    /// every other CPU before using the framebuffer mapping there.
     pub framebuffer: Mapping,
     /// The bootloader supports to map the whole physical memory into the virtual address
     /// space at some offset. This is useful for accessing and modifying the page tables set
     /// up by the bootloader.
     ///
+    /// If a fixed address is set, it must be canonical and 2MiB-aligned. Kernels in the higher
+    /// half typically choose an offset like `0xffff_8000_0000_0000`.
+    ///
     /// Defaults to `None`, i.e. no mapping of the physical memory.
     pub physical_memory: Option<Mapping>,
     /// As an alternative to mapping the whole physical memory (see [`Self::physical_memory`]),
@@ -404,6 +722,37 @@ pub struct Mappings {
     /// Virtual address to map ramdisk image, if present on disk
     /// Defaults to dynamic
     pub ramdisk_memory: Mapping,
+    /// Whether the data passed to the kernel must be mapped in the higher half of the
+    /// address space.
+    ///
+    /// If enabled, the [`crate::BootInfo`] struct (including the memory map and the kernel
+    /// stack list it points to) and the ramdisk are placed at virtual addresses
+    /// `>= 0xffff_8000_0000_0000`, so the pointer passed to the kernel entry point and all
+    /// pointers inside the `BootInfo` refer to the higher half. [`Mapping::Dynamic`] mappings
+    /// for these regions are chosen from the higher half automatically. Fixed addresses for
+    /// [`Self::boot_info`] and [`Self::ramdisk_memory`] must be in the higher half and must
+    /// not overlap the kernel segments, otherwise the bootloader panics.
+    ///
+    /// This is intended for kernels that are linked in the higher half and don't want to
+    /// access the lower half at all. Unlike [`Self::dynamic_range_start`], it doesn't affect
+    /// the placement of the other mappings such as the kernel stack.
+    ///
+    /// Defaults to `false`.
+    pub higher_half_boot_data: bool,
+    /// Specifies the virtual base address of the kernel image, i.e. the address at which
+    /// the lowest loadable segment of the kernel is mapped.
+    ///
+    /// For position independent kernels, a fixed address forces the kernel to be loaded at
+    /// this base. The address must be aligned to the largest segment alignment of the kernel.
+    /// For non-relocatable kernels, the kernel is always loaded at its link address, so a
+    /// fixed address must match it, otherwise the bootloader fails with an error. This is
+    /// useful to get predictable addresses for debugging with the symbols of the kernel.
+    ///
+    /// A fixed address can't be combined with [`Self::aslr`].
+    ///
+    /// Defaults to [`Mapping::Dynamic`], which chooses an unused base address for position
+    /// independent kernels.
+    pub kernel_base: Mapping,
 }
 
 impl Mappings {
@@ -421,6 +770,8 @@ impl Mappings {
             dynamic_range_start: None,
             dynamic_range_end: None,
             ramdisk_memory: Mapping::new_default(),
+            higher_half_boot_data: false,
+            kernel_base: Mapping::new_default(),
         }
     }
 
@@ -454,6 +805,8 @@ impl Mappings {
                 Option::None
             },
             ramdisk_memory: Mapping::random(),
+            higher_half_boot_data: rand::random(),
+            kernel_base: Mapping::random(),
         }
     }
 }
@@ -591,4 +944,48 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn validate_physical_memory_offset() {
+        let mut config = BootloaderConfig::new_default();
+        config.validate();
+        config.mappings.physical_memory = Some(Mapping::FixedAddress(0xffff_8000_0000_0000));
+        config.validate();
+        config.mappings.physical_memory = Some(Mapping::FixedAddress(0x0000_4000_0000_0000));
+        config.validate();
+    }
+
+    #[test]
+    #[should_panic(expected = "ASLR")]
+    fn validate_fixed_kernel_base_with_aslr() {
+        let mut config = BootloaderConfig::new_default();
+        config.mappings.kernel_base = Mapping::FixedAddress(0xffff_ffff_8000_0000);
+        config.validate();
+        config.mappings.aslr = true;
+        config.validate();
+    }
+
+    #[test]
+    #[should_panic(expected = "canonical")]
+    fn validate_non_canonical_physical_memory_offset() {
+        let mut config = BootloaderConfig::new_default();
+        config.mappings.physical_memory = Some(Mapping::FixedAddress(0x8000_0000_0000_0000));
+        config.validate();
+    }
+
+    #[test]
+    #[should_panic(expected = "aligned")]
+    fn validate_unaligned_physical_memory_offset() {
+        let mut config = BootloaderConfig::new_default();
+        config.mappings.physical_memory = Some(Mapping::FixedAddress(0xffff_8000_0000_1000));
+        config.validate();
+    }
+
+    #[test]
+    #[should_panic(expected = "page-aligned")]
+    fn validate_unaligned_min_frame_address() {
+        let mut config = BootloaderConfig::new_default();
+        config.min_frame_address = 0x10_0800;
+        config.validate();
+    }
 }
@@ -1,4 +1,4 @@
-use core::{ops, slice};
+use core::{cmp, fmt, ops, slice};
 
 use crate::config::ApiVersion;
 
@@ -21,6 +21,10 @@ use crate::config::ApiVersion;
 #[non_exhaustive]
 pub struct BootInfo {
     /// The version of the `bootloader_api` crate. Must match the `bootloader` version.
+    ///
+    /// This is the first field of the struct and its layout never changes, so it can be read
+    /// even if the rest of the struct layout differs between bootloader and kernel. Compare it
+    /// against [`ApiVersion::CURRENT`] to detect such a mismatch early.
     pub api_version: ApiVersion,
     /// A map of the physical memory regions of the underlying machine.
     ///
@@ -28,6 +32,14 @@ pub struct BootInfo {
     /// information to Rust types. It also marks any memory regions that the bootloader uses in
     /// the memory map before passing it to the kernel. Regions marked as usable can be freely
     /// used by the kernel.
+    ///
+    /// The regions are stored directly behind the `BootInfo` struct, in the same virtual
+    /// mapping that is configured through
+    /// [`Mappings::boot_info`](crate::config::Mappings::boot_info). The slice therefore
+    /// points to a virtual address of that mapping, not to an identity mapping of physical
+    /// memory, and stays valid as long as the kernel keeps the boot info mapped. To place it
+    /// in the higher half, enable
+    /// [`Mappings::higher_half_boot_data`](crate::config::Mappings::higher_half_boot_data).
     pub memory_regions: MemoryRegions,
     /// Information about the framebuffer for screen output if available.
     pub framebuffer: Optional<FrameBuffer>,
@@ -50,24 +62,123 @@ pub struct BootInfo {
     ///
     /// This field is `None` if no `RSDP` was found (for BIOS) or reported (for UEFI).
     pub rsdp_addr: Optional<u64>,
+    /// The physical address of the SMBIOS entry point structure, which can be used to find
+    /// the SMBIOS tables.
+    ///
+    /// If both are available, the 64-bit SMBIOS 3.0 entry point (anchor string `_SM3_`) is
+    /// preferred over the 32-bit entry point (anchor string `_SM_`). The anchor string at the
+    /// start of the structure tells which one it is. This field is `None` if no entry point
+    /// was found (for BIOS) or reported (for UEFI).
+    pub smbios_addr: Optional<u64>,
+    /// The physical base address of the local APIC of the bootstrap processor, as read from
+    /// the `IA32_APIC_BASE` MSR.
+    ///
+    /// This field is `None` if the CPU has no local APIC or if it is disabled. The bootloader
+    /// doesn't map the APIC registers and doesn't change the base address, so the kernel
+    /// needs to map (and possibly relocate) them itself. The firmware normally reports this
+    /// memory-mapped I/O range as reserved or doesn't include it in the memory map at all.
+    pub local_apic_addr: Optional<u64>,
+    /// The number of enabled CPUs, including the bootstrap processor.
+    ///
+    /// On UEFI, this is queried through the MP services protocol if available. Otherwise it
+    /// is determined by counting the enabled processor entries of the ACPI MADT. If neither
+    /// is available, this field is set to 1.
+    pub cpu_count: u32,
     /// The thread local storage (TLS) template of the kernel executable, if present.
     pub tls_template: Optional<TlsTemplate>,
     /// Ramdisk address, if loaded
     pub ramdisk_addr: Optional<u64>,
     /// Ramdisk image size, set to 0 if addr is None
     pub ramdisk_len: u64,
+    /// Virtual address of the kernel command line.
+    ///
+    /// The command line is copied into the boot info mapping, so this address stays valid
+    /// for as long as the boot info does, even if no command line was set.
+    pub cmdline_addr: u64,
+    /// Length of the kernel command line in bytes, set to 0 if no command line was set.
+    ///
+    /// The command line is guaranteed to be valid UTF-8. It is not null-terminated.
+    pub cmdline_len: u64,
     /// Physical address of the kernel ELF in memory.
+    ///
+    /// The loadable segments of the kernel are mapped directly to the frames of this
+    /// region, so it also describes where the kernel image physically resides. Only
+    /// zero-initialized (`.bss`) memory and segment parts that are relocated or written
+    /// by the bootloader are backed by separately allocated frames.
     pub kernel_addr: u64,
     /// Size of the kernel ELF in memory.
     pub kernel_len: u64,
     /// Virtual address of the loaded kernel image.
     pub kernel_image_offset: u64,
+    /// Virtual start address of the stack that the kernel is entered on.
+    ///
+    /// The page directly below this address is an unmapped guard page. The size and location
+    /// of the stack can be configured through the `kernel_stack_size` and
+    /// `mappings.kernel_stack` config options.
+    pub kernel_stack_addr: u64,
+    /// Size of the kernel entry stack in bytes.
+    ///
+    /// The initial stack pointer of the kernel is `kernel_stack_addr + kernel_stack_len`.
+    pub kernel_stack_len: u64,
+    /// The bounds of the kernel stacks for all CPUs, indexed by logical CPU number.
+    ///
+    /// The first entry is the stack of the bootstrap processor, i.e. the stack that the kernel
+    /// is entered on. The other entries can be used by the kernel when starting the
+    /// application processors. Each stack has an unmapped guard region directly below it.
+    ///
+    /// Empty unless the `smp_kernel_stacks` config option is enabled.
+    pub cpu_stacks: CpuStacks,
+    /// Physical address of the identity-mapped DMA buffer, if the `dma_buffer_size` config
+    /// option is set.
+    ///
+    /// The buffer is physically contiguous, located below 4GiB, and mapped with caching
+    /// disabled.
+    pub dma_buffer_addr: Optional<u64>,
+    /// Size of the DMA buffer in bytes, set to 0 if addr is None
+    pub dma_buffer_len: u64,
+    /// Virtual address of the back buffer for the framebuffer, if the
+    /// `framebuffer_back_buffer` config option is enabled.
+    ///
+    /// The buffer is zeroed and has the same size as the
+    /// [`framebuffer`][Self::framebuffer], rounded up to the page size. It is `None` if
+    /// there is no framebuffer or if the bootloader failed to allocate the buffer.
+    pub back_buffer_addr: Optional<u64>,
+    /// The graphics modes that the firmware reported for the display, if the
+    /// `report_graphics_modes` config option is enabled.
+    ///
+    /// This list was captured before the bootloader exited the UEFI boot services, so it
+    /// only describes the capabilities reported by the firmware. Switching to one of these
+    /// modes after boot still requires a native driver for the graphics card. Modes without
+    /// a linear framebuffer are not included.
+    ///
+    /// Always empty on BIOS systems.
+    pub graphics_modes: GraphicsModes,
+    /// Virtual address of the firmware vendor string reported by the UEFI system table.
+    ///
+    /// Like the kernel command line, the string is copied into the boot info mapping. It is
+    /// valid UTF-8, not null-terminated, and truncated to at most
+    /// [`FIRMWARE_VENDOR_MAX_LEN`][Self::FIRMWARE_VENDOR_MAX_LEN] bytes.
+    pub firmware_vendor_addr: u64,
+    /// Length of the firmware vendor string in bytes, set to 0 on BIOS systems.
+    pub firmware_vendor_len: u64,
+    /// The revision of the UEFI firmware, as reported by the UEFI system table.
+    ///
+    /// The meaning of the value is vendor-specific. Set to 0 on BIOS systems.
+    pub firmware_revision: u32,
+    /// CPU features that were detected by the bootloader.
+    pub cpu_features: CpuFeatures,
+    /// Information about the frequency of the time stamp counter (TSC).
+    pub tsc: TscInfo,
 
     #[doc(hidden)]
     pub _test_sentinel: u64,
 }
 
 impl BootInfo {
+    /// The maximum length of the [firmware vendor string][Self::firmware_vendor_addr] in
+    /// bytes. Longer strings are truncated.
+    pub const FIRMWARE_VENDOR_MAX_LEN: usize = 128;
+
     /// Create a new boot info structure with the given memory map.
     ///
     /// The other fields are initialized with default values.
@@ -79,12 +190,29 @@ impl BootInfo {
             physical_memory_offset: Optional::None,
             recursive_index: Optional::None,
             rsdp_addr: Optional::None,
+            smbios_addr: Optional::None,
+            local_apic_addr: Optional::None,
+            cpu_count: 1,
             tls_template: Optional::None,
             ramdisk_addr: Optional::None,
             ramdisk_len: 0,
+            cmdline_addr: 0,
+            cmdline_len: 0,
             kernel_addr: 0,
             kernel_len: 0,
             kernel_image_offset: 0,
+            kernel_stack_addr: 0,
+            kernel_stack_len: 0,
+            cpu_stacks: CpuStacks::empty(),
+            dma_buffer_addr: Optional::None,
+            dma_buffer_len: 0,
+            back_buffer_addr: Optional::None,
+            graphics_modes: GraphicsModes::empty(),
+            firmware_vendor_addr: 0,
+            firmware_vendor_len: 0,
+            firmware_revision: 0,
+            cpu_features: CpuFeatures::new(),
+            tsc: TscInfo::new(),
             _test_sentinel: 0,
         }
     }
@@ -117,6 +245,138 @@ impl ops::DerefMut for MemoryRegions {
     }
 }
 
+impl MemoryRegions {
+    /// Returns the memory regions as a slice, ordered by start address.
+    ///
+    /// The [`len`][slice::len], [`is_empty`][slice::is_empty], and [`iter`][slice::iter]
+    /// methods of the slice are also available on `MemoryRegions` directly. Iterating over
+    /// a `&MemoryRegions` yields the same regions.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// use bootloader_api::info::{MemoryRegionKind, MemoryRegions};
+    ///
+    /// fn usable_region_count(memory_regions: &MemoryRegions) -> usize {
+    ///     memory_regions
+    ///         .regions()
+    ///         .iter()
+    ///         .filter(|region| region.kind == MemoryRegionKind::Usable)
+    ///         .count()
+    /// }
+    /// ```
+    pub fn regions(&self) -> &[MemoryRegion] {
+        self
+    }
+
+    /// Returns the total size of all [`Usable`][MemoryRegionKind::Usable] regions in bytes.
+    pub fn usable_bytes(&self) -> u64 {
+        self.usable_regions()
+            .map(|region| region.end - region.start)
+            .sum()
+    }
+
+    /// Returns the largest [`Usable`][MemoryRegionKind::Usable] region, or `None` if there
+    /// is no usable memory.
+    ///
+    /// Since the bootloader merges adjacent regions of the same kind, this is the largest
+    /// physically contiguous block of usable memory. If multiple regions have the same size,
+    /// the first one is returned.
+    pub fn largest_usable_region(&self) -> Option<MemoryRegion> {
+        self.usable_regions()
+            .rev()
+            .max_by_key(|region| region.end - region.start)
+            .copied()
+    }
+
+    /// Returns the region that contains the given physical address, or `None` if the address
+    /// is not covered by any region.
+    ///
+    /// Performs a binary search, so the regions must be sorted by start address and must not
+    /// overlap. This is the case for the memory map created by the bootloader, as long as it
+    /// isn't modified through [`DerefMut`][core::ops::DerefMut].
+    pub fn region_containing(&self, addr: u64) -> Option<&MemoryRegion> {
+        let index = self.partition_point(|region| region.end <= addr);
+        self.get(index).filter(|region| region.start <= addr)
+    }
+
+    /// Returns the kind of the region that contains the given physical address, or `None` if
+    /// the address is not covered by any region.
+    ///
+    /// See [`region_containing`][Self::region_containing] for details.
+    pub fn kind_of(&self, addr: u64) -> Option<MemoryRegionKind> {
+        self.region_containing(addr).map(|region| region.kind)
+    }
+
+    fn usable_regions(&self) -> impl DoubleEndedIterator<Item = &MemoryRegion> {
+        self.iter()
+            .filter(|region| region.kind == MemoryRegionKind::Usable)
+    }
+
+    /// The version of the format written by [`write_csv`][Self::write_csv].
+    ///
+    /// Incremented whenever the format changes in an incompatible way.
+    pub const CSV_FORMAT_VERSION: u32 = 2;
+
+    /// Serializes the memory regions as CSV to the given writer.
+    ///
+    /// The output starts with a `bootloader-memory-map,v<VERSION>` line, followed by a
+    /// `start,end,kind` header line. Each memory region is then written as one line
+    /// with hexadecimal `start` and `end` addresses. The `kind` column is one of `usable`,
+    /// `bootloader`, `acpi-reclaimable`, `acpi-nvs`, `kernel`, `uefi:<type>`, or
+    /// `bios:<type>`, where `<type>` is the decimal memory type tag reported by the firmware.
+    pub fn write_csv(&self, writer: &mut impl fmt::Write) -> fmt::Result {
+        writeln!(
+            writer,
+            "bootloader-memory-map,v{}",
+            Self::CSV_FORMAT_VERSION
+        )?;
+        writeln!(writer, "start,end,kind")?;
+        for region in self.iter() {
+            write!(writer, "{:#x},{:#x},", region.start, region.end)?;
+            match region.kind {
+                MemoryRegionKind::Usable => writeln!(writer, "usable")?,
+                MemoryRegionKind::Bootloader => writeln!(writer, "bootloader")?,
+                MemoryRegionKind::UnknownUefi(ty) => writeln!(writer, "uefi:{ty}")?,
+                MemoryRegionKind::UnknownBios(ty) => writeln!(writer, "bios:{ty}")?,
+                MemoryRegionKind::AcpiReclaimable => writeln!(writer, "acpi-reclaimable")?,
+                MemoryRegionKind::AcpiNvs => writeln!(writer, "acpi-nvs")?,
+                MemoryRegionKind::KernelAndModules => writeln!(writer, "kernel-and-modules")?,
+                MemoryRegionKind::DmaBuffer => writeln!(writer, "dma-buffer")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Formats the memory regions as a table with one [`MemoryRegion`] per line.
+impl fmt::Display for MemoryRegions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for region in self.iter() {
+            writeln!(f, "{region}")?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> IntoIterator for &'a MemoryRegions {
+    type Item = &'a MemoryRegion;
+    type IntoIter = slice::Iter<'a, MemoryRegion>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a mut MemoryRegions {
+    type Item = &'a mut MemoryRegion;
+    type IntoIter = slice::IterMut<'a, MemoryRegion>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
+    }
+}
+
 impl From<&'static mut [MemoryRegion]> for MemoryRegions {
     fn from(regions: &'static mut [MemoryRegion]) -> Self {
         MemoryRegions {
@@ -132,9 +392,115 @@ impl From<MemoryRegions> for &'static mut [MemoryRegion] {
     }
 }
 
-/// Represent a physical memory region.
+/// FFI-safe slice of [`CpuStack`] structs, semantically equivalent to
+/// `&'static [CpuStack]`.
+///
+/// This type implements the [`Deref`][core::ops::Deref] trait, so it can be used like a
+/// `&[CpuStack]` slice.
+#[derive(Debug)]
+#[repr(C)]
+pub struct CpuStacks {
+    pub(crate) ptr: *const CpuStack,
+    pub(crate) len: usize,
+}
+
+impl CpuStacks {
+    /// Creates an empty slice.
+    pub const fn empty() -> Self {
+        Self {
+            ptr: core::ptr::NonNull::dangling().as_ptr(),
+            len: 0,
+        }
+    }
+}
+
+impl ops::Deref for CpuStacks {
+    type Target = [CpuStack];
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+impl From<&'static mut [CpuStack]> for CpuStacks {
+    fn from(stacks: &'static mut [CpuStack]) -> Self {
+        CpuStacks {
+            ptr: stacks.as_ptr(),
+            len: stacks.len(),
+        }
+    }
+}
+
+/// The virtual address range of a kernel stack.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[repr(C)]
+pub struct CpuStack {
+    /// The lowest address of the stack, directly above the guard region.
+    pub bottom: u64,
+    /// The (exclusive) end address of the stack, which should be used as initial stack pointer.
+    ///
+    /// Aligned to 16 bytes.
+    pub top: u64,
+}
+
+/// FFI-safe slice of [`GraphicsMode`] structs, semantically equivalent to
+/// `&'static [GraphicsMode]`.
+///
+/// This type implements the [`Deref`][core::ops::Deref] trait, so it can be used like a
+/// `&[GraphicsMode]` slice.
+#[derive(Debug)]
+#[repr(C)]
+pub struct GraphicsModes {
+    pub(crate) ptr: *const GraphicsMode,
+    pub(crate) len: usize,
+}
+
+impl GraphicsModes {
+    /// Creates an empty slice.
+    pub const fn empty() -> Self {
+        Self {
+            ptr: core::ptr::NonNull::dangling().as_ptr(),
+            len: 0,
+        }
+    }
+}
+
+impl ops::Deref for GraphicsModes {
+    type Target = [GraphicsMode];
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+impl From<&'static mut [GraphicsMode]> for GraphicsModes {
+    fn from(modes: &'static mut [GraphicsMode]) -> Self {
+        GraphicsModes {
+            ptr: modes.as_ptr(),
+            len: modes.len(),
+        }
+    }
+}
+
+/// A graphics mode supported by the display, as reported by the firmware.
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 #[repr(C)]
+pub struct GraphicsMode {
+    /// The width in pixels.
+    pub width: usize,
+    /// The height in pixels.
+    pub height: usize,
+    /// Number of pixels between the start of a line and the start of the next.
+    pub stride: usize,
+    /// The color format of each pixel.
+    pub pixel_format: PixelFormat,
+    /// The number of bytes per pixel.
+    pub bytes_per_pixel: usize,
+}
+
+/// Represent a physical memory region.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+#[repr(C)]
 pub struct MemoryRegion {
     /// The physical start address of the region.
     pub start: u64,
@@ -144,6 +510,13 @@ pub struct MemoryRegion {
     ///
     /// Only [`Usable`][MemoryRegionKind::Usable] regions can be freely used.
     pub kind: MemoryRegionKind,
+    /// The attributes of the memory region as reported by the firmware.
+    ///
+    /// On UEFI, these are the `Attribute` bits of the memory descriptor, e.g.
+    /// `EFI_MEMORY_WB` (`0x8`) or `EFI_MEMORY_RUNTIME` (`0x8000_0000_0000_0000`). Regions
+    /// that the bootloader splits off a firmware region keep the attributes of that region.
+    /// Always `0` on BIOS.
+    pub attributes: u64,
 }
 
 impl MemoryRegion {
@@ -153,12 +526,56 @@ impl MemoryRegion {
             start: 0,
             end: 0,
             kind: MemoryRegionKind::Bootloader,
+            attributes: 0,
+        }
+    }
+}
+
+/// Formats the region as `[start..end] size=<size> kind=<kind>`, followed by the attributes
+/// if they are not zero.
+///
+/// The addresses are zero-padded hexadecimal numbers, so that the regions of a memory map
+/// line up when they are printed below each other. The size is printed in the largest
+/// fitting binary unit (B, KiB, MiB, or GiB), rounded down to one decimal place.
+impl fmt::Display for MemoryRegion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "[{:#018x}..{:#018x}] size={} kind={:?}",
+            self.start,
+            self.end,
+            ByteSize(self.end.saturating_sub(self.start)),
+            self.kind
+        )?;
+        if self.attributes != 0 {
+            write!(f, " attributes={:#x}", self.attributes)?;
         }
+        Ok(())
+    }
+}
+
+/// Formats a number of bytes in a human-readable binary unit.
+struct ByteSize(u64);
+
+impl fmt::Display for ByteSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const UNITS: [(u64, &str); 3] = [(1 << 30, "GiB"), (1 << 20, "MiB"), (1 << 10, "KiB")];
+        for (unit, name) in UNITS {
+            if self.0 >= unit {
+                let whole = self.0 / unit;
+                let tenths = self.0 % unit * 10 / unit;
+                return match tenths {
+                    0 => write!(f, "{whole} {name}"),
+                    _ => write!(f, "{whole}.{tenths} {name}"),
+                };
+            }
+        }
+        write!(f, "{} B", self.0)
     }
 }
 
 /// Represents the different types of memory.
-#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
 #[non_exhaustive]
 #[repr(C)]
 pub enum MemoryRegionKind {
@@ -167,6 +584,15 @@ pub enum MemoryRegionKind {
     /// Memory mappings created by the bootloader, including the page table and boot info mappings.
     ///
     /// This memory should _not_ be used by the kernel.
+    ///
+    /// Regions of this kind contain the stack that the bootloader ran on and all frames that
+    /// the bootloader allocated, except for those reported as
+    /// [`KernelAndModules`][Self::KernelAndModules] or [`DmaBuffer`][Self::DmaBuffer]. The
+    /// allocated frames hold the kernel page tables, the kernel stacks, the GDT, and the boot
+    /// info including the memory map, but also some page tables that were only used by the
+    /// bootloader itself. These are allocated in the same regions, so the kernel can only
+    /// reclaim a `Bootloader` region as a whole. It can do so after it has switched to its own
+    /// page table, its own stack and GDT, and copied everything it needs from the boot info.
     Bootloader,
     /// An unknown memory region reported by the UEFI firmware.
     ///
@@ -174,6 +600,28 @@ pub enum MemoryRegionKind {
     UnknownUefi(u32),
     /// An unknown memory region reported by the BIOS firmware.
     UnknownBios(u32),
+    /// Memory that contains ACPI tables.
+    ///
+    /// The kernel can use this memory after it has read the ACPI tables.
+    AcpiReclaimable,
+    /// Memory that the firmware uses to store its state across sleep states (ACPI NVS).
+    ///
+    /// This memory must be preserved by the kernel.
+    AcpiNvs,
+    /// Memory that contains the loaded kernel image and its modules.
+    ///
+    /// Regions of this kind contain the kernel ELF file, whose frames are mapped directly for
+    /// the kernel segments, the frames that the bootloader allocated while loading the kernel,
+    /// and the ramdisk. The allocated frames back the `.bss` sections and the copied pages of
+    /// the segments, and hold the page tables that map them. This memory must not be used by
+    /// the kernel as long as it runs from the loaded image or needs the ramdisk.
+    KernelAndModules,
+    /// The identity-mapped DMA buffer requested through the
+    /// [`dma_buffer_size`][crate::BootloaderConfig::dma_buffer_size] config option.
+    ///
+    /// Its address is also reported in [`BootInfo::dma_buffer_addr`]. This memory must not be
+    /// used for anything else as long as the kernel uses the buffer.
+    DmaBuffer,
 }
 
 /// A pixel-based framebuffer that controls the screen output.
@@ -182,6 +630,8 @@ pub enum MemoryRegionKind {
 pub struct FrameBuffer {
     pub(crate) buffer_start: u64,
     pub(crate) info: FrameBufferInfo,
+    pub(crate) id: u64,
+    pub(crate) firmware_pixel_format: Optional<u32>,
 }
 
 impl FrameBuffer {
@@ -192,7 +642,51 @@ impl FrameBuffer {
     /// The given start address and info must describe a valid, accessible, and unaliased
     /// framebuffer.
     pub unsafe fn new(buffer_start: u64, info: FrameBufferInfo) -> Self {
-        Self { buffer_start, info }
+        Self {
+            buffer_start,
+            info,
+            id: 0,
+            firmware_pixel_format: Optional::None,
+        }
+    }
+
+    /// Sets the pixel format code that the firmware reported for this framebuffer.
+    ///
+    /// See [`Self::firmware_pixel_format`] for details.
+    pub fn with_firmware_pixel_format(mut self, format: u32) -> Self {
+        self.firmware_pixel_format = Optional::Some(format);
+        self
+    }
+
+    /// Returns the raw pixel format code reported by the firmware, if available.
+    ///
+    /// On UEFI, this is the `EFI_GRAPHICS_PIXEL_FORMAT` value of the graphics output
+    /// protocol mode (`0` for RGB, `1` for BGR, `2` for bitmask). This allows the kernel
+    /// to handle pixel formats that are reported as [`PixelFormat::Unknown`]. On BIOS,
+    /// this is always `None`.
+    pub fn firmware_pixel_format(&self) -> Option<u32> {
+        self.firmware_pixel_format.into_option()
+    }
+
+    /// Sets the identifier of the display output that this framebuffer belongs to.
+    ///
+    /// See [`Self::id`] for details.
+    pub fn with_id(mut self, id: u64) -> Self {
+        self.id = id;
+        self
+    }
+
+    /// Returns a stable identifier of the display output that this framebuffer belongs to.
+    ///
+    /// The identifier stays the same across reboots as long as the hardware configuration
+    /// doesn't change, so it can be used to recognize a specific display output.
+    ///
+    /// On UEFI, the identifier is the 64-bit FNV-1a hash of the raw bytes of the device
+    /// path of the graphics output protocol handle, including the end node. If no device
+    /// path is available, the identifier is `0`. On BIOS, the identifier is always `0`
+    /// since there is only a single VESA framebuffer.
+    pub fn id(&self) -> u64 {
+        self.id
     }
 
     /// Returns the raw bytes of the framebuffer as slice.
@@ -225,6 +719,45 @@ impl FrameBuffer {
     pub fn info(&self) -> FrameBufferInfo {
         self.info
     }
+
+    /// Sets the pixel at the given position to the given color.
+    ///
+    /// The color is given as `[red, green, blue]` and converted to the pixel format of the
+    /// framebuffer, see [`FrameBufferInfo::encode_color`]. Returns an error if the position
+    /// lies outside of the visible area of the framebuffer.
+    pub fn set_pixel(&mut self, x: usize, y: usize, rgb: [u8; 3]) -> Result<(), &'static str> {
+        let info = self.info;
+        let pixel = info
+            .pixel_offset(x, y)
+            .and_then(|offset| {
+                self.buffer_mut()
+                    .get_mut(offset..offset + info.bytes_per_pixel)
+            })
+            .ok_or("pixel position is outside of the framebuffer")?;
+        let color = info.encode_color(rgb);
+        let len = cmp::min(pixel.len(), color.len());
+        pixel[..len].copy_from_slice(&color[..len]);
+        pixel[len..].fill(0);
+        Ok(())
+    }
+
+    /// Sets all visible pixels of the framebuffer to the given `[red, green, blue]` color.
+    pub fn fill(&mut self, rgb: [u8; 3]) {
+        for y in 0..self.info.height {
+            for x in 0..self.info.width {
+                if self.set_pixel(x, y, rgb).is_err() {
+                    // the buffer is smaller than described by the info
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Sets all bytes of the framebuffer to zero, which results in a black screen for all
+    /// supported pixel formats.
+    pub fn clear(&mut self) {
+        self.buffer_mut().fill(0);
+    }
 }
 
 /// Describes the layout and pixel format of a framebuffer.
@@ -249,6 +782,55 @@ pub struct FrameBufferInfo {
     pub stride: usize,
 }
 
+impl FrameBufferInfo {
+    /// Returns the byte offset of the pixel at the given position from the start of the
+    /// framebuffer.
+    ///
+    /// The offset is calculated from the [`stride`][Self::stride] and
+    /// [`bytes_per_pixel`][Self::bytes_per_pixel] fields. Returns `None` if the position lies
+    /// outside of the visible area, i.e. if `x >= width` or `y >= height`.
+    pub fn pixel_offset(&self, x: usize, y: usize) -> Option<usize> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        Some((y * self.stride + x) * self.bytes_per_pixel)
+    }
+
+    /// Converts the given `[red, green, blue]` color to the bytes of a pixel in the pixel
+    /// format of the framebuffer.
+    ///
+    /// Only the first [`bytes_per_pixel`][Self::bytes_per_pixel] bytes of the returned array
+    /// are part of the pixel. For [`PixelFormat::U8`], the color is converted to grayscale.
+    /// For [`PixelFormat::Unknown`], each color component is shifted to its bit position in
+    /// a little endian pixel.
+    pub fn encode_color(&self, rgb: [u8; 3]) -> [u8; 4] {
+        let [red, green, blue] = rgb;
+        match self.pixel_format {
+            PixelFormat::Rgb => [red, green, blue, 0],
+            PixelFormat::Bgr => [blue, green, red, 0],
+            PixelFormat::U8 => {
+                let gray = (u16::from(red) + u16::from(green) + u16::from(blue)) / 3;
+                [gray as u8, 0, 0, 0]
+            }
+            PixelFormat::Unknown {
+                red_position,
+                green_position,
+                blue_position,
+            } => {
+                let component = |value: u8, position: u8| {
+                    u32::from(value)
+                        .checked_shl(u32::from(position))
+                        .unwrap_or(0)
+                };
+                let pixel = component(red, red_position)
+                    | component(green, green_position)
+                    | component(blue, blue_position);
+                pixel.to_le_bytes()
+            }
+        }
+    }
+}
+
 /// Color format of pixels in the framebuffer.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[non_exhaustive]
@@ -300,6 +882,102 @@ pub struct TlsTemplate {
     ///
     /// Corresponds to the combined length of the `.tdata` and `.tbss` sections.
     pub mem_size: u64,
+    /// The required alignment of the thread local storage in bytes.
+    ///
+    /// Each thread's copy of the template must be placed at an address that is aligned to
+    /// this value. It is always a power of two, or `0`/`1` if no alignment is required.
+    pub align: u64,
+}
+
+/// Information about the features supported by the CPU.
+///
+/// The features are detected on the bootstrap processor through the `cpuid` instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+#[repr(C)]
+pub struct CpuFeatures {
+    /// Whether the CPU supports process-context identifiers (`CPUID.01H:ECX.PCID [bit 17]`).
+    pub pcid: bool,
+    /// Whether the CPU supports the `INVPCID` instruction (`CPUID.(EAX=07H,ECX=0H):EBX.INVPCID [bit 10]`).
+    pub invpcid: bool,
+    /// Whether the bootloader set the `CR4.PCIDE` bit before jumping to the kernel.
+    ///
+    /// This is only the case if the `enable_pcid` config option is set and the CPU supports
+    /// PCIDs.
+    pub pcid_enabled: bool,
+    /// Whether the CPU supports the no-execute page table bit (`CPUID.80000001H:EDX.NX [bit 20]`).
+    ///
+    /// The bootloader only sets `EFER.NXE` and maps non-executable pages if this is the case.
+    /// Otherwise, all pages of the kernel address space are executable.
+    pub nx: bool,
+    /// Whether the CPU supports 1GiB pages (`CPUID.80000001H:EDX.Page1GB [bit 26]`).
+    ///
+    /// If this is the case, the physical memory mapping uses 1GiB pages, provided that its
+    /// offset is 1GiB-aligned. All other mappings of the bootloader use 4KiB and 2MiB pages,
+    /// which every x86_64 CPU supports.
+    pub pages_1gib: bool,
+}
+
+impl CpuFeatures {
+    /// Creates a new instance with all features set to `false`.
+    pub const fn new() -> Self {
+        Self {
+            pcid: false,
+            invpcid: false,
+            pcid_enabled: false,
+            nx: false,
+            pages_1gib: false,
+        }
+    }
+}
+
+/// Information about the frequency of the time stamp counter (TSC).
+///
+/// A value of zero means that the respective information is not available.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+#[repr(C)]
+pub struct TscInfo {
+    /// The TSC frequency in Hz, as measured by the bootloader.
+    ///
+    /// On UEFI, the bootloader calibrates the TSC against the `Stall` boot service before
+    /// exiting boot services. The value is zero if no calibration was performed, which is
+    /// always the case on BIOS.
+    pub frequency: u64,
+    /// The numerator of the TSC/core crystal clock ratio (`CPUID.15H:EBX`).
+    ///
+    /// Zero if CPUID leaf `0x15` is not supported or doesn't enumerate the ratio.
+    pub crystal_ratio_numerator: u32,
+    /// The denominator of the TSC/core crystal clock ratio (`CPUID.15H:EAX`).
+    ///
+    /// Zero if CPUID leaf `0x15` is not supported or doesn't enumerate the ratio.
+    pub crystal_ratio_denominator: u32,
+    /// The nominal frequency of the core crystal clock in Hz (`CPUID.15H:ECX`).
+    ///
+    /// Zero if CPUID leaf `0x15` is not supported or doesn't enumerate the frequency. If
+    /// available, the TSC frequency is
+    /// `crystal_frequency * crystal_ratio_numerator / crystal_ratio_denominator`.
+    pub crystal_frequency: u32,
+    /// The value of the TSC when the bootloader started.
+    ///
+    /// This is read at the start of the UEFI application or at the start of the fourth
+    /// stage on BIOS, so it doesn't include the time spent in the firmware and the
+    /// earlier BIOS stages. Reading the TSC in the kernel entry point and subtracting this
+    /// value gives the number of cycles spent in the bootloader.
+    pub bootloader_start: u64,
+}
+
+impl TscInfo {
+    /// Creates a new instance with all fields set to zero.
+    pub const fn new() -> Self {
+        Self {
+            frequency: 0,
+            crystal_ratio_numerator: 0,
+            crystal_ratio_denominator: 0,
+            crystal_frequency: 0,
+            bootloader_start: 0,
+        }
+    }
 }
 
 /// FFI-safe variant of [`Option`].
@@ -368,3 +1046,254 @@ impl<T> From<Optional<T>> for Option<T> {
 
 /// Check that bootinfo is FFI-safe
 extern "C" fn _assert_ffi(_boot_info: BootInfo) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Creates a memory map with the given `(start, end, kind)` regions and no attributes.
+    fn regions(spec: &[(u64, u64, MemoryRegionKind)]) -> MemoryRegions {
+        let regions = spec
+            .iter()
+            .map(|&(start, end, kind)| MemoryRegion {
+                start,
+                end,
+                kind,
+                attributes: 0,
+            })
+            .collect::<Vec<_>>();
+        MemoryRegions::from(Box::leak(regions.into_boxed_slice()))
+    }
+
+    #[test]
+    fn memory_regions_csv() {
+        let regions = regions(&[
+            (0, 0x1000, MemoryRegionKind::Usable),
+            (0x1000, 0x2000, MemoryRegionKind::Bootloader),
+            (0x2000, 0x3000, MemoryRegionKind::UnknownUefi(7)),
+            (0x3000, 0x4000, MemoryRegionKind::UnknownBios(2)),
+            (0x4000, 0x5000, MemoryRegionKind::AcpiReclaimable),
+            (0x5000, 0x6000, MemoryRegionKind::AcpiNvs),
+            (0x6000, 0x7000, MemoryRegionKind::KernelAndModules),
+            (0x7000, 0x8000, MemoryRegionKind::DmaBuffer),
+        ]);
+
+        let mut csv = String::new();
+        regions.write_csv(&mut csv).unwrap();
+        assert_eq!(
+            csv,
+            "bootloader-memory-map,v2\n\
+             start,end,kind\n\
+             0x0,0x1000,usable\n\
+             0x1000,0x2000,bootloader\n\
+             0x2000,0x3000,uefi:7\n\
+             0x3000,0x4000,bios:2\n\
+             0x4000,0x5000,acpi-reclaimable\n\
+             0x5000,0x6000,acpi-nvs\n\
+             0x6000,0x7000,kernel-and-modules\n\
+             0x7000,0x8000,dma-buffer\n"
+        );
+    }
+
+    #[test]
+    fn memory_regions_usable() {
+        let empty = regions(&[]);
+        let regions = regions(&[
+            (0, 0x1000, MemoryRegionKind::Usable),
+            (0x1000, 0x10_0000, MemoryRegionKind::Bootloader),
+            (0x10_0000, 0x10_3000, MemoryRegionKind::Usable),
+            (0x10_3000, 0x10_4000, MemoryRegionKind::AcpiReclaimable),
+            (0x10_4000, 0x10_7000, MemoryRegionKind::Usable),
+        ]);
+        assert_eq!(regions.usable_bytes(), 0x7000);
+        // the first of the two largest regions
+        assert_eq!(
+            regions.largest_usable_region().map(|r| r.start),
+            Some(0x10_0000)
+        );
+
+        assert_eq!(empty.usable_bytes(), 0);
+        assert_eq!(empty.largest_usable_region(), None);
+    }
+
+    #[test]
+    fn memory_regions_lookup() {
+        let empty = regions(&[]);
+        let regions = regions(&[
+            (0x1000, 0x2000, MemoryRegionKind::Usable),
+            (0x2000, 0x4000, MemoryRegionKind::Bootloader),
+            // gap between 0x4000 and 0x8000
+            (0x8000, 0x9000, MemoryRegionKind::AcpiNvs),
+        ]);
+
+        assert_eq!(regions.kind_of(0x1000), Some(MemoryRegionKind::Usable));
+        assert_eq!(regions.kind_of(0x1fff), Some(MemoryRegionKind::Usable));
+        // the end address of a region belongs to the next one
+        assert_eq!(regions.kind_of(0x2000), Some(MemoryRegionKind::Bootloader));
+        assert_eq!(
+            regions.region_containing(0x3fff).map(|r| r.start),
+            Some(0x2000)
+        );
+        assert_eq!(regions.kind_of(0x8000), Some(MemoryRegionKind::AcpiNvs));
+
+        // before the first region, in the gap, and after the last region
+        assert_eq!(regions.region_containing(0), None);
+        assert_eq!(regions.region_containing(0xfff), None);
+        assert_eq!(regions.region_containing(0x4000), None);
+        assert_eq!(regions.region_containing(0x7fff), None);
+        assert_eq!(regions.region_containing(0x9000), None);
+        assert_eq!(regions.kind_of(u64::MAX), None);
+
+        assert_eq!(empty.kind_of(0x1000), None);
+    }
+
+    #[test]
+    fn boot_info_api_version() {
+        let boot_info = BootInfo::new(regions(&[]));
+        assert_eq!(boot_info.api_version, ApiVersion::CURRENT);
+    }
+
+    #[test]
+    fn memory_regions_iter() {
+        let regions = regions(&[
+            (0, 0x1000, MemoryRegionKind::Usable),
+            (0x1000, 0x2000, MemoryRegionKind::Bootloader),
+            (0x2000, 0x4000, MemoryRegionKind::Usable),
+        ]);
+
+        assert_eq!(regions.len(), 3);
+        assert!(!regions.is_empty());
+        assert_eq!(regions.regions().len(), 3);
+
+        let usable: u64 = (&regions)
+            .into_iter()
+            .filter(|region| region.kind == MemoryRegionKind::Usable)
+            .map(|region| region.end - region.start)
+            .sum();
+        assert_eq!(usable, 0x3000);
+
+        let starts: Vec<u64> = regions.iter().map(|region| region.start).collect();
+        assert_eq!(starts, [0, 0x1000, 0x2000]);
+    }
+
+    #[test]
+    fn framebuffer_pixel_offset() {
+        let info = FrameBufferInfo {
+            byte_len: 4 * 16 * 10,
+            width: 12,
+            height: 10,
+            pixel_format: PixelFormat::Bgr,
+            bytes_per_pixel: 4,
+            stride: 16,
+        };
+
+        assert_eq!(info.pixel_offset(0, 0), Some(0));
+        assert_eq!(info.pixel_offset(3, 0), Some(12));
+        assert_eq!(info.pixel_offset(0, 2), Some(128));
+        assert_eq!(info.pixel_offset(11, 9), Some((9 * 16 + 11) * 4));
+        // pixels in the padding at the end of a line are not visible
+        assert_eq!(info.pixel_offset(12, 0), None);
+        assert_eq!(info.pixel_offset(0, 10), None);
+    }
+
+    #[test]
+    fn framebuffer_set_pixel() {
+        let info = FrameBufferInfo {
+            byte_len: 4 * 4 * 2,
+            width: 3,
+            height: 2,
+            pixel_format: PixelFormat::Bgr,
+            bytes_per_pixel: 4,
+            stride: 4,
+        };
+        let buffer: &'static mut [u8] = Box::leak(vec![0xff; info.byte_len].into_boxed_slice());
+        let mut framebuffer = unsafe { FrameBuffer::new(buffer.as_ptr() as u64, info) };
+
+        framebuffer.clear();
+        assert!(framebuffer.buffer().iter().all(|&b| b == 0));
+
+        assert_eq!(framebuffer.set_pixel(2, 1, [1, 2, 3]), Ok(()));
+        assert_eq!(framebuffer.buffer()[24..28], [3, 2, 1, 0]);
+        assert!(framebuffer.set_pixel(3, 0, [1, 2, 3]).is_err());
+        assert!(framebuffer.set_pixel(0, 2, [1, 2, 3]).is_err());
+
+        framebuffer.fill([0xaa, 0xbb, 0xcc]);
+        for (i, pixel) in framebuffer.buffer().chunks(4).enumerate() {
+            match i % 4 {
+                // padding at the end of each line
+                3 => assert_eq!(pixel, [0, 0, 0, 0]),
+                _ => assert_eq!(pixel, [0xcc, 0xbb, 0xaa, 0]),
+            }
+        }
+    }
+
+    #[test]
+    fn framebuffer_encode_color() {
+        let info = |pixel_format| FrameBufferInfo {
+            byte_len: 0,
+            width: 0,
+            height: 0,
+            pixel_format,
+            bytes_per_pixel: 4,
+            stride: 0,
+        };
+
+        assert_eq!(info(PixelFormat::Rgb).encode_color([1, 2, 3]), [1, 2, 3, 0]);
+        assert_eq!(info(PixelFormat::Bgr).encode_color([1, 2, 3]), [3, 2, 1, 0]);
+        assert_eq!(
+            info(PixelFormat::U8).encode_color([30, 60, 90]),
+            [60, 0, 0, 0]
+        );
+        let unknown = PixelFormat::Unknown {
+            red_position: 24,
+            green_position: 16,
+            blue_position: 8,
+        };
+        assert_eq!(info(unknown).encode_color([1, 2, 3]), [0, 3, 2, 1]);
+    }
+
+    #[test]
+    fn memory_regions_display() {
+        let mut regions = regions(&[
+            (0x1000, 0x9_f000, MemoryRegionKind::Usable),
+            (0x10_0000, 0x28_0000, MemoryRegionKind::UnknownUefi(7)),
+            (0x1_0000_0000, 0x2_0000_0000, MemoryRegionKind::Usable),
+            (0xffff_f000, 0xffff_f200, MemoryRegionKind::Bootloader),
+        ]);
+        regions[1].attributes = 0xf;
+
+        assert_eq!(
+            regions.to_string(),
+            "[0x0000000000001000..0x000000000009f000] size=632 KiB kind=Usable\n\
+             [0x0000000000100000..0x0000000000280000] size=1.5 MiB kind=UnknownUefi(7) attributes=0xf\n\
+             [0x0000000100000000..0x0000000200000000] size=4 GiB kind=Usable\n\
+             [0x00000000fffff000..0x00000000fffff200] size=512 B kind=Bootloader\n"
+        );
+    }
+
+    #[test]
+    fn memory_region_eq() {
+        let region = MemoryRegion {
+            start: 0x1000,
+            end: 0x2000,
+            kind: MemoryRegionKind::UnknownUefi(7),
+            attributes: 0xf,
+        };
+        let copy = region;
+        assert_eq!(region, copy);
+        assert_ne!(
+            region,
+            MemoryRegion {
+                kind: MemoryRegionKind::UnknownUefi(8),
+                ..region
+            }
+        );
+        assert_ne!(
+            MemoryRegionKind::UnknownUefi(7),
+            MemoryRegionKind::UnknownBios(7)
+        );
+
+        let set: std::collections::HashSet<_> = [region, copy, MemoryRegion::empty()].into();
+        assert_eq!(set.len(), 2);
+    }
+}
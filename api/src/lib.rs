@@ -28,6 +28,18 @@ mod version_info {
 /// point. The advantage of using this macro instead of providing an own `_start` function is
 /// that the macro ensures that the function and argument types are correct.
 ///
+/// The bootloader jumps to `_start` with the stack set up as if `_start` was invoked by a
+/// `call` instruction of the System V ABI: `rsp + 8` is 16-byte aligned and the (fake) return
+/// address is `0`. Two arguments are passed according to the System V calling convention:
+///
+/// - `rdi`: the pointer to the [`BootInfo`] struct
+/// - `rsi`: the size of the [`BootInfo`] struct in bytes, as compiled into the bootloader
+///
+/// The function generated by this macro only takes the first argument. Kernels that define
+/// their own `_start` function as `extern "C" fn(&'static mut BootInfo, usize) -> !` can
+/// compare the second argument against `size_of::<BootInfo>()` to detect a mismatch between
+/// the bootloader and `bootloader_api` versions before accessing any other field.
+///
 /// ## Configuration
 ///
 /// This macro supports an optional second parameter to configure how the bootloader should
@@ -118,6 +130,7 @@ macro_rules! entry_point {
             pub static __BOOTLOADER_CONFIG: [u8; $crate::BootloaderConfig::SERIALIZED_LEN] = {
                 // validate the type
                 let config: &$crate::BootloaderConfig = $config;
+                config.validate();
                 config.serialize()
             };
 
@@ -9,6 +9,7 @@ pub struct BiosInfo {
     pub kernel: Region,
     pub ramdisk: Region,
     pub config_file: Region,
+    pub cmdline: Region,
     pub last_used_addr: u64,
     pub framebuffer: BiosFramebufferInfo,
     pub memory_map_addr: u32,
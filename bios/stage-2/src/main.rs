@@ -122,6 +122,9 @@ fn start(disk_number: u16, partition_table_start: *const u8) -> ! {
         disk_buffer,
     )
     .unwrap_or(0);
+    let cmdline_start = config_file_start.wrapping_add(config_file_len.try_into().unwrap());
+    let cmdline_len =
+        try_load_file("cmdline", cmdline_start, &mut fs, &mut disk, disk_buffer).unwrap_or(0);
 
     let memory_map = unsafe { memory_map::query_memory_map() }.unwrap();
     writeln!(screen::Writer, "{memory_map:x?}").unwrap();
@@ -161,7 +164,11 @@ fn start(disk_number: u16, partition_table_start: *const u8) -> ! {
             start: config_file_start as u64,
             len: config_file_len,
         },
-        last_used_addr: config_file_start as u64 + config_file_len - 1,
+        cmdline: Region {
+            start: cmdline_start as u64,
+            len: cmdline_len,
+        },
+        last_used_addr: cmdline_start as u64 + cmdline_len - 1,
         memory_map_addr: memory_map.as_mut_ptr() as u32,
         memory_map_len: memory_map.len().try_into().unwrap(),
         framebuffer: BiosFramebufferInfo {
@@ -3,12 +3,12 @@
 
 use crate::memory_descriptor::MemoryRegion;
 use bootloader_api::info::{FrameBufferInfo, PixelFormat};
-use bootloader_boot_config::{BootConfig, LevelFilter};
+use bootloader_boot_config::{BootConfig, LevelFilter, UnknownPixelFormatPolicy};
 use bootloader_x86_64_bios_common::{BiosFramebufferInfo, BiosInfo, E820MemoryRegion};
 use bootloader_x86_64_common::RawFrameBufferInfo;
 use bootloader_x86_64_common::{
-    legacy_memory_region::LegacyFrameAllocator, load_and_switch_to_kernel, Kernel, PageTables,
-    SystemInfo,
+    acpi, legacy_memory_region::LegacyFrameAllocator, load_and_switch_to_kernel, timing::BootTimer,
+    Kernel, PageTables, SystemInfo,
 };
 use core::{cmp, slice};
 use usize_conversions::usize_from;
@@ -25,6 +25,8 @@ mod memory_descriptor;
 #[no_mangle]
 #[link_section = ".start"]
 pub extern "C" fn _start(info: &mut BiosInfo) -> ! {
+    let mut boot_timer = BootTimer::start();
+
     let memory_map: &mut [E820MemoryRegion] = unsafe {
         core::slice::from_raw_parts_mut(
             info.memory_map_addr as *mut _,
@@ -54,11 +56,18 @@ pub extern "C" fn _start(info: &mut BiosInfo) -> ! {
         PhysAddr::new(info.kernel.start)
     };
     let kernel_size = info.kernel.len;
+    let kernel_slice = {
+        let ptr = kernel_start.as_u64() as *const u8;
+        unsafe { slice::from_raw_parts(ptr, usize_from(kernel_size)) }
+    };
+    let kernel = Kernel::parse(kernel_slice);
+
     let next_free_frame = PhysFrame::containing_address(PhysAddr::new(info.last_used_addr)) + 1;
     let mut frame_allocator = LegacyFrameAllocator::new_starting_at(
         next_free_frame,
         memory_map.iter().copied().map(MemoryRegion),
     );
+    frame_allocator.set_min_addr(PhysAddr::new(kernel.config.min_frame_address));
 
     // We identity-mapped all memory, so the offset between physical and virtual addresses is 0
     let phys_offset = VirtAddr::new(0);
@@ -100,12 +109,6 @@ pub extern "C" fn _start(info: &mut BiosInfo) -> ! {
 
     let page_tables = create_page_tables(&mut frame_allocator);
 
-    let kernel_slice = {
-        let ptr = kernel_start.as_u64() as *const u8;
-        unsafe { slice::from_raw_parts(ptr, usize_from(kernel_size)) }
-    };
-    let kernel = Kernel::parse(kernel_slice);
-
     let mut config_file_slice: Option<&[u8]> = None;
     if info.config_file.len != 0 {
         config_file_slice = {
@@ -145,6 +148,7 @@ pub extern "C" fn _start(info: &mut BiosInfo) -> ! {
         config.log_level,
         config.frame_buffer_logging,
         config.serial_logging,
+        config.unknown_pixel_format,
     );
 
     if let Some(err) = error_loading_config {
@@ -154,21 +158,40 @@ pub extern "C" fn _start(info: &mut BiosInfo) -> ! {
     log::info!("4th Stage");
     log::info!("{info:x?}");
     log::info!("BIOS boot");
+    boot_timer.phase("Mapping physical memory and initializing the logger");
 
+    let rsdp_addr = detect_rsdp();
     let system_info = SystemInfo {
         framebuffer: Some(RawFrameBufferInfo {
             addr: PhysAddr::new(info.framebuffer.region.start),
             info: framebuffer_info,
+            id: 0,
+            firmware_pixel_format: None,
         }),
-        rsdp_addr: detect_rsdp(),
+        rsdp_addr,
+        smbios_addr: detect_smbios(),
         ramdisk_addr: match info.ramdisk.len {
             0 => None,
             _ => Some(info.ramdisk.start),
         },
         ramdisk_len: info.ramdisk.len,
+        cmdline: match info.cmdline.len {
+            0 => None,
+            len => Some(unsafe {
+                slice::from_raw_parts(info.cmdline.start as *const u8, usize_from(len))
+            }),
+        },
+        tsc_frequency: None,
+        cpu_count: acpi::cpu_count(rsdp_addr),
+        graphics_modes: &[],
+        firmware_vendor: None,
+        firmware_revision: 0,
+        boot_timer,
     };
 
-    load_and_switch_to_kernel(kernel, config, frame_allocator, page_tables, system_info);
+    let Err(err) =
+        load_and_switch_to_kernel(kernel, config, frame_allocator, page_tables, system_info);
+    panic!("Failed to set up the kernel address space: {err}");
 }
 
 fn init_logger(
@@ -176,6 +199,7 @@ fn init_logger(
     log_level: LevelFilter,
     frame_buffer_logger_status: bool,
     serial_logger_status: bool,
+    unknown_pixel_format: UnknownPixelFormatPolicy,
 ) -> FrameBufferInfo {
     let framebuffer_info = FrameBufferInfo {
         byte_len: info.region.len.try_into().unwrap(),
@@ -206,11 +230,11 @@ fn init_logger(
     };
 
     bootloader_x86_64_common::init_logger(
-        framebuffer,
-        framebuffer_info,
+        Some((framebuffer, framebuffer_info)),
         log_level,
         frame_buffer_logger_status,
         serial_logger_status,
+        unknown_pixel_format,
     );
 
     framebuffer_info
@@ -231,7 +255,9 @@ fn create_page_tables(frame_allocator: &mut impl FrameAllocator<Size4KiB>) -> Pa
     // create a new page table hierarchy for the kernel
     let (kernel_page_table, kernel_level_4_frame) = {
         // get an unused frame for new level 4 page table
-        let frame: PhysFrame = frame_allocator.allocate_frame().expect("no unused frames");
+        let frame: PhysFrame = frame_allocator
+            .allocate_frame()
+            .expect("out of physical memory while creating the kernel page table");
         log::info!("New page table at: {frame:#?}");
         // get the corresponding virtual address
         let addr = phys_offset + frame.start_address().as_u64();
@@ -290,6 +316,25 @@ fn detect_rsdp() -> Option<PhysAddr> {
     }
 }
 
+/// Searches the BIOS area for an SMBIOS entry point structure.
+///
+/// The entry point starts with an anchor string on a 16-byte boundary between `0xf0000` and
+/// `0xfffff`. The 64-bit SMBIOS 3.0 entry point is preferred if both exist.
+fn detect_smbios() -> Option<PhysAddr> {
+    const START: usize = 0xf_0000;
+    const END: usize = 0x10_0000;
+
+    // the BIOS area is identity-mapped
+    let area = unsafe { slice::from_raw_parts(START as *const u8, END - START) };
+    let find = |anchor: &[u8]| {
+        (0..area.len())
+            .step_by(16)
+            .find(|&offset| area[offset..].starts_with(anchor))
+            .map(|offset| PhysAddr::new((START + offset) as u64))
+    };
+    find(b"_SM3_").or_else(|| find(b"_SM_"))
+}
+
 #[cfg(target_os = "none")]
 #[panic_handler]
 fn panic(info: &core::panic::PanicInfo) -> ! {
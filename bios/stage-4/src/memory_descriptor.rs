@@ -15,6 +15,8 @@ impl LegacyMemoryRegion for MemoryRegion {
     fn kind(&self) -> MemoryRegionKind {
         match self.0.region_type {
             1 => MemoryRegionKind::Usable,
+            3 => MemoryRegionKind::AcpiReclaimable,
+            4 => MemoryRegionKind::AcpiNvs,
             other => MemoryRegionKind::UnknownBios(other),
         }
     }
@@ -74,6 +74,18 @@ async fn build_uefi_bootloader(out_dir: &Path) -> PathBuf {
         cmd.arg("--version").arg(BOOTLOADER_VERSION);
     }
     cmd.arg("--locked");
+    if std::env::var_os("CARGO_FEATURE_KERNEL_COMPRESSION").is_some() {
+        cmd.arg("--features").arg("kernel-compression");
+    }
+    if std::env::var_os("CARGO_FEATURE_BOOT_MENU").is_some() {
+        cmd.arg("--features").arg("boot-menu");
+    }
+    if std::env::var_os("CARGO_FEATURE_BOOT_LOG").is_some() {
+        cmd.arg("--features").arg("boot-log");
+    }
+    if std::env::var_os("CARGO_FEATURE_RESET_ON_ERROR").is_some() {
+        cmd.arg("--features").arg("reset-on-error");
+    }
     cmd.arg("--target").arg("x86_64-unknown-uefi");
     cmd.arg("-Zbuild-std=core")
         .arg("-Zbuild-std-features=compiler-builtins-mem");
@@ -2,6 +2,14 @@
 
 use serde::{Deserialize, Serialize};
 
+/// Magic bytes at the start of a kernel image that was compressed with the `kernel-compression`
+/// feature of the `bootloader` crate.
+///
+/// Shared by the compressor of the disk image builder and the decompressor of the UEFI
+/// bootloader. Chosen so that a compressed image can never be confused with an ELF file, which
+/// starts with `\x7fELF`.
+pub const COMPRESSED_KERNEL_MAGIC: [u8; 4] = *b"BLZ4";
+
 /// Configures the boot behavior of the bootloader.
 #[derive(Serialize, Deserialize)]
 #[serde(default)]
@@ -17,6 +25,10 @@ pub struct BootConfig {
 
     /// Whether the bootloader should print log messages to the framebuffer during boot.
     ///
+    /// If enabled, the whole framebuffer is cleared to black before the first message is
+    /// printed. If disabled, the bootloader doesn't write to the framebuffer at all, so any
+    /// content drawn by the firmware is preserved for the kernel.
+    ///
     /// Enabled by default.
     pub frame_buffer_logging: bool,
 
@@ -25,6 +37,46 @@ pub struct BootConfig {
     /// Enabled by default.
     pub serial_logging: bool,
 
+    /// Configures how the bootloader handles framebuffers with a pixel format that its
+    /// logger doesn't support.
+    ///
+    /// The default is [`UnknownPixelFormatPolicy::SerialFallback`].
+    pub unknown_pixel_format: UnknownPixelFormatPolicy,
+
+    /// Configures which UEFI memory types are reported as usable to the kernel.
+    ///
+    /// Only used when booting on UEFI systems.
+    pub uefi_memory_types: UefiMemoryTypes,
+
+    /// Whether the bootloader should write the final memory map to the serial port before
+    /// jumping to the kernel.
+    ///
+    /// The memory map is written in the CSV format described in
+    /// `bootloader_api::info::MemoryRegions::write_csv`. This is useful for validating the
+    /// memory map passed to the kernel with external tools. Has no effect if `serial_logging`
+    /// is disabled.
+    ///
+    /// Disabled by default.
+    pub serial_memory_map: bool,
+
+    /// Configuration for the boot menu that is shown if the disk image contains multiple
+    /// kernels.
+    ///
+    /// Only used by the UEFI bootloader, if it is built with the `boot-menu` feature.
+    pub boot_menu: BootMenu,
+
+    /// Whether the bootloader should write its log messages to a `bootlog.txt` file on the
+    /// boot partition.
+    ///
+    /// The messages are kept in a fixed-size in-memory buffer that drops the oldest lines
+    /// when it is full. The file is written just before exiting the UEFI boot services, so
+    /// it contains all messages up to that point. This is useful for debugging machines
+    /// without a serial port. Has no effect when booting over the network.
+    ///
+    /// Only used by the UEFI bootloader, if it is built with the `boot-log` feature.
+    /// Disabled by default.
+    pub boot_log_file: bool,
+
     #[doc(hidden)]
     pub _test_sentinel: u64,
 }
@@ -36,11 +88,112 @@ impl Default for BootConfig {
             log_level: Default::default(),
             frame_buffer_logging: true,
             serial_logging: true,
+            unknown_pixel_format: Default::default(),
+            uefi_memory_types: Default::default(),
+            serial_memory_map: false,
+            boot_menu: Default::default(),
+            boot_log_file: false,
             _test_sentinel: 0,
         }
     }
 }
 
+/// Configures how the bootloader reacts to a framebuffer pixel format that it doesn't support.
+///
+/// Independent of this setting, the framebuffer is still passed to the kernel with its
+/// original pixel format, so that the kernel can decide how to use it.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum UnknownPixelFormatPolicy {
+    /// Disables logging to the framebuffer and only logs to the serial port (if enabled).
+    SerialFallback,
+    /// Treats the framebuffer as `BGR` when logging to it.
+    ///
+    /// The output might be garbled or have wrong colors, depending on the actual format.
+    AssumeBgr,
+}
+
+impl Default for UnknownPixelFormatPolicy {
+    fn default() -> Self {
+        Self::SerialFallback
+    }
+}
+
+/// Configures how UEFI memory types that are only needed during boot are classified in the
+/// memory map passed to the kernel.
+///
+/// Each field corresponds to one UEFI memory type. If set to `true`, memory regions of that
+/// type are reported as `Usable` once the bootloader passes control to the kernel. Otherwise,
+/// they keep their `UnknownUefi` kind so that the kernel can decide when to reclaim them.
+///
+/// Conventional memory is always reported as usable and runtime services memory is never
+/// reported as usable, as required by the UEFI standard. ACPI memory is reported as
+/// `AcpiReclaimable` or `AcpiNvs`. All other memory types keep their `UnknownUefi` kind.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+#[serde(default)]
+#[non_exhaustive]
+pub struct UefiMemoryTypes {
+    /// Whether `LOADER_CODE` memory is usable after boot.
+    ///
+    /// Defaults to `true`.
+    pub loader_code: bool,
+    /// Whether `LOADER_DATA` memory is usable after boot.
+    ///
+    /// The kernel and ramdisk files are loaded into `LOADER_DATA` memory, but these
+    /// regions stay reserved independently of this setting.
+    ///
+    /// Defaults to `true`.
+    pub loader_data: bool,
+    /// Whether `BOOT_SERVICES_CODE` memory is usable after boot.
+    ///
+    /// Defaults to `true`.
+    pub boot_services_code: bool,
+    /// Whether `BOOT_SERVICES_DATA` memory is usable after boot.
+    ///
+    /// Defaults to `true`.
+    pub boot_services_data: bool,
+}
+
+impl Default for UefiMemoryTypes {
+    fn default() -> Self {
+        Self {
+            loader_code: true,
+            loader_data: true,
+            boot_services_code: true,
+            boot_services_data: true,
+        }
+    }
+}
+
+/// Configuration for the boot menu that allows choosing between multiple kernels.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+#[serde(default)]
+#[non_exhaustive]
+pub struct BootMenu {
+    /// The number of seconds to wait for the user to select an entry before the default
+    /// entry is booted.
+    ///
+    /// If set to `0`, the menu is printed, but the default entry is booted immediately.
+    ///
+    /// Defaults to `5`.
+    pub timeout_secs: u64,
+    /// The index of the entry that is booted if the user doesn't select an entry.
+    ///
+    /// The first entry is used if the index is out of bounds.
+    ///
+    /// Defaults to `0`.
+    pub default_entry: usize,
+}
+
+impl Default for BootMenu {
+    fn default() -> Self {
+        Self {
+            timeout_secs: 5,
+            default_entry: 0,
+        }
+    }
+}
+
 /// Configuration for the frame buffer used for graphical output.
 #[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq, Clone, Copy)]
 #[non_exhaustive]
@@ -53,6 +206,30 @@ pub struct FrameBuffer {
     ///
     /// If this is not possible, the bootloader will fall back to a smaller format.
     pub minimum_framebuffer_width: Option<u64>,
+    /// Instructs the bootloader to set up a framebuffer with the given resolution.
+    ///
+    /// Takes precedence over the `minimum_framebuffer_height` and `minimum_framebuffer_width`
+    /// options. If the display mode can't be set, the bootloader keeps the current mode.
+    ///
+    /// Only supported when booting on UEFI systems.
+    pub preferred_resolution: Option<PreferredResolution>,
+}
+
+/// Describes the preferred resolution of the framebuffer.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+#[non_exhaustive]
+pub enum PreferredResolution {
+    /// Use the display mode with the given resolution.
+    ///
+    /// If no such mode exists, the mode with the closest resolution is used instead.
+    Exact {
+        /// The width in pixels.
+        width: u64,
+        /// The height in pixels.
+        height: u64,
+    },
+    /// Use the display mode with the highest resolution.
+    Highest,
 }
 
 /// An enum representing the available verbosity level filters of the logger.
@@ -0,0 +1,167 @@
+use core::slice;
+use x86_64::PhysAddr;
+
+/// Size of the header that all ACPI system description tables start with.
+const SDT_HEADER_LEN: usize = 36;
+/// Size of the fixed MADT fields between the table header and the interrupt controller
+/// structures (local APIC address and flags).
+const MADT_FIELDS_LEN: usize = 8;
+
+const MADT_LOCAL_APIC: u8 = 0;
+const MADT_LOCAL_X2APIC: u8 = 9;
+/// The `Enabled` bit in the flags of (x2)APIC entries.
+const APIC_ENABLED: u32 = 1;
+
+/// Determines the number of enabled CPUs from the MADT of the given ACPI tables.
+///
+/// Falls back to a single CPU and logs a warning if no MADT is found or if it is malformed.
+///
+/// The physical memory containing the ACPI tables must be identity-mapped.
+pub fn cpu_count(rsdp_addr: Option<PhysAddr>) -> usize {
+    let count = rsdp_addr
+        .and_then(|rsdp_addr| unsafe { find_table(rsdp_addr, b"APIC") })
+        .map(count_enabled_cpus);
+    match count {
+        Some(Some(count)) if count > 0 => count,
+        Some(_) => {
+            log::warn!("Failed to parse the MADT, assuming a single CPU");
+            1
+        }
+        None => {
+            log::warn!("No MADT found, assuming a single CPU");
+            1
+        }
+    }
+}
+
+/// Counts the enabled local APIC and local x2APIC entries of the given MADT.
+///
+/// Returns `None` if the table is malformed.
+fn count_enabled_cpus(madt: &[u8]) -> Option<usize> {
+    if !checksum_valid(madt) {
+        return None;
+    }
+    let mut entries = madt.get(SDT_HEADER_LEN + MADT_FIELDS_LEN..)?;
+    let mut count = 0;
+    while !entries.is_empty() {
+        let entry_type = entries[0];
+        let entry_len = usize::from(*entries.get(1)?);
+        if entry_len < 2 {
+            return None;
+        }
+        let entry = entries.get(..entry_len)?;
+        let flags = match entry_type {
+            MADT_LOCAL_APIC => Some(read_u32(entry, 4)?),
+            MADT_LOCAL_X2APIC => Some(read_u32(entry, 8)?),
+            _ => None,
+        };
+        if flags.map_or(false, |flags| flags & APIC_ENABLED != 0) {
+            count += 1;
+        }
+        entries = &entries[entry_len..];
+    }
+    Some(count)
+}
+
+/// Looks up the system description table with the given signature through the RSDT or XSDT.
+///
+/// ## Safety
+///
+/// The given address must point to a valid RSDP and the ACPI tables must be identity-mapped.
+unsafe fn find_table(rsdp_addr: PhysAddr, signature: &[u8; 4]) -> Option<&'static [u8]> {
+    let rsdp = unsafe { slice::from_raw_parts(rsdp_addr.as_u64() as *const u8, 20) };
+    if &rsdp[..8] != b"RSD PTR " {
+        return None;
+    }
+    // the XSDT is only available in ACPI 2.0 and later
+    let (root, entry_size) = match rsdp[15] {
+        0 => (u64::from(read_u32(rsdp, 16)?), 4),
+        _ => {
+            let rsdp = unsafe { slice::from_raw_parts(rsdp_addr.as_u64() as *const u8, 36) };
+            (read_u64(rsdp, 24)?, 8)
+        }
+    };
+    let root = unsafe { table(root) }?;
+    if !checksum_valid(root) {
+        return None;
+    }
+    root[SDT_HEADER_LEN..]
+        .chunks_exact(entry_size)
+        .map(|entry| match entry_size {
+            4 => read_u32(entry, 0).map(u64::from),
+            _ => read_u64(entry, 0),
+        })
+        .filter_map(|addr| unsafe { table(addr?) })
+        .find(|table| &table[..4] == signature)
+}
+
+/// Returns the system description table at the given physical address, including its header.
+///
+/// ## Safety
+///
+/// The given address must point to a valid table and the table must be identity-mapped.
+unsafe fn table(addr: u64) -> Option<&'static [u8]> {
+    if addr == 0 {
+        return None;
+    }
+    let header = unsafe { slice::from_raw_parts(addr as *const u8, SDT_HEADER_LEN) };
+    let len = usize::try_from(read_u32(header, 4)?).ok()?;
+    if len < SDT_HEADER_LEN {
+        return None;
+    }
+    Some(unsafe { slice::from_raw_parts(addr as *const u8, len) })
+}
+
+fn checksum_valid(table: &[u8]) -> bool {
+    table.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)) == 0
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    let bytes = bytes.get(offset..offset + 4)?;
+    Some(u32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> Option<u64> {
+    let bytes = bytes.get(offset..offset + 8)?;
+    Some(u64::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// MADT in the format generated by QEMU, with two enabled and one disabled local APIC,
+    /// an I/O APIC, an interrupt source override, a local APIC NMI, and an enabled x2APIC.
+    const MADT: [u8; 112] = [
+        0x41, 0x50, 0x49, 0x43, 0x70, 0x00, 0x00, 0x00, 0x03, 0x05, 0x42, 0x4f, 0x43, 0x48, 0x53,
+        0x20, 0x42, 0x58, 0x50, 0x43, 0x20, 0x20, 0x20, 0x20, 0x01, 0x00, 0x00, 0x00, 0x42, 0x58,
+        0x50, 0x43, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0xe0, 0xfe, 0x01, 0x00, 0x00, 0x00, 0x00,
+        0x08, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x08, 0x01, 0x01, 0x01, 0x00, 0x00, 0x00,
+        0x00, 0x08, 0x02, 0x02, 0x00, 0x00, 0x00, 0x00, 0x01, 0x0c, 0x00, 0x00, 0x00, 0x00, 0xc0,
+        0xfe, 0x00, 0x00, 0x00, 0x00, 0x02, 0x0a, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x04, 0x06, 0xff, 0x00, 0x00, 0x01, 0x09, 0x10, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x01,
+        0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00,
+    ];
+
+    #[test]
+    fn test_count_enabled_cpus() {
+        assert_eq!(count_enabled_cpus(&MADT), Some(3));
+    }
+
+    #[test]
+    fn test_count_enabled_cpus_invalid_checksum() {
+        let mut madt = MADT;
+        madt[9] = madt[9].wrapping_add(1);
+        assert_eq!(count_enabled_cpus(&madt), None);
+    }
+
+    #[test]
+    fn test_count_enabled_cpus_truncated_entry() {
+        // cut the x2APIC entry in half and fix up the checksum
+        let mut madt = MADT[..104].to_vec();
+        madt[4] = 104;
+        madt[9] = 0;
+        madt[9] = 0u8.wrapping_sub(madt.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)));
+        assert_eq!(count_enabled_cpus(&madt), None);
+    }
+}
@@ -0,0 +1,185 @@
+use core::fmt;
+#[cfg(feature = "boot-log")]
+use core::{
+    fmt::Write,
+    sync::atomic::{AtomicBool, Ordering},
+};
+#[cfg(feature = "boot-log")]
+use spinning_top::{const_spinlock, Spinlock};
+
+/// Size of the global boot log buffer in bytes.
+///
+/// The global buffer is only available if this crate is built with the `boot-log` feature.
+pub const BOOT_LOG_SIZE: usize = 32 * 1024;
+
+#[cfg(feature = "boot-log")]
+static BOOT_LOG: Spinlock<LogRing<BOOT_LOG_SIZE>> = const_spinlock(LogRing::new());
+#[cfg(feature = "boot-log")]
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Starts recording all subsequent log messages in the global boot log buffer.
+///
+/// This makes boot failures debuggable on machines without a serial port, as the UEFI
+/// bootloader writes the buffer to a file on the boot partition before exiting the boot
+/// services.
+#[cfg(feature = "boot-log")]
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// Returns whether log messages are recorded in the global boot log buffer.
+#[cfg(feature = "boot-log")]
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Appends the given log record to the global boot log buffer, if recording is enabled.
+#[cfg(feature = "boot-log")]
+pub(crate) fn record(record: &log::Record) {
+    if is_enabled() {
+        let mut boot_log = BOOT_LOG.lock();
+        // writing to the ring buffer never fails
+        let _ = writeln!(boot_log, "{:5}: {}", record.level(), record.args());
+    }
+}
+
+/// Calls the given closure with the contents of the global boot log buffer.
+///
+/// The closure must not log any messages, as the buffer is locked while it runs.
+#[cfg(feature = "boot-log")]
+pub fn with_contents<R>(f: impl FnOnce(&LogRing<BOOT_LOG_SIZE>) -> R) -> R {
+    f(&BOOT_LOG.lock())
+}
+
+/// Force-unlocks the global boot log buffer to prevent a deadlock.
+///
+/// ## Safety
+/// This method is not memory safe and should be only used when absolutely necessary.
+#[cfg(feature = "boot-log")]
+pub(crate) unsafe fn force_unlock() {
+    unsafe { BOOT_LOG.force_unlock() };
+}
+
+/// A fixed-size ring buffer of log lines.
+///
+/// When the buffer is full, the oldest lines are dropped to make room for new ones. Lines that
+/// are longer than the whole buffer are truncated at their start.
+pub struct LogRing<const N: usize> {
+    buf: [u8; N],
+    start: usize,
+    len: usize,
+    dropped_lines: usize,
+}
+
+impl<const N: usize> LogRing<N> {
+    /// Creates an empty buffer.
+    pub const fn new() -> Self {
+        Self {
+            buf: [0; N],
+            start: 0,
+            len: 0,
+            dropped_lines: 0,
+        }
+    }
+
+    /// Returns the buffered bytes, starting with the oldest line.
+    ///
+    /// Because of the wrap-around, the contents are returned as two slices that have to be
+    /// concatenated.
+    pub fn as_slices(&self) -> (&[u8], &[u8]) {
+        if self.start + self.len <= N {
+            (&self.buf[self.start..self.start + self.len], &[])
+        } else {
+            let end = self.start + self.len - N;
+            (&self.buf[self.start..], &self.buf[..end])
+        }
+    }
+
+    /// Returns the number of lines that were dropped because the buffer was full.
+    pub fn dropped_lines(&self) -> usize {
+        self.dropped_lines
+    }
+
+    fn push(&mut self, byte: u8) {
+        if self.len == N {
+            self.drop_oldest_line();
+        }
+        self.buf[(self.start + self.len) % N] = byte;
+        self.len += 1;
+    }
+
+    /// Removes the oldest line from the buffer.
+    ///
+    /// If the buffer only contains a single unfinished line, only its first byte is removed.
+    fn drop_oldest_line(&mut self) {
+        let newline = (0..self.len).find(|i| self.buf[(self.start + i) % N] == b'\n');
+        let removed = match newline {
+            Some(i) => {
+                self.dropped_lines += 1;
+                i + 1
+            }
+            None => 1,
+        };
+        self.start = (self.start + removed) % N;
+        self.len -= removed;
+    }
+}
+
+impl<const N: usize> Default for LogRing<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> fmt::Write for LogRing<N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for &byte in s.as_bytes() {
+            self.push(byte);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::fmt::Write;
+
+    fn contents<const N: usize>(ring: &LogRing<N>) -> Vec<u8> {
+        let (a, b) = ring.as_slices();
+        [a, b].concat()
+    }
+
+    #[test]
+    fn test_log_ring() {
+        let mut ring = LogRing::<16>::new();
+        assert_eq!(contents(&ring), b"");
+        ring.write_str("first\n").unwrap();
+        ring.write_str("second\n").unwrap();
+        assert_eq!(contents(&ring), b"first\nsecond\n");
+        assert_eq!(ring.dropped_lines(), 0);
+    }
+
+    #[test]
+    fn test_log_ring_drops_oldest_lines() {
+        let mut ring = LogRing::<16>::new();
+        ring.write_str("first\n").unwrap();
+        ring.write_str("second\n").unwrap();
+        ring.write_str("third\n").unwrap();
+        assert_eq!(contents(&ring), b"second\nthird\n");
+        assert_eq!(ring.dropped_lines(), 1);
+
+        ring.write_str("4\n5\n6\n7\n").unwrap();
+        assert_eq!(contents(&ring), b"third\n4\n5\n6\n7\n");
+        assert_eq!(ring.dropped_lines(), 2);
+    }
+
+    #[test]
+    fn test_log_ring_long_line() {
+        let mut ring = LogRing::<8>::new();
+        ring.write_str("a\n").unwrap();
+        ring.write_str("0123456789\n").unwrap();
+        assert_eq!(contents(&ring), b"3456789\n");
+        assert_eq!(ring.dropped_lines(), 1);
+    }
+}
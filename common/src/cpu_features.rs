@@ -0,0 +1,109 @@
+use bootloader_api::info::{CpuFeatures, TscInfo};
+use core::arch::asm;
+use raw_cpuid::CpuId;
+use x86_64::{instructions::tlb, registers::model_specific::Msr, PhysAddr};
+
+/// The `IA32_APIC_BASE` model-specific register.
+const IA32_APIC_BASE: u32 = 0x1b;
+/// The `IA32_PAT` model-specific register.
+const IA32_PAT: u32 = 0x277;
+/// The page attribute table entry that is reprogrammed to the write-combining memory type.
+///
+/// Entry 1 is selected by setting only the `WRITE_THROUGH` page table flag. This works for
+/// all page sizes, in contrast to the entries that need the PAT bit, whose position differs
+/// between small and huge pages.
+const WRITE_COMBINING_PAT_ENTRY: u64 = 1;
+/// The encoding of the write-combining memory type in the `IA32_PAT` register.
+const WRITE_COMBINING: u64 = 0x01;
+
+/// Detects the features of the current CPU that are reported to the kernel.
+pub fn detect() -> CpuFeatures {
+    let cpuid = CpuId::new();
+    let mut features = CpuFeatures::new();
+    features.pcid = cpuid
+        .get_feature_info()
+        .map_or(false, |info| info.has_pcid());
+    features.invpcid = cpuid
+        .get_extended_feature_info()
+        .map_or(false, |info| info.has_invpcid());
+    if let Some(info) = cpuid.get_extended_processor_and_feature_identifiers() {
+        features.nx = info.has_execute_disable();
+        features.pages_1gib = info.has_1gib_pages();
+    }
+    features
+}
+
+/// Collects information about the TSC frequency.
+///
+/// The `measured_frequency` is the TSC frequency in Hz that was calibrated by the
+/// firmware-specific part of the bootloader, if any.
+pub fn tsc_info(measured_frequency: Option<u64>) -> TscInfo {
+    let mut tsc = TscInfo::new();
+    tsc.frequency = measured_frequency.unwrap_or(0);
+    if let Some(info) = CpuId::new().get_tsc_info() {
+        tsc.crystal_ratio_numerator = info.numerator();
+        tsc.crystal_ratio_denominator = info.denominator();
+        tsc.crystal_frequency = info.nominal_frequency();
+    }
+    tsc
+}
+
+/// Reprograms the page attribute table so that pages with only the `WRITE_THROUGH` flag set
+/// use the write-combining memory type instead of write-through.
+///
+/// Returns `false` without changing anything if the CPU doesn't support the PAT.
+pub fn enable_write_combining() -> bool {
+    let has_pat = CpuId::new()
+        .get_feature_info()
+        .map_or(false, |info| info.has_pat());
+    if !has_pat {
+        return false;
+    }
+    let mut msr = Msr::new(IA32_PAT);
+    unsafe {
+        let pat = msr.read();
+        // the SDM requires flushing the caches and TLBs when changing memory types
+        asm!("wbinvd", options(nomem, nostack, preserves_flags));
+        msr.write(with_write_combining(pat));
+    }
+    tlb::flush_all();
+    true
+}
+
+/// Returns the given `IA32_PAT` value with the [`WRITE_COMBINING_PAT_ENTRY`] set to
+/// write-combining.
+fn with_write_combining(pat: u64) -> u64 {
+    let shift = WRITE_COMBINING_PAT_ENTRY * 8;
+    (pat & !(0xff << shift)) | (WRITE_COMBINING << shift)
+}
+
+/// Reads the physical base address of the local APIC from the `IA32_APIC_BASE` MSR.
+///
+/// Returns `None` if the CPU has no local APIC or if it is disabled.
+pub fn local_apic_base() -> Option<PhysAddr> {
+    let has_apic = CpuId::new()
+        .get_feature_info()
+        .map_or(false, |info| info.has_apic());
+    if !has_apic {
+        return None;
+    }
+    let value = unsafe { Msr::new(IA32_APIC_BASE).read() };
+    // bit 11 is the global enable flag, the base address starts at bit 12
+    if value & (1 << 11) == 0 {
+        return None;
+    }
+    Some(PhysAddr::new_truncate(value & !0xfff))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_with_write_combining() {
+        // the power-on default: WB, WT, UC-, UC, WB, WT, UC-, UC
+        let default = 0x0007_0406_0007_0406;
+        assert_eq!(with_write_combining(default), 0x0007_0406_0007_0106);
+        assert_eq!(with_write_combining(u64::MAX), 0xffff_ffff_ffff_01ff);
+    }
+}
@@ -34,6 +34,8 @@ fn rd_rand_entropy() -> [u8; 32] {
                 entropy[i * 8..(i + 1) * 8].copy_from_slice(&value.to_ne_bytes());
             }
         }
+    } else {
+        log::warn!("RDRAND is not supported, randomized addresses only use TSC and PIT entropy");
     }
 
     entropy
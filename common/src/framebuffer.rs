@@ -1,5 +1,5 @@
 use bootloader_api::info::{FrameBufferInfo, PixelFormat};
-use core::{fmt, ptr};
+use core::{cmp, fmt, ptr};
 use font_constants::BACKUP_CHAR;
 use noto_sans_mono_bitmap::{
     get_raster, get_raster_width, FontWeight, RasterHeight, RasterizedChar,
@@ -13,6 +13,9 @@ const LETTER_SPACING: usize = 0;
 /// Padding from the border. Prevent that font is too close to border.
 const BORDER_PADDING: usize = 1;
 
+/// Color of the text as `[red, green, blue]`, used for RGB and BGR framebuffers.
+const TEXT_COLOR: [u8; 3] = [0xff, 0xff, 0x80];
+
 /// Constants for the usage of the [`noto_sans_mono_bitmap`] crate.
 mod font_constants {
     use super::*;
@@ -29,6 +32,9 @@ mod font_constants {
     pub const BACKUP_CHAR: char = '�';
 
     pub const FONT_WEIGHT: FontWeight = FontWeight::Regular;
+
+    /// The height of each line, including the spacing to the next line.
+    pub const LINE_HEIGHT: usize = CHAR_RASTER_HEIGHT.val() + LINE_SPACING;
 }
 
 /// Returns the raster of the given char or the raster of [`font_constants::BACKUP_CHAR`].
@@ -65,10 +71,26 @@ impl FrameBufferWriter {
     }
 
     fn newline(&mut self) {
-        self.y_pos += font_constants::CHAR_RASTER_HEIGHT.val() + LINE_SPACING;
+        self.y_pos += font_constants::LINE_HEIGHT;
         self.carriage_return()
     }
 
+    /// Moves all text up by one line to make room for a new line at the bottom of the screen.
+    ///
+    /// Falls back to clearing the screen if it is not high enough for multiple lines.
+    fn scroll(&mut self) {
+        let row_len = self.info.stride * self.info.bytes_per_pixel;
+        let visible_len = cmp::min(self.height() * row_len, self.framebuffer.len());
+        let shift = font_constants::LINE_HEIGHT * row_len;
+        if shift >= visible_len || self.y_pos < font_constants::LINE_HEIGHT {
+            self.clear();
+            return;
+        }
+        self.framebuffer.copy_within(shift..visible_len, 0);
+        self.framebuffer[visible_len - shift..visible_len].fill(0);
+        self.y_pos -= font_constants::LINE_HEIGHT;
+    }
+
     fn carriage_return(&mut self) {
         self.x_pos = BORDER_PADDING;
     }
@@ -102,7 +124,7 @@ impl FrameBufferWriter {
                 let new_ypos =
                     self.y_pos + font_constants::CHAR_RASTER_HEIGHT.val() + BORDER_PADDING;
                 if new_ypos >= self.height() {
-                    self.clear();
+                    self.scroll();
                 }
                 self.write_rendered_char(get_char_raster(c));
             }
@@ -123,8 +145,9 @@ impl FrameBufferWriter {
     fn write_pixel(&mut self, x: usize, y: usize, intensity: u8) {
         let pixel_offset = y * self.info.stride + x;
         let color = match self.info.pixel_format {
-            PixelFormat::Rgb => [intensity, intensity, intensity / 2, 0],
-            PixelFormat::Bgr => [intensity / 2, intensity, intensity, 0],
+            PixelFormat::Rgb | PixelFormat::Bgr => self.info.encode_color(
+                TEXT_COLOR.map(|c| (u16::from(c) * u16::from(intensity) / 0xff) as u8),
+            ),
             PixelFormat::U8 => [if intensity > 200 { 0xf } else { 0 }, 0, 0, 0],
             other => {
                 // set a supported (but invalid) pixel format before panicking to avoid a double
@@ -152,3 +175,45 @@ impl fmt::Write for FrameBufferWriter {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::fmt::Write;
+
+    #[test]
+    fn test_scroll() {
+        const LINES: usize = 3;
+        let info = FrameBufferInfo {
+            byte_len: 0,
+            width: 64,
+            height: LINES * font_constants::LINE_HEIGHT + 2 * BORDER_PADDING,
+            pixel_format: PixelFormat::Rgb,
+            bytes_per_pixel: 3,
+            stride: 70,
+        };
+        let row_len = info.stride * info.bytes_per_pixel;
+        let line_len = font_constants::LINE_HEIGHT * row_len;
+        let framebuffer = Box::leak(vec![0; info.height * row_len].into_boxed_slice());
+        let mut writer = FrameBufferWriter::new(framebuffer, info);
+        let line = |writer: &FrameBufferWriter, line: usize| {
+            writer.framebuffer[line * line_len..(line + 1) * line_len].to_vec()
+        };
+
+        writeln!(writer, "a").unwrap();
+        let first_line = line(&writer, 0);
+        assert!(first_line.iter().any(|&b| b != 0));
+        writeln!(writer, "b").unwrap();
+        writeln!(writer, "c").unwrap();
+        let last_line = line(&writer, 2);
+
+        // the fourth line doesn't fit, so all lines move up by one
+        write!(writer, "d").unwrap();
+        assert_ne!(line(&writer, 0), first_line);
+        assert_eq!(line(&writer, 1), last_line);
+        assert_eq!(
+            writer.y_pos,
+            BORDER_PADDING + 2 * font_constants::LINE_HEIGHT
+        );
+    }
+}
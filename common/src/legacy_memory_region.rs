@@ -1,3 +1,4 @@
+use crate::load_kernel::KernelFrames;
 use bootloader_api::info::{MemoryRegion, MemoryRegionKind};
 use core::{
     cmp,
@@ -6,13 +7,13 @@ use core::{
 };
 use x86_64::{
     align_down, align_up,
-    structures::paging::{FrameAllocator, PhysFrame, Size4KiB},
+    structures::paging::{FrameAllocator, PageSize, PhysFrame, Size4KiB},
     PhysAddr,
 };
 
 /// A slice of memory that is used by the bootloader and needs to be reserved
 /// in the kernel
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct UsedMemorySlice {
     /// the physical start of the slice
     pub start: u64,
@@ -45,19 +46,39 @@ pub trait LegacyMemoryRegion: Copy + core::fmt::Debug {
 
     /// Some regions become usable when the bootloader jumps to the kernel.
     fn usable_after_bootloader_exit(&self) -> bool;
+    /// Returns the firmware-specific attributes of the region.
+    ///
+    /// Defaults to `0`, i.e. no attributes.
+    fn attributes(&self) -> u64 {
+        0
+    }
 }
 
 /// A physical frame allocator based on a BIOS or UEFI provided memory map.
+///
+/// The allocated frames are not zeroed, they might still contain data left by the firmware.
+/// Zeroing them here would require access to the frames, which the allocator doesn't have,
+/// so callers are responsible for initializing the frames before use:
+///
+/// - Intermediate page tables created by the `x86_64` mappers are zeroed by the mappers.
+/// - The level 4 tables of both bootloaders, the `.bss` parts of the kernel segments, the
+///   kernel stacks, the GDT frame, the boot info, and the framebuffer back buffer and DMA
+///   buffer are zeroed by the bootloader.
+/// - The copied pages of the kernel segments are fully overwritten.
 pub struct LegacyFrameAllocator<I, D> {
     original: I,
     memory_map: I,
     current_descriptor: Option<D>,
+    start_frame: PhysFrame,
     next_frame: PhysFrame,
     min_frame: PhysFrame,
+    allocated_frames: u64,
 }
 
 /// Start address of the first frame that is not part of the lower 1MB of frames
 const LOWER_MEMORY_END_PAGE: u64 = 0x10_0000;
+/// Number of initial frame allocations that are logged at trace level.
+const TRACED_ALLOCATIONS: u64 = 8;
 
 impl<I, D> LegacyFrameAllocator<I, D>
 where
@@ -70,34 +91,62 @@ where
     /// identity-mapping the frame at address zero is not valid in Rust, because Rust's `core`
     /// library assumes that references can never point to virtual address `0`.  
     /// Also skips the lower 1MB of frames, there are use cases that require lower conventional memory access (Such as SMP SIPI).
+    /// The 1MB limit can be changed through [`Self::set_min_addr`].
     pub fn new(memory_map: I) -> Self {
         // skip frame 0 because the rust core library does not see 0 as a valid address
-        // Also skip at least the lower 1MB of frames, there are use cases that require lower conventional memory access (Such as SMP SIPI).
-        let start_frame = PhysFrame::containing_address(PhysAddr::new(LOWER_MEMORY_END_PAGE));
+        let start_frame = PhysFrame::containing_address(PhysAddr::new(Size4KiB::SIZE));
         Self::new_starting_at(start_frame, memory_map)
     }
 
     /// Creates a new frame allocator based on the given legacy memory regions. Skips any frames
     /// before the given `frame` or `0x10000`(1MB) whichever is higher, there are use cases that require
     /// lower conventional memory access (Such as SMP SIPI).
+    /// The 1MB limit can be changed through [`Self::set_min_addr`].
     pub fn new_starting_at(frame: PhysFrame, memory_map: I) -> Self {
-        // skip frame 0 because the rust core library does not see 0 as a valid address
-        // Also skip at least the lower 1MB of frames, there are use cases that require lower conventional memory access (Such as SMP SIPI).
-        let lower_mem_end = PhysFrame::containing_address(PhysAddr::new(LOWER_MEMORY_END_PAGE));
-        let frame = core::cmp::max(frame, lower_mem_end);
-        Self {
+        let mut allocator = Self {
             original: memory_map.clone(),
             memory_map,
             current_descriptor: None,
+            start_frame: frame,
             next_frame: frame,
             min_frame: frame,
-        }
+            allocated_frames: 0,
+        };
+        // Skip at least the lower 1MB of frames, there are use cases that require lower conventional memory access (Such as SMP SIPI).
+        allocator.set_min_addr(PhysAddr::new(LOWER_MEMORY_END_PAGE));
+        allocator
+    }
+
+    /// Sets the physical address below which no frames are allocated.
+    ///
+    /// The frame at physical address zero and the frames before the start frame passed to
+    /// [`Self::new_starting_at`] are skipped regardless of this address. Frames below the
+    /// address stay usable in the memory map constructed by [`Self::construct_memory_map`],
+    /// since the bootloader never touches them.
+    ///
+    /// Panics if frames were already allocated.
+    pub fn set_min_addr(&mut self, min_addr: PhysAddr) {
+        assert_eq!(
+            self.allocated_frames, 0,
+            "minimum address must be set before allocating frames"
+        );
+        let min_frame = PhysFrame::containing_address(min_addr.align_up(Size4KiB::SIZE));
+        // skip frame 0 because the rust core library does not see 0 as a valid address
+        let first_frame = PhysFrame::containing_address(PhysAddr::new(Size4KiB::SIZE));
+        let frame = self.start_frame.max(min_frame).max(first_frame);
+        self.next_frame = frame;
+        self.min_frame = frame;
     }
 
     fn allocate_frame_from_descriptor(&mut self, descriptor: D) -> Option<PhysFrame> {
-        let start_addr = descriptor.start();
+        // only hand out frames that lie completely inside the descriptor, even if its bounds
+        // are not page aligned
+        let start_addr = descriptor.start().align_up(Size4KiB::SIZE);
+        let end_addr = (descriptor.start() + descriptor.len()).align_down(Size4KiB::SIZE);
+        if end_addr <= start_addr {
+            return None;
+        }
         let start_frame = PhysFrame::containing_address(start_addr);
-        let end_addr = start_addr + descriptor.len();
         let end_frame = PhysFrame::containing_address(end_addr - 1u64);
 
         // increase self.next_frame to start_frame if smaller
@@ -115,6 +164,106 @@ where
         }
     }
 
+    fn next_usable_frame(&mut self) -> Option<PhysFrame> {
+        if let Some(current_descriptor) = self.current_descriptor {
+            match self.allocate_frame_from_descriptor(current_descriptor) {
+                Some(frame) => return Some(frame),
+                None => {
+                    self.current_descriptor = None;
+                }
+            }
+        }
+
+        // find next suitable descriptor
+        while let Some(descriptor) = self.memory_map.next() {
+            if descriptor.kind() != MemoryRegionKind::Usable {
+                continue;
+            }
+            if let Some(frame) = self.allocate_frame_from_descriptor(descriptor) {
+                self.current_descriptor = Some(descriptor);
+                return Some(frame);
+            }
+        }
+
+        None
+    }
+
+    /// Returns the number of frames that were handed out by this allocator so far.
+    pub fn allocated_frames(&self) -> u64 {
+        self.allocated_frames
+    }
+
+    /// Allocates `count` physically contiguous frames that end at or below `max_addr`.
+    ///
+    /// Returns the first frame of the allocation. Since this is a bump allocator, frames that
+    /// are skipped because they are not contiguous are not reused, but they are still reported
+    /// as used by the bootloader in the memory map. If no suitable frames are found, the
+    /// allocator is reset to its previous state, so that the failed allocation doesn't use
+    /// up any memory.
+    pub fn allocate_contiguous_frames(
+        &mut self,
+        count: u64,
+        max_addr: PhysAddr,
+    ) -> Option<PhysFrame> {
+        self.allocate_contiguous_frames_where(count, max_addr, |_| true)
+    }
+
+    /// Like [`Self::allocate_contiguous_frames`], but only uses frames for which `suitable`
+    /// returns `true`.
+    ///
+    /// Unsuitable frames are skipped like frames that are not contiguous.
+    pub fn allocate_contiguous_frames_where(
+        &mut self,
+        count: u64,
+        max_addr: PhysAddr,
+        suitable: impl FnMut(PhysFrame) -> bool,
+    ) -> Option<PhysFrame> {
+        let memory_map = self.memory_map.clone();
+        let current_descriptor = self.current_descriptor;
+        let next_frame = self.next_frame;
+        let allocated_frames = self.allocated_frames;
+
+        let frames = self.find_contiguous_frames(count, max_addr, suitable);
+        if frames.is_none() {
+            self.memory_map = memory_map;
+            self.current_descriptor = current_descriptor;
+            self.next_frame = next_frame;
+            self.allocated_frames = allocated_frames;
+        }
+        frames
+    }
+
+    fn find_contiguous_frames(
+        &mut self,
+        count: u64,
+        max_addr: PhysAddr,
+        mut suitable: impl FnMut(PhysFrame) -> bool,
+    ) -> Option<PhysFrame> {
+        let mut next_frame = || loop {
+            let frame = self.next_usable_frame()?;
+            self.allocated_frames += 1;
+            if frame.start_address() >= max_addr {
+                return None;
+            }
+            if suitable(frame) {
+                return Some(frame);
+            }
+        };
+        let mut start = next_frame()?;
+        let mut end = start;
+        while end - start + 1 < count {
+            let frame = next_frame()?;
+            if frame != end + 1 {
+                start = frame;
+            }
+            end = frame;
+        }
+        if (end + 1).start_address() > max_addr {
+            return None;
+        }
+        Some(start)
+    }
+
     /// Returns the number of memory regions in the underlying memory map.
     ///
     /// The function always returns the same value, i.e. the length doesn't
@@ -128,6 +277,15 @@ where
         self.len() == 0
     }
 
+    /// Returns the combined size of all usable memory regions in bytes.
+    pub fn usable_memory_len(&self) -> u64 {
+        self.original
+            .clone()
+            .filter(|r| r.kind() == MemoryRegionKind::Usable)
+            .map(|r| r.len())
+            .sum()
+    }
+
     /// Returns the largest detected physical memory address.
     ///
     /// Useful for creating a mapping for all physical memory.
@@ -149,40 +307,52 @@ where
     pub fn memory_map_max_region_count(&self) -> usize {
         // every used region can split an original region into 3 new regions,
         // this means we need to reserve 2 extra spaces for each region.
-        // There are 3 used regions: kernel, ramdisk and the bootloader heap
-        self.len() + 6
+        // The used regions are the kernel ELF file, the ranges of frames
+        // allocated for the kernel, ramdisk, the DMA buffer, the frames
+        // allocated by the bootloader and the bootloader stack
+        self.len() + 2 * (5 + KernelFrames::MAX_RANGES)
     }
 
     /// Converts this type to a boot info memory map.
     ///
     /// The memory map is placed in the given `regions` slice. The length of the given slice
-    /// must be at least the value returned by [`len`] plus 1.
+    /// must be at least the value returned by [`memory_map_max_region_count`], which is
+    /// derived from the number of descriptors in the firmware memory map. This function
+    /// panics instead of truncating the memory map if `regions` is too small.
+    ///
+    /// [`memory_map_max_region_count`]: Self::memory_map_max_region_count
     ///
     /// The return slice is a subslice of `regions`, shortened to the actual number of regions.
-    pub fn construct_memory_map(
+    /// The regions are sorted by start address and adjacent regions of the same kind and
+    /// with the same attributes are merged.
+    pub fn construct_memory_map<'a>(
         self,
-        regions: &mut [MaybeUninit<MemoryRegion>],
-        kernel_slice_start: PhysAddr,
-        kernel_slice_len: u64,
+        regions: &'a mut [MaybeUninit<MemoryRegion>],
+        kernel_slices: &[UsedMemorySlice],
         ramdisk_slice_start: Option<PhysAddr>,
         ramdisk_slice_len: u64,
-    ) -> &mut [MemoryRegion] {
-        let used_slices = [
-            UsedMemorySlice {
-                start: self.min_frame.start_address().as_u64(),
-                end: self.next_frame.start_address().as_u64(),
-            },
-            UsedMemorySlice::new_from_len(kernel_slice_start.as_u64(), kernel_slice_len),
-        ]
-        .into_iter()
-        .chain(
-            ramdisk_slice_start
-                .map(|start| UsedMemorySlice::new_from_len(start.as_u64(), ramdisk_slice_len)),
-        )
-        .map(|slice| UsedMemorySlice {
+        stack_slice: Option<UsedMemorySlice>,
+        dma_buffer_slice: Option<UsedMemorySlice>,
+    ) -> &'a mut [MemoryRegion] {
+        let page_aligned = |slice: UsedMemorySlice| UsedMemorySlice {
             start: align_down(slice.start, 0x1000),
             end: align_up(slice.end, 0x1000),
-        });
+        };
+        let kernel_slices = kernel_slices
+            .iter()
+            .copied()
+            .chain(
+                ramdisk_slice_start
+                    .map(|start| UsedMemorySlice::new_from_len(start.as_u64(), ramdisk_slice_len)),
+            )
+            .map(page_aligned);
+        let used_slices = [UsedMemorySlice {
+            start: self.min_frame.start_address().as_u64(),
+            end: self.next_frame.start_address().as_u64(),
+        }]
+        .into_iter()
+        .chain(stack_slice)
+        .map(page_aligned);
 
         let mut next_index = 0;
         for descriptor in self.original {
@@ -203,31 +373,115 @@ where
                 start: descriptor.start().as_u64(),
                 end: end.as_u64(),
                 kind,
+                attributes: descriptor.attributes(),
             };
-            if region.kind == MemoryRegionKind::Usable {
-                Self::split_and_add_region(region, regions, &mut next_index, used_slices.clone());
-            } else {
-                Self::add_region(region, regions, &mut next_index);
-            }
+            // The kernel and ramdisk slices take precedence over the other used slices, which
+            // include the frames allocated by the bootloader and thus those used for the kernel
+            // and the DMA buffer.
+            Self::split_region(
+                region,
+                kernel_slices.clone(),
+                MemoryRegionKind::KernelAndModules,
+                &mut |region| {
+                    Self::split_region(
+                        region,
+                        dma_buffer_slice.into_iter().map(page_aligned),
+                        MemoryRegionKind::DmaBuffer,
+                        &mut |region| {
+                            Self::split_region(
+                                region,
+                                used_slices.clone(),
+                                MemoryRegionKind::Bootloader,
+                                &mut |region| Self::add_region(region, regions, &mut next_index),
+                            )
+                        },
+                    )
+                },
+            );
         }
 
         let initialized = &mut regions[..next_index];
-        unsafe {
+        let initialized: &mut [MemoryRegion] = unsafe {
             // inlined variant of: `MaybeUninit::slice_assume_init_mut(initialized)`
             // TODO: undo inlining when `slice_assume_init_mut` becomes stable
             &mut *(initialized as *mut [_] as *mut [_])
+        };
+
+        // firmware memory maps are often heavily fragmented, so merge adjacent regions
+        initialized.sort_unstable_by_key(|region| region.start);
+        let merged_len = Self::merge_adjacent_regions(initialized);
+        &mut initialized[..merged_len]
+    }
+
+    /// Merges adjacent regions of the same kind and with the same attributes in the given
+    /// slice, which must be sorted by start address.
+    ///
+    /// Overlapping regions, which are only created by broken firmware memory maps, are
+    /// merged too if they are of the same kind. Otherwise, a [`Usable`][MemoryRegionKind::Usable]
+    /// region is cut off where the other region starts, or, if neither is usable, the region
+    /// that starts later is cut off where the other one ends. This errs on the side of
+    /// reporting less usable memory. Regions that become empty are dropped.
+    ///
+    /// The merged regions are moved to the front of the slice. Returns their number.
+    fn merge_adjacent_regions(regions: &mut [MemoryRegion]) -> usize {
+        let mut merged_len = 0usize;
+        for i in 0..regions.len() {
+            let mut region = regions[i];
+            while let Some(last) = merged_len.checked_sub(1).map(|last| &mut regions[last]) {
+                if region.start >= last.end
+                    || (last.kind == region.kind && last.attributes == region.attributes)
+                {
+                    break;
+                }
+                log::warn!("Overlapping memory regions {last:x?} and {region:x?}");
+                if last.kind == MemoryRegionKind::Usable {
+                    last.end = region.start;
+                    if last.start == last.end {
+                        // check the overlap with the region before it again
+                        merged_len -= 1;
+                        continue;
+                    }
+                } else {
+                    region.start = cmp::min(last.end, region.end);
+                }
+                break;
+            }
+            if region.start == region.end {
+                continue;
+            }
+
+            match merged_len.checked_sub(1).map(|last| &mut regions[last]) {
+                Some(last)
+                    if last.end >= region.start
+                        && last.kind == region.kind
+                        && last.attributes == region.attributes =>
+                {
+                    last.end = cmp::max(last.end, region.end);
+                }
+                _ => {
+                    regions[merged_len] = region;
+                    merged_len += 1;
+                }
+            }
         }
+        merged_len
     }
 
-    fn split_and_add_region<'a, U>(
+    /// Splits the given usable region into the parts that overlap one of the `used_slices`,
+    /// which are reported as `used_kind`, and the remaining usable parts.
+    fn split_region<U>(
         mut region: MemoryRegion,
-        regions: &mut [MaybeUninit<MemoryRegion>],
-        next_index: &mut usize,
         used_slices: U,
+        used_kind: MemoryRegionKind,
+        add_region: &mut impl FnMut(MemoryRegion),
     ) where
         U: Iterator<Item = UsedMemorySlice> + Clone,
     {
-        assert!(region.kind == MemoryRegionKind::Usable);
+        // Only usable memory is split, all other regions are added unchanged.
+        if region.kind != MemoryRegionKind::Usable {
+            add_region(region);
+            return;
+        }
         // Each loop iteration takes a chunk of `region` and adds it to
         // `regions`. Do this until `region` is empty.
         while region.start != region.end {
@@ -259,19 +513,21 @@ where
                     start: region.start,
                     end: overlap_start,
                     kind: MemoryRegionKind::Usable,
+                    attributes: region.attributes,
                 };
-                let bootloader = MemoryRegion {
+                let used = MemoryRegion {
                     start: overlap_start,
                     end: overlap_end,
-                    kind: MemoryRegionKind::Bootloader,
+                    kind: used_kind,
+                    attributes: region.attributes,
                 };
-                Self::add_region(usable, regions, next_index);
-                Self::add_region(bootloader, regions, next_index);
+                add_region(usable);
+                add_region(used);
                 // Continue after the overlapped region.
                 region.start = overlap_end;
             } else {
                 // There's no overlap. We can add the whole region.
-                Self::add_region(region, regions, next_index);
+                add_region(region);
                 break;
             }
         }
@@ -303,27 +559,21 @@ where
     I::Item: LegacyMemoryRegion,
 {
     fn allocate_frame(&mut self) -> Option<PhysFrame<Size4KiB>> {
-        if let Some(current_descriptor) = self.current_descriptor {
-            match self.allocate_frame_from_descriptor(current_descriptor) {
-                Some(frame) => return Some(frame),
-                None => {
-                    self.current_descriptor = None;
+        let frame = self.next_usable_frame();
+        match frame {
+            Some(frame) => {
+                // makes it easy to compare the physical layout of different boots
+                if self.allocated_frames < TRACED_ALLOCATIONS {
+                    log::trace!("Allocated frame {:#x}", frame.start_address());
                 }
+                self.allocated_frames += 1
             }
+            None => log::error!(
+                "Out of physical memory after allocating {} frames",
+                self.allocated_frames
+            ),
         }
-
-        // find next suitable descriptor
-        while let Some(descriptor) = self.memory_map.next() {
-            if descriptor.kind() != MemoryRegionKind::Usable {
-                continue;
-            }
-            if let Some(frame) = self.allocate_frame_from_descriptor(descriptor) {
-                self.current_descriptor = Some(descriptor);
-                return Some(frame);
-            }
-        }
-
-        None
+        frame
     }
 }
 
@@ -386,10 +636,14 @@ mod tests {
 
         let kernel_regions = allocator.construct_memory_map(
             &mut regions,
-            kernel_slice_start,
-            kernel_slice_len,
+            &[UsedMemorySlice::new_from_len(
+                kernel_slice_start.as_u64(),
+                kernel_slice_len,
+            )],
             ramdisk_slice_start,
             ramdisk_slice_len,
+            None,
+            None,
         );
 
         for region in kernel_regions.iter() {
@@ -413,10 +667,14 @@ mod tests {
 
         let kernel_regions = allocator.construct_memory_map(
             &mut regions,
-            kernel_slice_start,
-            kernel_slice_len,
+            &[UsedMemorySlice::new_from_len(
+                kernel_slice_start.as_u64(),
+                kernel_slice_len,
+            )],
             ramdisk_slice_start,
             ramdisk_slice_len,
+            None,
+            None,
         );
         let mut kernel_regions = kernel_regions.iter();
         // usable memory before the kernel
@@ -425,7 +683,8 @@ mod tests {
             Some(&MemoryRegion {
                 start: 0x0000,
                 end: 0x50000,
-                kind: MemoryRegionKind::Usable
+                kind: MemoryRegionKind::Usable,
+                attributes: 0,
             })
         );
         // kernel
@@ -434,7 +693,8 @@ mod tests {
             Some(&MemoryRegion {
                 start: 0x50000,
                 end: 0x51000,
-                kind: MemoryRegionKind::Bootloader
+                kind: MemoryRegionKind::KernelAndModules,
+                attributes: 0,
             })
         );
         // usabel memory between kernel and ramdisk
@@ -443,7 +703,8 @@ mod tests {
             Some(&MemoryRegion {
                 start: 0x51000,
                 end: 0x60000,
-                kind: MemoryRegionKind::Usable
+                kind: MemoryRegionKind::Usable,
+                attributes: 0,
             })
         );
         // ramdisk
@@ -452,7 +713,8 @@ mod tests {
             Some(&MemoryRegion {
                 start: 0x60000,
                 end: 0x62000,
-                kind: MemoryRegionKind::Bootloader
+                kind: MemoryRegionKind::KernelAndModules,
+                attributes: 0,
             })
         );
         // usabele memory after ramdisk, up until bootloader allocated memory
@@ -461,7 +723,8 @@ mod tests {
             Some(&MemoryRegion {
                 start: 0x62000,
                 end: 0x10_0000,
-                kind: MemoryRegionKind::Usable
+                kind: MemoryRegionKind::Usable,
+                attributes: 0,
             })
         );
         // bootloader allocated memory
@@ -470,7 +733,8 @@ mod tests {
             Some(&MemoryRegion {
                 start: 0x10_0000,
                 end: 0x10_1000,
-                kind: MemoryRegionKind::Bootloader
+                kind: MemoryRegionKind::Bootloader,
+                attributes: 0,
             })
         );
         // rest is free
@@ -479,7 +743,8 @@ mod tests {
             Some(&MemoryRegion {
                 start: 0x10_1000,
                 end: MAX_PHYS_ADDR,
-                kind: MemoryRegionKind::Usable
+                kind: MemoryRegionKind::Usable,
+                attributes: 0,
             })
         );
         assert_eq!(kernel_regions.next(), None);
@@ -516,10 +781,14 @@ mod tests {
 
         let kernel_regions = allocator.construct_memory_map(
             &mut regions,
-            kernel_slice_start,
-            kernel_slice_len,
+            &[UsedMemorySlice::new_from_len(
+                kernel_slice_start.as_u64(),
+                kernel_slice_len,
+            )],
             ramdisk_slice_start,
             ramdisk_slice_len,
+            None,
+            None,
         );
         let mut kernel_regions = kernel_regions.iter();
 
@@ -529,7 +798,8 @@ mod tests {
             Some(&MemoryRegion {
                 start: 0x0000,
                 end: 0x50000,
-                kind: MemoryRegionKind::Usable
+                kind: MemoryRegionKind::Usable,
+                attributes: 0,
             })
         );
         // kernel
@@ -538,7 +808,8 @@ mod tests {
             Some(&MemoryRegion {
                 start: 0x50000,
                 end: 0x51000,
-                kind: MemoryRegionKind::Bootloader
+                kind: MemoryRegionKind::KernelAndModules,
+                attributes: 0,
             })
         );
         // usabel memory between kernel and ramdisk
@@ -547,7 +818,8 @@ mod tests {
             Some(&MemoryRegion {
                 start: 0x51000,
                 end: 0x60000,
-                kind: MemoryRegionKind::Usable
+                kind: MemoryRegionKind::Usable,
+                attributes: 0,
             })
         );
         // ramdisk
@@ -556,7 +828,8 @@ mod tests {
             Some(&MemoryRegion {
                 start: 0x60000,
                 end: 0x62000,
-                kind: MemoryRegionKind::Bootloader
+                kind: MemoryRegionKind::KernelAndModules,
+                attributes: 0,
             })
         );
         // usabele memory after ramdisk, up until bootloader allocated memory
@@ -565,7 +838,8 @@ mod tests {
             Some(&MemoryRegion {
                 start: 0x62000,
                 end: 0x10_0000,
-                kind: MemoryRegionKind::Usable
+                kind: MemoryRegionKind::Usable,
+                attributes: 0,
             })
         );
         // the unknown bios region
@@ -574,7 +848,8 @@ mod tests {
             Some(&MemoryRegion {
                 start: 0x10_0000,
                 end: 0x10_5000,
-                kind: MemoryRegionKind::UnknownBios(0)
+                kind: MemoryRegionKind::UnknownBios(0),
+                attributes: 0,
             })
         );
         // bootloader allocated memory, this gets pushed back by the bios region
@@ -583,7 +858,8 @@ mod tests {
             Some(&MemoryRegion {
                 start: 0x10_5000,
                 end: 0x10_6000,
-                kind: MemoryRegionKind::Bootloader
+                kind: MemoryRegionKind::Bootloader,
+                attributes: 0,
             })
         );
         // rest is free
@@ -592,9 +868,732 @@ mod tests {
             Some(&MemoryRegion {
                 start: 0x10_6000,
                 end: MAX_PHYS_ADDR,
-                kind: MemoryRegionKind::Usable
+                kind: MemoryRegionKind::Usable,
+                attributes: 0,
             })
         );
         assert_eq!(kernel_regions.next(), None);
     }
+
+    #[test]
+    fn test_stack_slice_reserved() {
+        let regions = create_single_test_region();
+        let mut allocator = LegacyFrameAllocator::new(regions.into_iter());
+        // allocate at least 1 frame
+        allocator.allocate_frame();
+
+        let mut regions = [MaybeUninit::uninit(); 10];
+        let kernel_slice_start = PhysAddr::new(0x50000);
+        let kernel_slice_len = 0x1000;
+        let stack_slice = Some(UsedMemorySlice::new_from_len(0x70800, 0x1000));
+
+        let kernel_regions = allocator.construct_memory_map(
+            &mut regions,
+            &[UsedMemorySlice::new_from_len(
+                kernel_slice_start.as_u64(),
+                kernel_slice_len,
+            )],
+            None,
+            0,
+            stack_slice,
+            None,
+        );
+
+        // the stack slice is page aligned and marked as used by the bootloader
+        assert!(kernel_regions.contains(&MemoryRegion {
+            start: 0x70000,
+            end: 0x72000,
+            kind: MemoryRegionKind::Bootloader,
+            attributes: 0,
+        }));
+        assert!(kernel_regions.contains(&MemoryRegion {
+            start: 0x51000,
+            end: 0x70000,
+            kind: MemoryRegionKind::Usable,
+            attributes: 0,
+        }));
+        assert!(kernel_regions.contains(&MemoryRegion {
+            start: 0x72000,
+            end: 0x10_0000,
+            kind: MemoryRegionKind::Usable,
+            attributes: 0,
+        }));
+    }
+
+    #[test]
+    fn test_allocate_contiguous_frames() {
+        let regions = vec![
+            TestMemoryRegion {
+                start: PhysAddr::new(0x10_0000),
+                len: 0x2000,
+                kind: MemoryRegionKind::Usable,
+            },
+            TestMemoryRegion {
+                start: PhysAddr::new(0x10_2000),
+                len: 0x1000,
+                kind: MemoryRegionKind::UnknownBios(0),
+            },
+            TestMemoryRegion {
+                start: PhysAddr::new(0x10_3000),
+                len: 0x4000,
+                kind: MemoryRegionKind::Usable,
+            },
+        ];
+        let mut allocator = LegacyFrameAllocator::new(regions.into_iter());
+
+        // the first region is too small, so the frames are taken from the second one
+        let start = allocator.allocate_contiguous_frames(3, PhysAddr::new(0x1_0000_0000));
+        assert_eq!(start.map(|f| f.start_address().as_u64()), Some(0x10_3000));
+        // not enough frames left
+        let start = allocator.allocate_contiguous_frames(2, PhysAddr::new(0x1_0000_0000));
+        assert_eq!(start, None);
+        // the failed allocation doesn't use up the remaining frame
+        let frame = allocator.allocate_frame();
+        assert_eq!(frame.map(|f| f.start_address().as_u64()), Some(0x10_6000));
+        // the two skipped frames of the first region are counted too
+        assert_eq!(allocator.allocated_frames(), 6);
+    }
+
+    #[test]
+    fn test_bootloader_regions_cover_allocated_frames() {
+        let regions = vec![
+            // starts below the lower 1MiB boundary of the allocator
+            TestMemoryRegion {
+                start: PhysAddr::new(0),
+                len: 0x10_2000,
+                kind: MemoryRegionKind::Usable,
+            },
+            TestMemoryRegion {
+                start: PhysAddr::new(0x10_2000),
+                len: 0x2000,
+                kind: MemoryRegionKind::UnknownBios(0),
+            },
+            TestMemoryRegion {
+                start: PhysAddr::new(0x10_4000),
+                len: MAX_PHYS_ADDR - 0x10_4000,
+                kind: MemoryRegionKind::Usable,
+            },
+        ];
+        let mut allocator = LegacyFrameAllocator::new(regions.into_iter());
+        // the allocation skips over the unknown region in the middle
+        let frames: Vec<_> = (0..5)
+            .map(|_| allocator.allocate_frame().unwrap())
+            .collect();
+        assert_eq!(frames[1].start_address().as_u64(), 0x10_1000);
+        assert_eq!(frames[2].start_address().as_u64(), 0x10_4000);
+
+        let mut regions = vec![MaybeUninit::uninit(); allocator.memory_map_max_region_count()];
+        let kernel_slice_start = PhysAddr::new(0x50000);
+        let kernel_slice_len = 0x1000;
+
+        let kernel_regions = allocator.construct_memory_map(
+            &mut regions,
+            &[UsedMemorySlice::new_from_len(
+                kernel_slice_start.as_u64(),
+                kernel_slice_len,
+            )],
+            None,
+            0,
+            None,
+            None,
+        );
+
+        // only the allocated frames are reported as used by the bootloader
+        let bootloader_len: u64 = kernel_regions
+            .iter()
+            .filter(|r| r.kind == MemoryRegionKind::Bootloader)
+            .map(|r| r.end - r.start)
+            .sum();
+        assert_eq!(bootloader_len, 5 * 0x1000);
+
+        for frame in frames {
+            let start = frame.start_address().as_u64();
+            let end = start + 0x1000;
+            assert!(
+                kernel_regions
+                    .iter()
+                    .any(|r| r.kind == MemoryRegionKind::Bootloader
+                        && r.start <= start
+                        && end <= r.end),
+                "frame {start:#x} is not covered by a bootloader region"
+            );
+            assert!(
+                !kernel_regions
+                    .iter()
+                    .any(|r| r.kind == MemoryRegionKind::Usable && r.start < end && start < r.end),
+                "frame {start:#x} is reported as usable"
+            );
+        }
+    }
+
+    #[test]
+    fn test_adjacent_regions_merged() {
+        let regions = vec![
+            TestMemoryRegion {
+                start: PhysAddr::new(0),
+                len: 0x8_0000,
+                kind: MemoryRegionKind::Usable,
+            },
+            TestMemoryRegion {
+                start: PhysAddr::new(0x8_0000),
+                len: 0x8_0000,
+                kind: MemoryRegionKind::Usable,
+            },
+            TestMemoryRegion {
+                start: PhysAddr::new(0x10_0000),
+                len: 0x1000,
+                kind: MemoryRegionKind::UnknownBios(1),
+            },
+            TestMemoryRegion {
+                start: PhysAddr::new(0x10_1000),
+                len: 0x1000,
+                kind: MemoryRegionKind::UnknownBios(1),
+            },
+            TestMemoryRegion {
+                start: PhysAddr::new(0x10_2000),
+                len: 0x1000,
+                kind: MemoryRegionKind::UnknownBios(2),
+            },
+            TestMemoryRegion {
+                start: PhysAddr::new(0x10_3000),
+                len: 0x1000,
+                kind: MemoryRegionKind::Usable,
+            },
+            TestMemoryRegion {
+                start: PhysAddr::new(0x10_4000),
+                len: MAX_PHYS_ADDR - 0x10_4000,
+                kind: MemoryRegionKind::Usable,
+            },
+        ];
+        let mut allocator = LegacyFrameAllocator::new(regions.into_iter());
+        allocator.allocate_frame();
+
+        let mut regions = vec![MaybeUninit::uninit(); allocator.memory_map_max_region_count()];
+        let kernel_regions = allocator.construct_memory_map(
+            &mut regions,
+            &[UsedMemorySlice::new_from_len(0x50000, 0x1000)],
+            None,
+            0,
+            None,
+            None,
+        );
+
+        assert_eq!(
+            kernel_regions,
+            [
+                MemoryRegion {
+                    start: 0,
+                    end: 0x50000,
+                    kind: MemoryRegionKind::Usable,
+                    attributes: 0,
+                },
+                MemoryRegion {
+                    start: 0x50000,
+                    end: 0x51000,
+                    kind: MemoryRegionKind::KernelAndModules,
+                    attributes: 0,
+                },
+                // the two usable descriptors are merged
+                MemoryRegion {
+                    start: 0x51000,
+                    end: 0x10_0000,
+                    kind: MemoryRegionKind::Usable,
+                    attributes: 0,
+                },
+                MemoryRegion {
+                    start: 0x10_0000,
+                    end: 0x10_2000,
+                    kind: MemoryRegionKind::UnknownBios(1),
+                    attributes: 0,
+                },
+                MemoryRegion {
+                    start: 0x10_2000,
+                    end: 0x10_3000,
+                    kind: MemoryRegionKind::UnknownBios(2),
+                    attributes: 0,
+                },
+                // the allocated frame is never merged with usable memory
+                MemoryRegion {
+                    start: 0x10_3000,
+                    end: 0x10_4000,
+                    kind: MemoryRegionKind::Bootloader,
+                    attributes: 0,
+                },
+                MemoryRegion {
+                    start: 0x10_4000,
+                    end: MAX_PHYS_ADDR,
+                    kind: MemoryRegionKind::Usable,
+                    attributes: 0,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_regions_sorted() {
+        // firmware doesn't guarantee that the descriptors are sorted
+        let regions = vec![
+            TestMemoryRegion {
+                start: PhysAddr::new(0x10_0000),
+                len: MAX_PHYS_ADDR - 0x10_0000,
+                kind: MemoryRegionKind::Usable,
+            },
+            TestMemoryRegion {
+                start: PhysAddr::new(0x8_0000),
+                len: 0x8_0000,
+                kind: MemoryRegionKind::UnknownBios(1),
+            },
+            TestMemoryRegion {
+                start: PhysAddr::new(0),
+                len: 0x8_0000,
+                kind: MemoryRegionKind::Usable,
+            },
+        ];
+        let mut allocator = LegacyFrameAllocator::new(regions.into_iter());
+        allocator.allocate_frame();
+
+        let mut regions = vec![MaybeUninit::uninit(); allocator.memory_map_max_region_count()];
+        // the kernel splits the last descriptor
+        let kernel_regions = allocator.construct_memory_map(
+            &mut regions,
+            &[UsedMemorySlice::new_from_len(0x50000, 0x1000)],
+            None,
+            0,
+            None,
+            None,
+        );
+
+        assert!(kernel_regions
+            .windows(2)
+            .all(|pair| pair[0].end <= pair[1].start));
+        assert_eq!(kernel_regions.first().map(|r| r.start), Some(0));
+        assert_eq!(kernel_regions.last().map(|r| r.end), Some(MAX_PHYS_ADDR));
+    }
+
+    #[test]
+    fn test_misaligned_descriptor() {
+        let regions = vec![TestMemoryRegion {
+            start: PhysAddr::new(0x10_0800),
+            len: 0x2000,
+            kind: MemoryRegionKind::Usable,
+        }];
+        let mut allocator = LegacyFrameAllocator::new(regions.into_iter());
+
+        // only the frame at 0x10_1000 lies completely inside the descriptor
+        let frame = allocator.allocate_frame().unwrap();
+        assert_eq!(frame.start_address(), PhysAddr::new(0x10_1000));
+        assert_eq!(allocator.allocate_frame(), None);
+        assert_eq!(allocator.allocated_frames(), 1);
+    }
+
+    #[test]
+    fn test_attributes() {
+        const WRITE_BACK: u64 = 0x8;
+        const RUNTIME: u64 = 0x8000_0000_0000_0000;
+
+        #[derive(Copy, Clone, Debug)]
+        struct AttributedRegion(TestMemoryRegion, u64);
+
+        impl LegacyMemoryRegion for AttributedRegion {
+            fn start(&self) -> PhysAddr {
+                self.0.start()
+            }
+
+            fn len(&self) -> u64 {
+                self.0.len()
+            }
+
+            fn kind(&self) -> MemoryRegionKind {
+                self.0.kind()
+            }
+
+            fn usable_after_bootloader_exit(&self) -> bool {
+                self.0.usable_after_bootloader_exit()
+            }
+
+            fn attributes(&self) -> u64 {
+                self.1
+            }
+        }
+
+        let region = |start, len, kind, attributes| {
+            let start = PhysAddr::new(start);
+            AttributedRegion(TestMemoryRegion { start, len, kind }, attributes)
+        };
+        let regions = vec![
+            region(0x10_0000, 0x1_0000, MemoryRegionKind::Usable, WRITE_BACK),
+            region(0x11_0000, 0x1000, MemoryRegionKind::Usable, 0),
+            region(
+                0x11_1000,
+                0x1000,
+                MemoryRegionKind::UnknownUefi(5),
+                RUNTIME | WRITE_BACK,
+            ),
+        ];
+        let mut allocator = LegacyFrameAllocator::new(regions.into_iter());
+        allocator.allocate_frame().unwrap();
+
+        let mut regions = [MaybeUninit::uninit(); 10];
+        let kernel_regions = allocator.construct_memory_map(
+            &mut regions,
+            &[UsedMemorySlice::new_from_len(0x11_1000, 0)],
+            None,
+            0,
+            None,
+            None,
+        );
+        let attributes: Vec<_> = kernel_regions
+            .iter()
+            .map(|r| (r.start, r.kind, r.attributes))
+            .collect();
+        assert_eq!(
+            attributes,
+            [
+                // the frame allocated by the bootloader keeps the attributes of its region
+                (0x10_0000, MemoryRegionKind::Bootloader, WRITE_BACK),
+                (0x10_1000, MemoryRegionKind::Usable, WRITE_BACK),
+                // adjacent regions with different attributes are not merged
+                (0x11_0000, MemoryRegionKind::Usable, 0),
+                (
+                    0x11_1000,
+                    MemoryRegionKind::UnknownUefi(5),
+                    RUNTIME | WRITE_BACK
+                ),
+            ]
+        );
+    }
+
+    /// Asserts that the given memory map is sorted and contains no empty or overlapping
+    /// regions.
+    fn assert_well_formed(regions: &[MemoryRegion]) {
+        assert!(regions.iter().all(|r| r.start < r.end), "empty region");
+        assert!(
+            regions.windows(2).all(|pair| pair[0].end <= pair[1].start),
+            "regions overlap or are not sorted"
+        );
+    }
+
+    #[test]
+    fn test_next_free_on_region_boundary() {
+        let regions = vec![
+            TestMemoryRegion {
+                start: PhysAddr::new(0x10_0000),
+                len: 0x2000,
+                kind: MemoryRegionKind::Usable,
+            },
+            TestMemoryRegion {
+                start: PhysAddr::new(0x20_0000),
+                len: 0x2000,
+                kind: MemoryRegionKind::Usable,
+            },
+        ];
+        let mut allocator = LegacyFrameAllocator::new(regions.into_iter());
+        // use up the first descriptor exactly, so that `next_frame` is its end address
+        allocator.allocate_frame().unwrap();
+        allocator.allocate_frame().unwrap();
+
+        let mut regions = vec![MaybeUninit::uninit(); allocator.memory_map_max_region_count()];
+        let kernel_regions = allocator.construct_memory_map(
+            &mut regions,
+            &[UsedMemorySlice::new_from_len(0x20_1000, 0x1000)],
+            None,
+            0,
+            None,
+            None,
+        );
+
+        assert_well_formed(kernel_regions);
+        let regions: Vec<_> = kernel_regions
+            .iter()
+            .map(|r| (r.start, r.end, r.kind))
+            .collect();
+        assert_eq!(
+            regions,
+            [
+                (0x10_0000, 0x10_2000, MemoryRegionKind::Bootloader),
+                (0x20_0000, 0x20_1000, MemoryRegionKind::Usable),
+                (0x20_1000, 0x20_2000, MemoryRegionKind::KernelAndModules),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_overlapping_descriptors() {
+        let regions = vec![
+            TestMemoryRegion {
+                start: PhysAddr::new(0x10_0000),
+                len: 0x4000,
+                kind: MemoryRegionKind::Usable,
+            },
+            // overlaps the end of the first usable descriptor
+            TestMemoryRegion {
+                start: PhysAddr::new(0x10_3000),
+                len: 0x2000,
+                kind: MemoryRegionKind::UnknownUefi(0),
+            },
+            // overlaps the reserved descriptor and is contained in the next one
+            TestMemoryRegion {
+                start: PhysAddr::new(0x10_4000),
+                len: 0x2000,
+                kind: MemoryRegionKind::Usable,
+            },
+            TestMemoryRegion {
+                start: PhysAddr::new(0x10_4000),
+                len: 0x4000,
+                kind: MemoryRegionKind::Usable,
+            },
+        ];
+        let mut allocator = LegacyFrameAllocator::new(regions.into_iter());
+        allocator.allocate_frame().unwrap();
+
+        let mut regions = vec![MaybeUninit::uninit(); allocator.memory_map_max_region_count()];
+        let kernel_regions = allocator.construct_memory_map(
+            &mut regions,
+            &[UsedMemorySlice::new_from_len(0x10_7000, 0x1000)],
+            None,
+            0,
+            None,
+            None,
+        );
+
+        assert_well_formed(kernel_regions);
+        let regions: Vec<_> = kernel_regions
+            .iter()
+            .map(|r| (r.start, r.end, r.kind))
+            .collect();
+        assert_eq!(
+            regions,
+            [
+                (0x10_0000, 0x10_1000, MemoryRegionKind::Bootloader),
+                (0x10_1000, 0x10_3000, MemoryRegionKind::Usable),
+                (0x10_3000, 0x10_5000, MemoryRegionKind::UnknownUefi(0)),
+                (0x10_5000, 0x10_7000, MemoryRegionKind::Usable),
+                (0x10_7000, 0x10_8000, MemoryRegionKind::KernelAndModules),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_min_addr() {
+        let regions = vec![
+            TestMemoryRegion {
+                start: PhysAddr::new(0),
+                len: 0x40_0000,
+                kind: MemoryRegionKind::Usable,
+            },
+            TestMemoryRegion {
+                start: PhysAddr::new(0x40_0000),
+                len: 0x1000,
+                kind: MemoryRegionKind::UnknownUefi(0),
+            },
+        ];
+
+        // by default, the lower 1MiB is skipped
+        let mut allocator = LegacyFrameAllocator::new(regions.clone().into_iter());
+        let frame = allocator.allocate_frame().unwrap();
+        assert_eq!(frame.start_address(), PhysAddr::new(0x10_0000));
+
+        // the frame at address zero is skipped even without a minimum address
+        let mut allocator = LegacyFrameAllocator::new(regions.clone().into_iter());
+        allocator.set_min_addr(PhysAddr::new(0));
+        let frame = allocator.allocate_frame().unwrap();
+        assert_eq!(frame.start_address(), PhysAddr::new(0x1000));
+
+        let floor = PhysAddr::new(0x20_0000);
+        let mut allocator = LegacyFrameAllocator::new(regions.into_iter());
+        allocator.set_min_addr(floor);
+        for _ in 0..16 {
+            let frame = allocator.allocate_frame().unwrap();
+            assert!(frame.start_address() >= floor);
+        }
+        let frame = allocator
+            .allocate_contiguous_frames(4, PhysAddr::new(0x40_0000))
+            .unwrap();
+        assert!(frame.start_address() >= floor);
+        while let Some(frame) = allocator.allocate_frame() {
+            assert!(frame.start_address() >= floor);
+        }
+
+        // the memory below the floor is reported as usable
+        let mut regions = vec![MaybeUninit::uninit(); allocator.memory_map_max_region_count()];
+        let kernel_regions = allocator.construct_memory_map(
+            &mut regions,
+            &[UsedMemorySlice::new_from_len(0x1000, 0x1000)],
+            None,
+            0,
+            None,
+            None,
+        );
+        assert_well_formed(kernel_regions);
+        let regions: Vec<_> = kernel_regions
+            .iter()
+            .map(|r| (r.start, r.end, r.kind))
+            .collect();
+        assert_eq!(
+            regions,
+            [
+                (0, 0x1000, MemoryRegionKind::Usable),
+                (0x1000, 0x2000, MemoryRegionKind::KernelAndModules),
+                (0x2000, 0x20_0000, MemoryRegionKind::Usable),
+                (0x20_0000, 0x40_0000, MemoryRegionKind::Bootloader),
+                (0x40_0000, 0x40_1000, MemoryRegionKind::UnknownUefi(0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_kernel_frames() {
+        let regions = create_single_test_region();
+        let mut allocator = LegacyFrameAllocator::new(regions.into_iter());
+        // the frames in the middle are allocated while loading the kernel
+        let frames: Vec<_> = (0..6)
+            .map(|_| allocator.allocate_frame().unwrap())
+            .collect();
+        let kernel_frames = UsedMemorySlice {
+            start: frames[2].start_address().as_u64(),
+            end: frames[4].start_address().as_u64(),
+        };
+
+        let mut regions = vec![MaybeUninit::uninit(); allocator.memory_map_max_region_count()];
+        let kernel_regions = allocator.construct_memory_map(
+            &mut regions,
+            &[
+                UsedMemorySlice::new_from_len(0x50000, 0x1800),
+                kernel_frames,
+            ],
+            None,
+            0,
+            None,
+            None,
+        );
+        assert_well_formed(kernel_regions);
+        let regions: Vec<_> = kernel_regions
+            .iter()
+            .map(|r| (r.start, r.end, r.kind))
+            .collect();
+        assert_eq!(
+            regions,
+            [
+                (0, 0x50000, MemoryRegionKind::Usable),
+                (0x50000, 0x52000, MemoryRegionKind::KernelAndModules),
+                (0x52000, 0x10_0000, MemoryRegionKind::Usable),
+                (0x10_0000, 0x10_2000, MemoryRegionKind::Bootloader),
+                (0x10_2000, 0x10_4000, MemoryRegionKind::KernelAndModules),
+                (0x10_4000, 0x10_6000, MemoryRegionKind::Bootloader),
+                (0x10_6000, MAX_PHYS_ADDR, MemoryRegionKind::Usable),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dma_buffer() {
+        let regions = create_single_test_region();
+        let mut allocator = LegacyFrameAllocator::new(regions.into_iter());
+        allocator.allocate_frame().unwrap();
+        // the frames at 0x10_2000 and 0x10_3000 are unsuitable, so the buffer starts after them
+        let start = allocator
+            .allocate_contiguous_frames_where(2, PhysAddr::new(0x1_0000_0000), |frame| {
+                !(0x10_2000..0x10_4000).contains(&frame.start_address().as_u64())
+            })
+            .unwrap();
+        assert_eq!(start.start_address().as_u64(), 0x10_4000);
+        allocator.allocate_frame().unwrap();
+        // there are no suitable frames below the maximum address
+        assert_eq!(
+            allocator.allocate_contiguous_frames_where(1, PhysAddr::new(0x1_0000_0000), |_| false),
+            None
+        );
+
+        let mut regions = vec![MaybeUninit::uninit(); allocator.memory_map_max_region_count()];
+        let kernel_regions = allocator.construct_memory_map(
+            &mut regions,
+            &[],
+            None,
+            0,
+            None,
+            Some(UsedMemorySlice::new_from_len(0x10_4000, 0x2000)),
+        );
+        assert_well_formed(kernel_regions);
+        let regions: Vec<_> = kernel_regions
+            .iter()
+            .map(|r| (r.start, r.end, r.kind))
+            .collect();
+        assert_eq!(
+            regions,
+            [
+                (0, 0x10_0000, MemoryRegionKind::Usable),
+                (0x10_0000, 0x10_4000, MemoryRegionKind::Bootloader),
+                (0x10_4000, 0x10_6000, MemoryRegionKind::DmaBuffer),
+                (0x10_6000, 0x10_7000, MemoryRegionKind::Bootloader),
+                (0x10_7000, MAX_PHYS_ADDR, MemoryRegionKind::Usable),
+            ]
+        );
+    }
+
+    /// Constructs the memory map for two usable descriptors separated by a reserved one, after
+    /// allocating `allocated_frames` frames from the start of the first descriptor.
+    fn next_free_test_map(allocated_frames: usize) -> Vec<(u64, u64, MemoryRegionKind)> {
+        let regions = [
+            (0x10_0000, 0x10000, MemoryRegionKind::Usable),
+            (0x11_0000, 0x1000, MemoryRegionKind::UnknownBios(2)),
+            (0x11_1000, 0x10000, MemoryRegionKind::Usable),
+        ]
+        .map(|(start, len, kind)| TestMemoryRegion {
+            start: PhysAddr::new(start),
+            len,
+            kind,
+        });
+        let mut allocator = LegacyFrameAllocator::new(regions.into_iter());
+        for _ in 0..allocated_frames {
+            allocator.allocate_frame().unwrap();
+        }
+
+        let mut regions = vec![MaybeUninit::uninit(); allocator.memory_map_max_region_count()];
+        let kernel_regions = allocator.construct_memory_map(&mut regions, &[], None, 0, None, None);
+        assert_well_formed(kernel_regions);
+        kernel_regions
+            .iter()
+            .map(|r| (r.start, r.end, r.kind))
+            .collect()
+    }
+
+    #[test]
+    fn test_next_free_at_descriptor_start() {
+        // nothing of the first descriptor is used, so no empty bootloader region is reported
+        assert_eq!(
+            next_free_test_map(0),
+            [
+                (0x10_0000, 0x11_0000, MemoryRegionKind::Usable),
+                (0x11_0000, 0x11_1000, MemoryRegionKind::UnknownBios(2)),
+                (0x11_1000, 0x12_1000, MemoryRegionKind::Usable),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_next_free_inside_descriptor() {
+        assert_eq!(
+            next_free_test_map(2),
+            [
+                (0x10_0000, 0x10_2000, MemoryRegionKind::Bootloader),
+                (0x10_2000, 0x11_0000, MemoryRegionKind::Usable),
+                (0x11_0000, 0x11_1000, MemoryRegionKind::UnknownBios(2)),
+                (0x11_1000, 0x12_1000, MemoryRegionKind::Usable),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_next_free_at_descriptor_end() {
+        // the first descriptor is fully used, so no empty usable region is reported and the
+        // second descriptor is not affected
+        assert_eq!(
+            next_free_test_map(16),
+            [
+                (0x10_0000, 0x11_0000, MemoryRegionKind::Bootloader),
+                (0x11_0000, 0x11_1000, MemoryRegionKind::UnknownBios(2)),
+                (0x11_1000, 0x12_1000, MemoryRegionKind::Usable),
+            ]
+        );
+    }
 }
@@ -1,6 +1,6 @@
-use crate::{entropy, load_kernel::VirtualAddressOffset, BootInfo, RawFrameBufferInfo};
-use bootloader_api::{config, info::MemoryRegion, BootloaderConfig};
-use core::{alloc::Layout, iter::Step};
+use crate::{entropy, load_kernel::VirtualAddressOffset, RawFrameBufferInfo};
+use bootloader_api::{config, BootloaderConfig};
+use core::{iter::Step, ops::Range};
 use rand::{
     distributions::{Distribution, Uniform},
     seq::IteratorRandom,
@@ -28,9 +28,11 @@ impl UsedLevel4Entries {
     /// Initializes a new instance.
     ///
     /// Marks the statically configured virtual address ranges from the config as used.
+    /// `boot_info_size` is the size of the whole boot info mapping in bytes, including the
+    /// memory map and the other data stored behind the `BootInfo` struct.
     pub fn new(
         max_phys_addr: PhysAddr,
-        regions_len: usize,
+        boot_info_size: usize,
         framebuffer: Option<&RawFrameBufferInfo>,
         config: &BootloaderConfig,
     ) -> Self {
@@ -57,20 +59,18 @@ impl UsedLevel4Entries {
         }
 
         if let config::Mapping::FixedAddress(kernel_stack_address) = config.mappings.kernel_stack {
-            used.mark_range_as_used(kernel_stack_address, config.kernel_stack_size);
+            used.mark_range_as_used(
+                kernel_stack_address,
+                crate::kernel_stack_guard_size(config) + config.kernel_stack_size,
+            );
         }
 
         if let config::Mapping::FixedAddress(boot_info_address) = config.mappings.boot_info {
-            let boot_info_layout = Layout::new::<BootInfo>();
-            let regions = regions_len + 1; // one region might be split into used/unused
-            let memory_regions_layout = Layout::array::<MemoryRegion>(regions).unwrap();
-            let (combined, _) = boot_info_layout.extend(memory_regions_layout).unwrap();
-
-            used.mark_range_as_used(boot_info_address, combined.size());
+            used.mark_range_as_used(boot_info_address, boot_info_size);
         }
 
         if let config::Mapping::FixedAddress(framebuffer_address) = config.mappings.framebuffer {
-            if let Some(framebuffer) = framebuffer {
+            if let Some(framebuffer) = framebuffer.filter(|f| f.info.byte_len != 0) {
                 used.mark_range_as_used(framebuffer_address, framebuffer.info.byte_len);
             }
         }
@@ -103,7 +103,7 @@ impl UsedLevel4Entries {
     /// Marks all p4 entries in the range `[address..address+size)` as used.
     ///
     /// `size` can be a `u64` or `usize`.
-    fn mark_range_as_used<S>(&mut self, address: u64, size: S)
+    pub fn mark_range_as_used<S>(&mut self, address: u64, size: S)
     where
         VirtAddr: core::ops::Add<S, Output = VirtAddr>,
     {
@@ -142,13 +142,18 @@ impl UsedLevel4Entries {
     /// Since this method marks each returned index as used, it can be used multiple times
     /// to determine multiple unused virtual memory regions.
     pub fn get_free_entries(&mut self, num: u64) -> PageTableIndex {
+        self.get_free_entries_in(num, 0..512)
+    }
+
+    /// Like [`Self::get_free_entries`], but only considers the given range of level 4
+    /// indices.
+    fn get_free_entries_in(&mut self, num: u64, indices: Range<usize>) -> PageTableIndex {
         // Create an iterator over all available p4 indices with `num` contiguous free entries.
-        let mut free_entries = self
-            .entry_state
+        let mut free_entries = self.entry_state[indices.clone()]
             .windows(num.into_usize())
             .enumerate()
             .filter(|(_, entries)| entries.iter().all(|used| !used))
-            .map(|(idx, _)| idx);
+            .map(|(idx, _)| indices.start + idx);
 
         // Choose the free entry index.
         let idx_opt = if let Some(rng) = self.rng.as_mut() {
@@ -175,13 +180,28 @@ impl UsedLevel4Entries {
     /// This function calls [`get_free_entries`] internally, so all of its docs applies here
     /// too.
     pub fn get_free_address(&mut self, size: u64, alignment: u64) -> VirtAddr {
+        self.get_free_address_in(size, alignment, 0..512)
+    }
+
+    /// Like [`Self::get_free_address`], but only returns addresses in the higher half of the
+    /// address space (i.e. `>= 0xffff_8000_0000_0000`).
+    pub fn get_free_higher_half_address(&mut self, size: u64, alignment: u64) -> VirtAddr {
+        self.get_free_address_in(size, alignment, 256..512)
+    }
+
+    fn get_free_address_in(
+        &mut self,
+        size: u64,
+        alignment: u64,
+        indices: Range<usize>,
+    ) -> VirtAddr {
         assert!(alignment.is_power_of_two());
 
         const LEVEL_4_SIZE: u64 = 4096 * 512 * 512 * 512;
 
         let level_4_entries = (size + (LEVEL_4_SIZE - 1)) / LEVEL_4_SIZE;
         let base = Page::from_page_table_indices_1gib(
-            self.get_free_entries(level_4_entries),
+            self.get_free_entries_in(level_4_entries, indices),
             PageTableIndex::new(0),
         )
         .start_address();
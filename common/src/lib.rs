@@ -2,25 +2,47 @@
 #![feature(step_trait)]
 #![deny(unsafe_op_in_unsafe_fn)]
 
-use crate::legacy_memory_region::{LegacyFrameAllocator, LegacyMemoryRegion};
+use crate::legacy_memory_region::{LegacyFrameAllocator, LegacyMemoryRegion, UsedMemorySlice};
 use bootloader_api::{
     config::Mapping,
-    info::{FrameBuffer, FrameBufferInfo, MemoryRegion, TlsTemplate},
+    info::{
+        CpuStack, FrameBuffer, FrameBufferInfo, GraphicsMode, MemoryRegion, MemoryRegionKind,
+        PixelFormat, TlsTemplate,
+    },
     BootInfo, BootloaderConfig,
 };
-use bootloader_boot_config::{BootConfig, LevelFilter};
-use core::{alloc::Layout, arch::asm, mem::MaybeUninit, slice};
+use bootloader_boot_config::{BootConfig, LevelFilter, UnknownPixelFormatPolicy};
+use core::convert::Infallible;
+use core::{
+    alloc::Layout,
+    arch::asm,
+    mem::{self, MaybeUninit},
+    ops::Range,
+    slice,
+};
 use level_4_entries::UsedLevel4Entries;
-use usize_conversions::FromUsize;
+use load_kernel::{KernelFrames, LoadedKernel};
+pub use mapping::{try_map, MapError};
+use timing::BootTimer;
+use usize_conversions::{FromUsize, IntoUsize};
 use x86_64::{
+    align_up,
+    registers::control::{Cr4, Cr4Flags},
     structures::paging::{
-        page_table::PageTableLevel, FrameAllocator, Mapper, OffsetPageTable, Page, PageSize,
-        PageTableFlags, PageTableIndex, PhysFrame, Size2MiB, Size4KiB,
+        mapper::TranslateResult, page_table::PageTableLevel, FrameAllocator, Mapper,
+        OffsetPageTable, Page, PageSize, PageTableFlags, PageTableIndex, PhysFrame, Size1GiB,
+        Size2MiB, Size4KiB, Translate,
     },
     PhysAddr, VirtAddr,
 };
 use xmas_elf::ElfFile;
 
+/// Provides a function to determine the number of CPUs from the ACPI tables.
+pub mod acpi;
+/// Provides an in-memory buffer of the boot log messages.
+pub mod boot_log;
+/// Provides a function to detect the features supported by the CPU.
+mod cpu_features;
 /// Provides a function to gather entropy and build a RNG.
 mod entropy;
 /// Provides a type that logs output as text to pixel-based framebuffers.
@@ -34,23 +56,52 @@ pub mod level_4_entries;
 pub mod load_kernel;
 /// Provides a logger that logs output as text in various formats.
 pub mod logger;
+/// Provides a decompressor for LZ4-compressed kernel images.
+pub mod lz4;
+/// Provides a helper for mapping pages with descriptive errors.
+pub mod mapping;
+/// Provides a debug check for the flags of the kernel page table.
+#[cfg(debug_assertions)]
+mod page_table_check;
 /// Provides a type that logs output as text to a Serial Being port.
 pub mod serial;
+/// Provides a timer to measure the duration of the boot phases.
+pub mod timing;
 
 const PAGE_SIZE: u64 = 4096;
 
+/// Interval at which a serial heartbeat is printed while mapping the physical memory.
+const HEARTBEAT_INTERVAL: u64 = 16 * 1024 * 1024 * 1024;
+
+/// Number of bytes below and above the current stack pointer that are reserved in the memory
+/// map because the bootloader's stack is still in use until the kernel is entered.
+const BOOTLOADER_STACK_RESERVATION: u64 = 64 * 1024;
+
 /// Initialize a text-based logger using the given pixel-based framebuffer as output.
 pub fn init_logger(
-    framebuffer: &'static mut [u8],
-    info: FrameBufferInfo,
+    framebuffer: Option<(&'static mut [u8], FrameBufferInfo)>,
     log_level: LevelFilter,
     frame_buffer_logger_status: bool,
     serial_logger_status: bool,
+    unknown_pixel_format: UnknownPixelFormatPolicy,
 ) {
+    let info = framebuffer.as_ref().map(|(_, info)| *info);
+    let unknown_format = matches!(
+        info.map(|info| info.pixel_format),
+        Some(PixelFormat::Unknown { .. })
+    );
+    let framebuffer = match (framebuffer, unknown_pixel_format) {
+        (Some(_), UnknownPixelFormatPolicy::SerialFallback) if unknown_format => None,
+        (Some((framebuffer, mut info)), UnknownPixelFormatPolicy::AssumeBgr) if unknown_format => {
+            info.pixel_format = PixelFormat::Bgr;
+            Some((framebuffer, info))
+        }
+        (framebuffer, _) => framebuffer,
+    };
+
     let logger = logger::LOGGER.get_or_init(move || {
         logger::LockedLogger::new(
             framebuffer,
-            info,
             frame_buffer_logger_status,
             serial_logger_status,
         )
@@ -58,6 +109,12 @@ pub fn init_logger(
     log::set_logger(logger).expect("logger already set");
     log::set_max_level(convert_level(log_level));
     log::info!("Framebuffer info: {:?}", info);
+    if unknown_format {
+        log::warn!(
+            "Unsupported framebuffer pixel format, using policy {:?}",
+            unknown_pixel_format
+        );
+    }
 }
 
 fn convert_level(level: LevelFilter) -> log::LevelFilter {
@@ -78,8 +135,31 @@ pub struct SystemInfo {
     pub framebuffer: Option<RawFrameBufferInfo>,
     /// Address of the _Root System Description Pointer_ structure of the ACPI standard.
     pub rsdp_addr: Option<PhysAddr>,
+    /// Address of the SMBIOS entry point structure, if any.
+    pub smbios_addr: Option<PhysAddr>,
     pub ramdisk_addr: Option<u64>,
     pub ramdisk_len: u64,
+    /// The TSC frequency in Hz, if it was calibrated by the bootloader.
+    pub tsc_frequency: Option<u64>,
+    /// The number of enabled CPUs, including the bootstrap processor.
+    pub cpu_count: usize,
+    /// The kernel command line loaded from the boot medium, if any.
+    ///
+    /// The memory must stay valid until [`create_boot_info`] copied it into the boot info.
+    pub cmdline: Option<&'static [u8]>,
+    /// The graphics modes reported by the firmware, see [`BootInfo::graphics_modes`].
+    ///
+    /// The memory must stay valid until [`create_boot_info`] copied it into the boot info.
+    pub graphics_modes: &'static [GraphicsMode],
+    /// The vendor string of the UEFI firmware, see [`BootInfo::firmware_vendor_addr`].
+    ///
+    /// Must not be longer than [`BootInfo::FIRMWARE_VENDOR_MAX_LEN`]. The memory must stay
+    /// valid until [`create_boot_info`] copied it into the boot info.
+    pub firmware_vendor: Option<&'static str>,
+    /// The revision of the UEFI firmware, or 0 on BIOS systems.
+    pub firmware_revision: u32,
+    /// The timer that was started when the bootloader started.
+    pub boot_timer: BootTimer,
 }
 
 /// The physical address of the framebuffer and information about the framebuffer.
@@ -89,6 +169,10 @@ pub struct RawFrameBufferInfo {
     pub addr: PhysAddr,
     /// Information about the framebuffer, including layout and pixel format.
     pub info: FrameBufferInfo,
+    /// Stable identifier of the display output, see [`FrameBuffer::id`].
+    pub id: u64,
+    /// The pixel format code reported by the firmware, if any.
+    pub firmware_pixel_format: Option<u32>,
 }
 
 pub struct Kernel<'a> {
@@ -99,22 +183,34 @@ pub struct Kernel<'a> {
 }
 
 impl<'a> Kernel<'a> {
+    /// Parses the given kernel ELF file and its bootloader configuration.
+    ///
+    /// Panics if the kernel is invalid, see [`Self::try_parse`].
     pub fn parse(kernel_slice: &'a [u8]) -> Self {
-        let kernel_elf = ElfFile::new(kernel_slice).unwrap();
+        Self::try_parse(kernel_slice).unwrap_or_else(|err| panic!("failed to parse kernel: {err}"))
+    }
+
+    /// Parses the given kernel ELF file and its bootloader configuration.
+    ///
+    /// Returns an error if the ELF file is invalid or not supported, or if it doesn't contain
+    /// a configuration that is compatible with this bootloader.
+    pub fn try_parse(kernel_slice: &'a [u8]) -> Result<Self, &'static str> {
+        let kernel_elf = ElfFile::new(kernel_slice)?;
+        load_kernel::check_elf_header(&kernel_elf)?;
         let config = {
-            let section = kernel_elf
-                .find_section_by_name(".bootloader-config")
-                .expect("bootloader config section not found; kernel must be compiled against bootloader_api");
+            let section = kernel_elf.find_section_by_name(".bootloader-config").ok_or(
+                "bootloader config section not found; kernel must be compiled against bootloader_api",
+            )?;
             let raw = section.raw_data(&kernel_elf);
             BootloaderConfig::deserialize(raw)
-                .expect("kernel was compiled with incompatible bootloader_api version")
+                .map_err(|_| "kernel was compiled with incompatible bootloader_api version")?
         };
-        Kernel {
+        Ok(Kernel {
             elf: kernel_elf,
             config,
             start_address: kernel_slice.as_ptr(),
             len: kernel_slice.len(),
-        }
+        })
     }
 }
 
@@ -123,18 +219,21 @@ impl<'a> Kernel<'a> {
 /// This function is a convenience function that first calls [`set_up_mappings`], then
 /// [`create_boot_info`], and finally [`switch_to_kernel`]. The given arguments are passed
 /// directly to these functions, so see their docs for more info.
+///
+/// This function only returns if one of the page mappings could not be created.
 pub fn load_and_switch_to_kernel<I, D>(
     kernel: Kernel,
     boot_config: BootConfig,
     mut frame_allocator: LegacyFrameAllocator<I, D>,
     mut page_tables: PageTables,
     system_info: SystemInfo,
-) -> !
+) -> Result<Infallible, MapError>
 where
     I: ExactSizeIterator<Item = D> + Clone,
     D: LegacyMemoryRegion,
 {
     let config = kernel.config;
+    let mut boot_timer = system_info.boot_timer;
     let mut mappings = set_up_mappings(
         kernel,
         &mut frame_allocator,
@@ -142,7 +241,8 @@ where
         system_info.framebuffer.as_ref(),
         &config,
         &system_info,
-    );
+    )?;
+    boot_timer.phase("Setting up the mappings");
     let boot_info = create_boot_info(
         &config,
         &boot_config,
@@ -150,7 +250,9 @@ where
         &mut page_tables,
         &mut mappings,
         system_info,
-    );
+    )?;
+    boot_timer.phase("Creating the boot info");
+    boot_timer.total();
     switch_to_kernel(page_tables, mappings, boot_info);
 }
 
@@ -166,8 +268,9 @@ where
 /// maps this framebuffer in the kernel-level page table, unless the `map_framebuffer` config
 /// option is disabled.
 ///
-/// This function reacts to unexpected situations (e.g. invalid kernel ELF file) with a panic, so
-/// errors are not recoverable.
+/// Errors that occur while mapping pages are returned as a [`MapError`]. Other unexpected
+/// situations (e.g. invalid kernel ELF file) lead to a panic, so these errors are not
+/// recoverable.
 pub fn set_up_mappings<I, D>(
     kernel: Kernel,
     frame_allocator: &mut LegacyFrameAllocator<I, D>,
@@ -175,65 +278,156 @@ pub fn set_up_mappings<I, D>(
     framebuffer: Option<&RawFrameBufferInfo>,
     config: &BootloaderConfig,
     system_info: &SystemInfo,
-) -> Mappings
+) -> Result<Mappings, MapError>
 where
     I: ExactSizeIterator<Item = D> + Clone,
     D: LegacyMemoryRegion,
 {
     let kernel_page_table = &mut page_tables.kernel;
 
+    let boot_info_layout = BootInfoLayout::new(
+        config,
+        system_info,
+        frame_allocator.memory_map_max_region_count(),
+    );
     let mut used_entries = UsedLevel4Entries::new(
         frame_allocator.max_phys_addr(),
-        frame_allocator.len(),
+        boot_info_layout.layout.size(),
         framebuffer,
         config,
     );
 
-    // Enable support for the no-execute bit in page tables.
-    enable_nxe_bit();
+    // Enable support for the no-execute bit in page tables. Very early x86_64 CPUs don't
+    // support it, so all pages need to be executable on them.
+    if cpu_features::detect().nx {
+        enable_nxe_bit();
+    } else {
+        log::warn!("The CPU doesn't support the no-execute bit, mapping all pages executable");
+        mapping::disable_no_execute();
+    }
     // Make the kernel respect the write-protection bits even when in ring 0 by default
     enable_write_protect_bit();
 
     let config = kernel.config;
     let kernel_slice_start = PhysAddr::new(kernel.start_address as _);
     let kernel_slice_len = u64::try_from(kernel.len).unwrap();
-
-    let (kernel_image_offset, entry_point, tls_template) = load_kernel::load_kernel(
+    let LoadedKernel {
+        entry_point,
+        tls_template,
+        image_offset: kernel_image_offset,
+        image_range: kernel_image,
+        frames: kernel_frames,
+    } = load_kernel::load_kernel(
         kernel,
         kernel_page_table,
         frame_allocator,
         &mut used_entries,
     )
-    .expect("no entry point");
+    .unwrap_or_else(|err| panic!("failed to load kernel: {err}"));
     log::info!("Entry point at: {:#x}", entry_point.as_u64());
     // create a stack
+    let guard_size = kernel_stack_guard_size(&config);
     let stack_start = {
-        // we need page-alignment because we want a guard page directly below the stack
-        let guard_page = mapping_addr_page_aligned(
+        // we need page-alignment because we want a guard region directly below the stack
+        let guard_start = mapping_addr_page_aligned(
             config.mappings.kernel_stack,
-            // allocate an additional page as a guard page
-            Size4KiB::SIZE + config.kernel_stack_size,
+            // allocate additional pages for the guard region
+            guard_size + config.kernel_stack_size,
             &mut used_entries,
             "kernel stack start",
         );
-        guard_page + 1
+        let stack_start = guard_start + guard_size / Size4KiB::SIZE;
+
+        // Make sure that the guard region doesn't overlap the kernel image, so that stack
+        // overflows can't silently corrupt kernel data. Dynamic stacks are placed in unused
+        // level 4 entries together with their guard region, so only fixed stacks can do so.
+        let stack_addr = stack_start.start_address();
+        if let Some(min_stack_start) = kernel_stack_min_start(stack_addr, &kernel_image, guard_size)
+        {
+            panic!(
+                "kernel stack at {:#x} is too close to the kernel image ending at {:#x}, \
+                its guard region of {:#x} bytes requires a start address of at least {:#x}",
+                stack_addr.as_u64(),
+                kernel_image.end.as_u64(),
+                guard_size,
+                min_stack_start.as_u64(),
+            );
+        }
+        stack_start
     };
     let stack_end_addr = stack_start.start_address() + config.kernel_stack_size;
 
+    log::info!(
+        "Kernel stack at {:#x}..{:#x} (guard region of {:#x} bytes)",
+        stack_start.start_address().as_u64(),
+        stack_end_addr.as_u64(),
+        guard_size,
+    );
+
     let stack_end = Page::containing_address(stack_end_addr - 1u64);
     for page in Page::range_inclusive(stack_start, stack_end) {
         let frame = frame_allocator
             .allocate_frame()
-            .expect("frame allocation failed when mapping a kernel stack");
+            .ok_or_else(|| MapError::frame_allocation_failed(page))?;
+        zero_frame(frame);
         let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
-        match unsafe { kernel_page_table.map_to(page, frame, flags, frame_allocator) } {
-            Ok(tlb) => tlb.flush(),
-            Err(err) => panic!("failed to map page {:?}: {:?}", page, err),
-        }
+        unsafe { try_map(kernel_page_table, page, frame, flags, frame_allocator) }?;
     }
 
+    // create stacks for the application processors
+    let ap_stack_count = match config.smp_kernel_stacks {
+        true => u64::from_usize(system_info.cpu_count.saturating_sub(1)),
+        false => 0,
+    };
+    let ap_stacks_start = if ap_stack_count > 0 {
+        log::info!("Map {ap_stack_count} kernel stacks for application processors");
+
+        let stack_len = align_up(config.kernel_stack_size, Size4KiB::SIZE);
+        let total_len = (ap_stack_count + 1) * stack_len;
+        let usable_len = frame_allocator.usable_memory_len();
+        if total_len > usable_len {
+            panic!(
+                "not enough memory for {} kernel stacks of {:#x} bytes each \
+                ({:#x} bytes of usable memory)",
+                ap_stack_count + 1,
+                stack_len,
+                usable_len
+            );
+        }
+
+        let start = mapping_addr_page_aligned(
+            Mapping::Dynamic,
+            ap_stack_count * (guard_size + stack_len),
+            &mut used_entries,
+            "cpu stacks start",
+        );
+        for i in 0..ap_stack_count {
+            let bottom = start.start_address() + i * (guard_size + stack_len) + guard_size;
+            let flags =
+                PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+            for page in Page::range(
+                Page::containing_address(bottom),
+                Page::containing_address(bottom + stack_len),
+            ) {
+                let frame = frame_allocator
+                    .allocate_frame()
+                    .ok_or_else(|| MapError::frame_allocation_failed(page))?;
+                zero_frame(frame);
+                unsafe { try_map(kernel_page_table, page, frame, flags, frame_allocator) }?;
+            }
+        }
+        Some(start.start_address())
+    } else {
+        None
+    };
+
     // identity-map context switch function, so that we don't get an immediate pagefault
     // after switching the active page table
+    //
+    // The pages are mapped without `NO_EXECUTE`, so they stay executable after the `mov cr3`.
+    // They are deliberately not writable: the context switch only writes to the kernel stack,
+    // which is mapped separately, and keeping code pages read-only avoids handing the kernel
+    // a writable and executable mapping.
     let context_switch_function = PhysAddr::new(context_switch as *const () as u64);
     let context_switch_function_start_frame: PhysFrame =
         PhysFrame::containing_address(context_switch_function);
@@ -242,7 +436,7 @@ where
         context_switch_function_start_frame + 1,
     ) {
         let page = Page::containing_address(VirtAddr::new(frame.start_address().as_u64()));
-        match unsafe {
+        unsafe {
             // The parent table flags need to be both readable and writable to
             // support recursive page tables.
             // See https://github.com/rust-osdev/bootloader/issues/443#issuecomment-2130010621
@@ -253,19 +447,19 @@ where
                 PageTableFlags::PRESENT | PageTableFlags::WRITABLE,
                 frame_allocator,
             )
-        } {
-            Ok(tlb) => tlb.flush(),
-            Err(err) => panic!("failed to identity map frame {:?}: {:?}", frame, err),
         }
+        .map_err(|err| MapError::new(page, frame, err))?
+        .flush();
     }
 
     // create, load, and identity-map GDT (required for working `iretq`)
     let gdt_frame = frame_allocator
         .allocate_frame()
-        .expect("failed to allocate GDT frame");
+        .expect("out of physical memory while allocating the GDT frame");
+    zero_frame(gdt_frame);
     gdt::create_and_load(gdt_frame);
     let gdt_page = Page::containing_address(VirtAddr::new(gdt_frame.start_address().as_u64()));
-    match unsafe {
+    unsafe {
         // The parent table flags need to be both readable and writable to
         // support recursive page tables.
         kernel_page_table.map_to_with_table_flags(
@@ -275,36 +469,92 @@ where
             PageTableFlags::PRESENT | PageTableFlags::WRITABLE,
             frame_allocator,
         )
-    } {
-        Ok(tlb) => tlb.flush(),
-        Err(err) => panic!("failed to identity map frame {:?}: {:?}", gdt_frame, err),
     }
+    .map_err(|err| MapError::new(gdt_page, gdt_frame, err))?
+    .flush();
+
+    // skip framebuffers that are empty or that don't fit into the physical address space
+    let framebuffer = framebuffer.filter(|framebuffer| {
+        let byte_len = u64::from_usize(framebuffer.info.byte_len);
+        if byte_len == 0 {
+            log::warn!("Framebuffer has a size of 0 bytes, not mapping it");
+            return false;
+        }
+        let end = framebuffer
+            .addr
+            .as_u64()
+            .checked_add(byte_len)
+            .and_then(|end| end.checked_add(Size4KiB::SIZE - 1))
+            .and_then(|end| PhysAddr::try_new(end).ok());
+        if end.is_none() {
+            log::error!(
+                "Framebuffer at {:#x} with size {:#x} exceeds the physical address space, \
+                not mapping it",
+                framebuffer.addr.as_u64(),
+                byte_len
+            );
+            return false;
+        }
+        true
+    });
 
     // map framebuffer
     let framebuffer_virt_addr = if let Some(framebuffer) = framebuffer {
         log::info!("Map framebuffer");
 
-        let framebuffer_start_frame: PhysFrame = PhysFrame::containing_address(framebuffer.addr);
-        let framebuffer_end_frame =
-            PhysFrame::containing_address(framebuffer.addr + framebuffer.info.byte_len - 1u64);
-        let start_page = mapping_addr_page_aligned(
-            config.mappings.framebuffer,
-            u64::from_usize(framebuffer.info.byte_len),
-            &mut used_entries,
-            "framebuffer",
-        );
-        for (i, frame) in
-            PhysFrame::range_inclusive(framebuffer_start_frame, framebuffer_end_frame).enumerate()
-        {
-            let page = start_page + u64::from_usize(i);
-            let flags =
-                PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
-            match unsafe { kernel_page_table.map_to(page, frame, flags, frame_allocator) } {
-                Ok(tlb) => tlb.flush(),
-                Err(err) => panic!(
-                    "failed to map page {:?} to frame {:?}: {:?}",
-                    page, frame, err
-                ),
+        let framebuffer_start = framebuffer.addr.align_down(Size4KiB::SIZE);
+        let framebuffer_end = (framebuffer.addr + u64::from_usize(framebuffer.info.byte_len))
+            .align_up(Size4KiB::SIZE);
+        let framebuffer_len = framebuffer_end - framebuffer_start;
+        let start_page = match config.mappings.framebuffer {
+            // choose an address with the same offset into a 2MiB page as the physical address,
+            // so that huge pages can be used for most of the framebuffer
+            Mapping::Dynamic => {
+                let huge_page_offset = framebuffer_start.as_u64() % Size2MiB::SIZE;
+                let addr =
+                    used_entries.get_free_address(framebuffer_len + Size2MiB::SIZE, Size2MiB::SIZE);
+                Page::containing_address(addr + huge_page_offset)
+            }
+            fixed => mapping_addr_page_aligned(
+                fixed,
+                u64::from_usize(framebuffer.info.byte_len),
+                &mut used_entries,
+                "framebuffer",
+            ),
+        };
+        let use_huge_pages = start_page.start_address().as_u64() % Size2MiB::SIZE
+            == framebuffer_start.as_u64() % Size2MiB::SIZE;
+
+        // the framebuffer is typically shared by all address spaces of the kernel
+        let mut flags = PageTableFlags::PRESENT
+            | PageTableFlags::WRITABLE
+            | PageTableFlags::NO_EXECUTE
+            | PageTableFlags::GLOBAL;
+        // framebuffer memory is only written sequentially, so combining writes is much faster
+        // than the default write-back caching
+        if cpu_features::enable_write_combining() {
+            log::info!("Using the write-combining memory type for the framebuffer");
+            flags |= PageTableFlags::WRITE_THROUGH;
+        } else {
+            log::info!("PAT is not supported, using the default memory type for the framebuffer");
+        }
+        let mut offset = 0;
+        while offset < framebuffer_len {
+            let phys_addr = framebuffer_start + offset;
+            let virt_addr = start_page.start_address() + offset;
+            if use_huge_pages
+                && phys_addr.is_aligned(Size2MiB::SIZE)
+                && framebuffer_len - offset >= Size2MiB::SIZE
+            {
+                let page = Page::<Size2MiB>::from_start_address(virt_addr).unwrap();
+                let frame = PhysFrame::<Size2MiB>::from_start_address(phys_addr).unwrap();
+                unsafe { try_map(kernel_page_table, page, frame, flags, frame_allocator) }?;
+                offset += Size2MiB::SIZE;
+            } else {
+                let page = Page::<Size4KiB>::from_start_address(virt_addr).unwrap();
+                let frame = PhysFrame::<Size4KiB>::from_start_address(phys_addr).unwrap();
+                unsafe { try_map(kernel_page_table, page, frame, flags, frame_allocator) }?;
+                offset += Size4KiB::SIZE;
             }
         }
         let framebuffer_virt_addr = start_page.start_address();
@@ -312,15 +562,64 @@ where
     } else {
         None
     };
+
+    let back_buffer = match framebuffer {
+        Some(framebuffer) if config.framebuffer_back_buffer => {
+            log::info!("Map framebuffer back buffer");
+
+            let len = align_up(u64::from_usize(framebuffer.info.byte_len), Size4KiB::SIZE);
+            let frame_count = len / Size4KiB::SIZE;
+            let max_phys_addr = frame_allocator.max_phys_addr();
+            match frame_allocator.allocate_contiguous_frames(frame_count, max_phys_addr) {
+                Some(start_frame) => {
+                    // zero the buffer, utilizing the identity mapping of the bootloader
+                    let buffer = start_frame.start_address().as_u64() as *mut u8;
+                    unsafe { slice::from_raw_parts_mut(buffer, len.into_usize()) }.fill(0);
+                    let start_page = mapping_addr_page_aligned(
+                        Mapping::Dynamic,
+                        len,
+                        &mut used_entries,
+                        "back buffer",
+                    );
+                    let flags = PageTableFlags::PRESENT
+                        | PageTableFlags::WRITABLE
+                        | PageTableFlags::NO_EXECUTE;
+                    for i in 0..frame_count {
+                        let (page, frame) = (start_page + i, start_frame + i);
+                        unsafe { try_map(kernel_page_table, page, frame, flags, frame_allocator) }?;
+                    }
+                    Some(start_page.start_address())
+                }
+                None => {
+                    log::warn!(
+                        "Not enough contiguous memory for a framebuffer back buffer of \
+                        {len:#x} bytes, skipping it"
+                    );
+                    None
+                }
+            }
+        }
+        None if config.framebuffer_back_buffer => {
+            log::warn!("No framebuffer available, skipping the framebuffer back buffer");
+            None
+        }
+        _ => None,
+    };
     let ramdisk_slice_len = system_info.ramdisk_len;
     let ramdisk_slice_phys_start = system_info.ramdisk_addr.map(PhysAddr::new);
     let ramdisk_slice_start = if let Some(physical_address) = ramdisk_slice_phys_start {
-        let start_page = mapping_addr_page_aligned(
+        let start_page = match boot_data_mapping_addr(
+            &config,
             config.mappings.ramdisk_memory,
             system_info.ramdisk_len,
+            Size4KiB::SIZE,
             &mut used_entries,
-            "ramdisk start",
-        );
+            &kernel_image,
+            "ramdisk",
+        ) {
+            Ok(addr) => Page::from_start_address(addr).unwrap(),
+            Err(addr) => panic!("ramdisk start address must be page-aligned (is `{addr:?})`"),
+        };
         let ramdisk_physical_start_page: PhysFrame<Size4KiB> =
             PhysFrame::containing_address(physical_address);
         let ramdisk_page_count = (system_info.ramdisk_len - 1) / Size4KiB::SIZE;
@@ -332,42 +631,96 @@ where
                 .enumerate()
         {
             let page = start_page + i as u64;
-            match unsafe { kernel_page_table.map_to(page, frame, flags, frame_allocator) } {
-                Ok(tlb) => tlb.ignore(),
-                Err(err) => panic!(
-                    "Failed to map page {:?} to frame {:?}: {:?}",
-                    page, frame, err
-                ),
-            };
+            unsafe { try_map(kernel_page_table, page, frame, flags, frame_allocator) }?;
         }
         Some(start_page.start_address())
     } else {
         None
     };
 
+    let dma_buffer_len = config
+        .dma_buffer_size
+        .map_or(0, |size| align_up(size, Size4KiB::SIZE));
+    let dma_buffer = if dma_buffer_len > 0 {
+        log::info!("Map DMA buffer");
+
+        let frame_count = dma_buffer_len / Size4KiB::SIZE;
+        // The buffer is identity-mapped, so only use frames whose virtual address is not
+        // mapped yet, e.g. by the kernel segments.
+        let is_unmapped = |frame: PhysFrame| {
+            let addr = VirtAddr::new(frame.start_address().as_u64());
+            kernel_page_table.translate_addr(addr).is_none()
+        };
+        let start_frame = frame_allocator
+            .allocate_contiguous_frames_where(
+                frame_count,
+                PhysAddr::new(0x1_0000_0000),
+                is_unmapped,
+            )
+            .unwrap_or_else(|| {
+                panic!(
+                    "no contiguous memory below 4GiB whose identity mapping doesn't overlap \
+                    existing mappings for DMA buffer of {dma_buffer_len:#x} bytes"
+                )
+            });
+
+        let flags = PageTableFlags::PRESENT
+            | PageTableFlags::WRITABLE
+            | PageTableFlags::NO_EXECUTE
+            | PageTableFlags::NO_CACHE;
+        for frame in PhysFrame::range(start_frame, start_frame + frame_count) {
+            zero_frame(frame);
+            let page = Page::containing_address(VirtAddr::new(frame.start_address().as_u64()));
+            unsafe { try_map(kernel_page_table, page, frame, flags, frame_allocator) }?;
+        }
+        used_entries.mark_range_as_used(start_frame.start_address().as_u64(), dma_buffer_len);
+        Some(start_frame.start_address())
+    } else {
+        None
+    };
+
     let physical_memory_offset = if let Some(mapping) = config.mappings.physical_memory {
         log::info!("Map physical memory");
 
-        let start_frame = PhysFrame::containing_address(PhysAddr::new(0));
         let max_phys = frame_allocator.max_phys_addr();
-        let end_frame: PhysFrame<Size2MiB> = PhysFrame::containing_address(max_phys - 1u64);
-
         let size = max_phys.as_u64();
-        let alignment = Size2MiB::SIZE;
+
+        // 1GiB pages need a 1GiB-aligned offset, but fixed offsets only need to be 2MiB-aligned
+        let use_1gib_pages = cpu_features::detect().pages_1gib;
+        let alignment = match (mapping, use_1gib_pages) {
+            (Mapping::Dynamic, true) => Size1GiB::SIZE,
+            _ => Size2MiB::SIZE,
+        };
         let offset = mapping_addr(mapping, size, alignment, &mut used_entries)
             .expect("start address for physical memory mapping must be 2MiB-page-aligned");
+        if use_1gib_pages && offset.is_aligned(Size1GiB::SIZE) {
+            log::info!("Using 1GiB pages for the physical memory mapping");
+        }
 
-        for frame in PhysFrame::range_inclusive(start_frame, end_frame) {
-            let page = Page::containing_address(offset + frame.start_address().as_u64());
-            let flags =
-                PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
-            match unsafe { kernel_page_table.map_to(page, frame, flags, frame_allocator) } {
-                Ok(tlb) => tlb.ignore(),
-                Err(err) => panic!(
-                    "failed to map page {:?} to frame {:?}: {:?}",
-                    page, frame, err
-                ),
-            };
+        // mapping large amounts of physical memory can take a while, so print a dot to the
+        // serial port every few GiB to show that we're still making progress
+        let heartbeat = |s| {
+            if let Some(logger) = logger::LOGGER.get() {
+                logger.write_serial(s);
+            }
+        };
+        // the kernel page table is not active yet
+        unsafe {
+            mapping::map_physical_memory(
+                kernel_page_table,
+                offset,
+                max_phys,
+                use_1gib_pages,
+                frame_allocator,
+                |mapped| {
+                    if mapped % HEARTBEAT_INTERVAL == 0 {
+                        heartbeat(".");
+                    }
+                },
+            )
+        }?;
+        if size >= HEARTBEAT_INTERVAL {
+            heartbeat("\n");
         }
 
         Some(offset)
@@ -402,20 +755,26 @@ where
             );
         }
         let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
-        entry.set_frame(page_tables.kernel_level_4_frame, flags);
+        entry.set_frame(
+            page_tables.kernel_level_4_frame,
+            mapping::supported_flags(flags),
+        );
 
         Some(index)
     } else {
         None
     };
 
-    Mappings {
+    Ok(Mappings {
         framebuffer: framebuffer_virt_addr,
         entry_point,
         // Use the configured stack size, even if it's not page-aligned. However, we
         // need to align it down to the next 16-byte boundary because the System V
         // ABI requires a 16-byte stack alignment.
         stack_top: stack_end_addr.align_down(16u8),
+        stack_bottom: stack_start.start_address(),
+        ap_stacks_start,
+        ap_stack_count,
         used_entries,
         physical_memory_offset,
         recursive_index,
@@ -424,11 +783,16 @@ where
         kernel_slice_start,
         kernel_slice_len,
         kernel_image_offset,
+        kernel_image,
+        kernel_frames,
 
         ramdisk_slice_phys_start,
+        dma_buffer,
+        dma_buffer_len,
+        back_buffer,
         ramdisk_slice_start,
         ramdisk_slice_len,
-    }
+    })
 }
 
 /// Contains the addresses of all memory mappings set up by [`set_up_mappings`].
@@ -437,6 +801,13 @@ pub struct Mappings {
     pub entry_point: VirtAddr,
     /// The (exclusive) end address of the kernel stack.
     pub stack_top: VirtAddr,
+    /// The start address of the kernel stack, directly above its guard page.
+    pub stack_bottom: VirtAddr,
+    /// The start address of the kernel stacks for the application processors (including
+    /// the guard region of the first stack), if enabled.
+    pub ap_stacks_start: Option<VirtAddr>,
+    /// The number of kernel stacks for the application processors.
+    pub ap_stack_count: u64,
     /// Keeps track of used entries in the level 4 page table, useful for finding a free
     /// virtual memory when needed.
     pub used_entries: UsedLevel4Entries,
@@ -455,11 +826,79 @@ pub struct Mappings {
     pub kernel_slice_len: u64,
     /// Relocation offset of the kernel image in virtual memory.
     pub kernel_image_offset: VirtAddr,
+    /// Virtual address range of the loaded kernel segments (including the relocation offset).
+    pub kernel_image: Range<VirtAddr>,
+    /// The frames allocated while loading the kernel.
+    pub kernel_frames: KernelFrames,
     pub ramdisk_slice_phys_start: Option<PhysAddr>,
+    /// Physical start address of the identity-mapped DMA buffer, if enabled.
+    pub dma_buffer: Option<PhysAddr>,
+    /// Size of the DMA buffer in bytes.
+    pub dma_buffer_len: u64,
+    /// The start address of the back buffer for the framebuffer, if enabled.
+    pub back_buffer: Option<VirtAddr>,
     pub ramdisk_slice_start: Option<VirtAddr>,
     pub ramdisk_slice_len: u64,
 }
 
+/// The memory layout of the boot info and the data that is stored directly behind it.
+///
+/// Used both for reserving a fixed boot info address and for creating the boot info, so that
+/// the reservation always covers the whole boot info mapping.
+struct BootInfoLayout {
+    /// The layout of the whole boot info mapping.
+    layout: Layout,
+    /// The maximum number of memory regions.
+    regions: usize,
+    memory_regions_offset: usize,
+    /// The number of kernel stacks in the CPU stack list.
+    stacks: usize,
+    cpu_stacks_offset: usize,
+    cmdline_offset: usize,
+    graphics_modes_offset: usize,
+    firmware_vendor_offset: usize,
+}
+
+impl BootInfoLayout {
+    /// Computes the layout for a memory map of at most `regions` regions and the data of the
+    /// given `system_info`.
+    ///
+    /// Space is reserved for the whole command line, even if it is later ignored because it
+    /// isn't valid UTF-8.
+    fn new(config: &BootloaderConfig, system_info: &SystemInfo, regions: usize) -> Self {
+        let memory_regions_layout = Layout::array::<MemoryRegion>(regions).unwrap();
+        let (layout, memory_regions_offset) = Layout::new::<BootInfo>()
+            .extend(memory_regions_layout)
+            .unwrap();
+        // one stack for the bootstrap processor and one for each application processor
+        let stacks = match config.smp_kernel_stacks {
+            true => system_info.cpu_count.max(1),
+            false => 0,
+        };
+        let cpu_stacks_layout = Layout::array::<CpuStack>(stacks).unwrap();
+        let (layout, cpu_stacks_offset) = layout.extend(cpu_stacks_layout).unwrap();
+        let cmdline_len = system_info.cmdline.map_or(0, <[u8]>::len);
+        let cmdline_layout = Layout::array::<u8>(cmdline_len).unwrap();
+        let (layout, cmdline_offset) = layout.extend(cmdline_layout).unwrap();
+        let graphics_modes_layout =
+            Layout::array::<GraphicsMode>(system_info.graphics_modes.len()).unwrap();
+        let (layout, graphics_modes_offset) = layout.extend(graphics_modes_layout).unwrap();
+        let firmware_vendor_len = system_info.firmware_vendor.map_or(0, str::len);
+        let firmware_vendor_layout = Layout::array::<u8>(firmware_vendor_len).unwrap();
+        let (layout, firmware_vendor_offset) = layout.extend(firmware_vendor_layout).unwrap();
+        Self {
+            layout,
+            regions,
+            memory_regions_offset,
+            stacks,
+            cpu_stacks_offset,
+            cmdline_offset,
+            graphics_modes_offset,
+            firmware_vendor_offset,
+        }
+    }
+}
+
 /// Allocates and initializes the boot info struct and the memory map.
 ///
 /// The boot info and memory map are mapped to both the kernel and bootloader
@@ -473,76 +912,165 @@ pub fn create_boot_info<I, D>(
     page_tables: &mut PageTables,
     mappings: &mut Mappings,
     system_info: SystemInfo,
-) -> &'static mut BootInfo
+) -> Result<&'static mut BootInfo, MapError>
 where
     I: ExactSizeIterator<Item = D> + Clone,
     D: LegacyMemoryRegion,
 {
     log::info!("Allocate bootinfo");
 
+    let cmdline = match system_info.cmdline.map(core::str::from_utf8) {
+        Some(Ok(cmdline)) => cmdline.as_bytes(),
+        Some(Err(err)) => {
+            log::warn!("Ignoring kernel command line that is not valid UTF-8: {err}");
+            &[]
+        }
+        None => &[],
+    };
+
+    let firmware_vendor = system_info.firmware_vendor.unwrap_or_default().as_bytes();
+    assert!(firmware_vendor.len() <= BootInfo::FIRMWARE_VENDOR_MAX_LEN);
+
     // allocate and map space for the boot info
-    let (boot_info, memory_regions) = {
-        let boot_info_layout = Layout::new::<BootInfo>();
-        let regions = frame_allocator.memory_map_max_region_count();
-        let memory_regions_layout = Layout::array::<MemoryRegion>(regions).unwrap();
-        let (combined, memory_regions_offset) =
-            boot_info_layout.extend(memory_regions_layout).unwrap();
+    let (boot_info, memory_regions, cpu_stacks, cmdline, graphics_modes, firmware_vendor) = {
+        let BootInfoLayout {
+            layout: combined,
+            regions,
+            memory_regions_offset,
+            stacks,
+            cpu_stacks_offset,
+            cmdline_offset,
+            graphics_modes_offset,
+            firmware_vendor_offset,
+        } = BootInfoLayout::new(
+            config,
+            &system_info,
+            frame_allocator.memory_map_max_region_count(),
+        );
 
-        let boot_info_addr = mapping_addr(
+        let boot_info_addr = boot_data_mapping_addr(
+            config,
             config.mappings.boot_info,
             u64::from_usize(combined.size()),
             u64::from_usize(combined.align()),
             &mut mappings.used_entries,
+            &mappings.kernel_image,
+            "boot info",
         )
         .expect("boot info addr is not properly aligned");
 
         let memory_map_regions_addr = boot_info_addr + memory_regions_offset;
-        let memory_map_regions_end = boot_info_addr + combined.size();
+        let cpu_stacks_addr = boot_info_addr + cpu_stacks_offset;
+        let cmdline_addr = boot_info_addr + cmdline_offset;
+        let graphics_modes_addr = boot_info_addr + graphics_modes_offset;
+        let firmware_vendor_addr = boot_info_addr + firmware_vendor_offset;
+        let boot_info_end = boot_info_addr + combined.size();
 
         let start_page = Page::containing_address(boot_info_addr);
-        let end_page = Page::containing_address(memory_map_regions_end - 1u64);
+        let end_page = Page::containing_address(boot_info_end - 1u64);
         for page in Page::range_inclusive(start_page, end_page) {
             let flags =
                 PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
             let frame = frame_allocator
                 .allocate_frame()
-                .expect("frame allocation for boot info failed");
-            match unsafe {
-                page_tables
-                    .kernel
-                    .map_to(page, frame, flags, &mut frame_allocator)
-            } {
-                Ok(tlb) => tlb.flush(),
-                Err(err) => panic!("failed to map page {:?}: {:?}", page, err),
-            }
+                .ok_or_else(|| MapError::frame_allocation_failed(page))?;
+            // the boot info doesn't initialize the padding between its parts
+            zero_frame(frame);
+            unsafe {
+                try_map(
+                    &mut page_tables.kernel,
+                    page,
+                    frame,
+                    flags,
+                    &mut frame_allocator,
+                )
+            }?;
             // we need to be able to access it too
-            match unsafe {
-                page_tables
-                    .bootloader
-                    .map_to(page, frame, flags, &mut frame_allocator)
-            } {
-                Ok(tlb) => tlb.flush(),
-                Err(err) => panic!("failed to map page {:?}: {:?}", page, err),
-            }
+            unsafe {
+                try_map(
+                    &mut page_tables.bootloader,
+                    page,
+                    frame,
+                    flags,
+                    &mut frame_allocator,
+                )
+            }?;
         }
 
         let boot_info: &'static mut MaybeUninit<BootInfo> =
             unsafe { &mut *boot_info_addr.as_mut_ptr() };
         let memory_regions: &'static mut [MaybeUninit<MemoryRegion>] =
             unsafe { slice::from_raw_parts_mut(memory_map_regions_addr.as_mut_ptr(), regions) };
-        (boot_info, memory_regions)
+        let cpu_stacks: &'static mut [MaybeUninit<CpuStack>] =
+            unsafe { slice::from_raw_parts_mut(cpu_stacks_addr.as_mut_ptr(), stacks) };
+        let cmdline_buffer: &'static mut [u8] =
+            unsafe { slice::from_raw_parts_mut(cmdline_addr.as_mut_ptr(), cmdline.len()) };
+        cmdline_buffer.copy_from_slice(cmdline);
+        let graphics_modes: &'static mut [GraphicsMode] = unsafe {
+            slice::from_raw_parts_mut(
+                graphics_modes_addr.as_mut_ptr(),
+                system_info.graphics_modes.len(),
+            )
+        };
+        graphics_modes.copy_from_slice(system_info.graphics_modes);
+        let firmware_vendor_buffer: &'static mut [u8] = unsafe {
+            slice::from_raw_parts_mut(firmware_vendor_addr.as_mut_ptr(), firmware_vendor.len())
+        };
+        firmware_vendor_buffer.copy_from_slice(firmware_vendor);
+        (
+            boot_info,
+            memory_regions,
+            cpu_stacks,
+            &*cmdline_buffer,
+            graphics_modes,
+            &*firmware_vendor_buffer,
+        )
     };
 
     log::info!("Create Memory Map");
 
+    // We're still running on the stack provided by the firmware until we switch to the
+    // kernel, so make sure that the kernel doesn't see the memory around it as usable.
+    let stack_slice = {
+        let rsp: u64;
+        unsafe { asm!("mov {}, rsp", out(reg) rsp, options(nomem, nostack, preserves_flags)) };
+        let start = VirtAddr::new(rsp.saturating_sub(BOOTLOADER_STACK_RESERVATION));
+        page_tables.bootloader.translate_addr(start).map(|start| {
+            UsedMemorySlice::new_from_len(start.as_u64(), 2 * BOOTLOADER_STACK_RESERVATION)
+        })
+    };
+
     // build memory map
+    let kernel_frames = mappings.kernel_frames.ranges();
+    let mut kernel_slices = [UsedMemorySlice::new_from_len(
+        mappings.kernel_slice_start.as_u64(),
+        mappings.kernel_slice_len,
+    ); 1 + KernelFrames::MAX_RANGES];
+    kernel_slices[1..][..kernel_frames.len()].copy_from_slice(kernel_frames);
     let memory_regions = frame_allocator.construct_memory_map(
         memory_regions,
-        mappings.kernel_slice_start,
-        mappings.kernel_slice_len,
+        &kernel_slices[..1 + kernel_frames.len()],
         mappings.ramdisk_slice_phys_start,
         mappings.ramdisk_slice_len,
+        stack_slice,
+        mappings
+            .dma_buffer
+            .map(|start| UsedMemorySlice::new_from_len(start.as_u64(), mappings.dma_buffer_len)),
     );
+    log::trace!("Memory map:");
+    for region in memory_regions.iter() {
+        log::trace!("{region}");
+    }
+
+    let local_apic_addr = cpu_features::local_apic_base();
+    if let Some(addr) = local_apic_addr {
+        let addr = addr.as_u64();
+        if memory_regions.iter().any(|region| {
+            region.kind == MemoryRegionKind::Usable && (region.start..region.end).contains(&addr)
+        }) {
+            log::warn!("Local APIC registers at {addr:#x} are reported as usable memory");
+        }
+    }
 
     log::info!("Create bootinfo");
 
@@ -551,36 +1079,99 @@ where
         let mut info = BootInfo::new(memory_regions.into());
         info.framebuffer = mappings
             .framebuffer
-            .map(|addr| unsafe {
-                FrameBuffer::new(
-                    addr.as_u64(),
-                    system_info
-                        .framebuffer
-                        .expect(
-                            "there shouldn't be a mapping for the framebuffer if there is \
-                            no framebuffer",
-                        )
-                        .info,
-                )
+            .map(|addr| {
+                let framebuffer = system_info.framebuffer.expect(
+                    "there shouldn't be a mapping for the framebuffer if there is \
+                    no framebuffer",
+                );
+                let mut framebuffer_info =
+                    unsafe { FrameBuffer::new(addr.as_u64(), framebuffer.info) }
+                        .with_id(framebuffer.id);
+                if let Some(format) = framebuffer.firmware_pixel_format {
+                    framebuffer_info = framebuffer_info.with_firmware_pixel_format(format);
+                }
+                framebuffer_info
             })
             .into();
         info.physical_memory_offset = mappings.physical_memory_offset.map(VirtAddr::as_u64).into();
         info.recursive_index = mappings.recursive_index.map(Into::into).into();
         info.rsdp_addr = system_info.rsdp_addr.map(|addr| addr.as_u64()).into();
+        info.smbios_addr = system_info.smbios_addr.map(|addr| addr.as_u64()).into();
+        info.local_apic_addr = local_apic_addr.map(PhysAddr::as_u64).into();
+        info.cpu_count = system_info.cpu_count.try_into().unwrap_or(u32::MAX);
         info.tls_template = mappings.tls_template.into();
         info.ramdisk_addr = mappings
             .ramdisk_slice_start
             .map(|addr| addr.as_u64())
             .into();
         info.ramdisk_len = mappings.ramdisk_slice_len;
+        info.cmdline_addr = cmdline.as_ptr() as u64;
+        info.cmdline_len = u64::from_usize(cmdline.len());
         info.kernel_addr = mappings.kernel_slice_start.as_u64();
         info.kernel_len = mappings.kernel_slice_len as _;
         info.kernel_image_offset = mappings.kernel_image_offset.as_u64();
+        info.kernel_stack_addr = mappings.stack_bottom.as_u64();
+        info.kernel_stack_len = mappings.stack_top - mappings.stack_bottom;
+        info.cpu_stacks = {
+            let stack_len = align_up(config.kernel_stack_size, Size4KiB::SIZE);
+            let stride = kernel_stack_guard_size(config) + stack_len;
+            for (i, stack) in cpu_stacks.iter_mut().enumerate() {
+                let stack_info = match (i, mappings.ap_stacks_start) {
+                    (0, _) => CpuStack {
+                        bottom: mappings.stack_bottom.as_u64(),
+                        top: mappings.stack_top.as_u64(),
+                    },
+                    (i, Some(start)) => {
+                        let bottom =
+                            start + (u64::from_usize(i) - 1) * stride + (stride - stack_len);
+                        CpuStack {
+                            bottom: bottom.as_u64(),
+                            top: (bottom + config.kernel_stack_size)
+                                .align_down(16u8)
+                                .as_u64(),
+                        }
+                    }
+                    (_, None) => unreachable!("no application processor stacks were mapped"),
+                };
+                stack.write(stack_info);
+            }
+            // inlined variant of: `MaybeUninit::slice_assume_init_mut(cpu_stacks)`
+            let cpu_stacks: &'static mut [CpuStack] =
+                unsafe { &mut *(cpu_stacks as *mut [_] as *mut [_]) };
+            cpu_stacks.into()
+        };
+        info.dma_buffer_addr = mappings.dma_buffer.map(|addr| addr.as_u64()).into();
+        info.dma_buffer_len = mappings.dma_buffer_len;
+        info.back_buffer_addr = mappings.back_buffer.map(|addr| addr.as_u64()).into();
+        info.graphics_modes = graphics_modes.into();
+        info.firmware_vendor_addr = firmware_vendor.as_ptr() as u64;
+        info.firmware_vendor_len = u64::from_usize(firmware_vendor.len());
+        info.firmware_revision = system_info.firmware_revision;
+        info.cpu_features = cpu_features::detect();
+        info.tsc = cpu_features::tsc_info(system_info.tsc_frequency);
+        info.tsc.bootloader_start = system_info.boot_timer.start_tsc();
+        if config.enable_pcid {
+            if info.cpu_features.pcid {
+                // the bit is set on the context switch to the kernel
+                info.cpu_features.pcid_enabled = true;
+            } else {
+                log::warn!("PCIDs are not supported by the CPU, not enabling CR4.PCIDE");
+            }
+        }
         info._test_sentinel = boot_config._test_sentinel;
         info
     });
 
-    boot_info
+    if boot_config.serial_memory_map {
+        if let Some(mut serial) = logger::LOGGER.get().and_then(|logger| logger.serial()) {
+            boot_info
+                .memory_regions
+                .write_csv(&mut *serial)
+                .expect("failed to write memory map to serial port");
+        }
+    }
+
+    Ok(boot_info)
 }
 
 /// Switches to the kernel address space and jumps to the kernel entry point.
@@ -589,12 +1180,65 @@ pub fn switch_to_kernel(
     mappings: Mappings,
     boot_info: &'static mut BootInfo,
 ) -> ! {
+    // Catch mapping bugs during development. This walks the complete page table of the
+    // kernel, so it's only done in debug builds.
+    //
+    // Without the no-execute bit, every writable page is executable too, so skip the check.
+    #[cfg(debug_assertions)]
+    let page_tables = if boot_info.cpu_features.nx {
+        let mut page_tables = page_tables;
+        let framebuffer = mappings
+            .framebuffer
+            .zip(boot_info.framebuffer.as_ref())
+            .map(|(addr, framebuffer)| (addr, u64::from_usize(framebuffer.info().byte_len)));
+        let violations = page_table_check::check_page_table_flags(
+            &mut page_tables.kernel,
+            mappings.recursive_index,
+            framebuffer,
+        );
+        if violations > 0 {
+            log::warn!("Found {violations} page table flag violations");
+        }
+        page_tables
+    } else {
+        page_tables
+    };
+
+    // A missing mapping for the instructions that run directly after the `mov cr3` leads to
+    // a triple fault, so check them while we can still report errors.
+    let context_switch_addr = VirtAddr::new(context_switch as *const () as u64);
+    for addr in [context_switch_addr, context_switch_addr + Size4KiB::SIZE] {
+        let phys_addr = PhysAddr::new(addr.as_u64());
+        check_executable(
+            &page_tables.kernel,
+            addr,
+            Some(phys_addr),
+            "context switch function",
+        );
+    }
+    check_executable(
+        &page_tables.kernel,
+        mappings.entry_point,
+        None,
+        "kernel entry point",
+    );
+
     let PageTables {
         kernel_level_4_frame,
         ..
     } = page_tables;
+    // Global pages require `CR4.PGE`. Without it, the `GLOBAL` flag of the kernel segments,
+    // the framebuffer, and the physical memory mapping is ignored and their TLB entries are
+    // flushed on every `CR3` reload like all others. The stack, the boot info, and the
+    // identity-mapped context switch pages are not global because the kernel might want to
+    // unmap them later.
+    let mut cr4 = Cr4::read_raw() | Cr4Flags::PAGE_GLOBAL.bits();
+    if boot_info.cpu_features.pcid_enabled {
+        cr4 |= Cr4Flags::PCID.bits();
+    }
     let addresses = Addresses {
         page_table: kernel_level_4_frame,
+        cr4,
         stack_top: mappings.stack_top,
         entry_point: mappings.entry_point,
         boot_info,
@@ -610,6 +1254,45 @@ pub fn switch_to_kernel(
     }
 }
 
+/// Panics if the given address is not mapped as executable in the given page table.
+///
+/// If `phys_addr` is given, the address must also be mapped to this physical address. Only
+/// the flags of the last level entry are checked.
+fn check_executable(
+    page_table: &OffsetPageTable,
+    addr: VirtAddr,
+    phys_addr: Option<PhysAddr>,
+    kind: &str,
+) {
+    match page_table.translate(addr) {
+        TranslateResult::Mapped {
+            frame,
+            offset,
+            flags,
+        } => {
+            if flags.contains(PageTableFlags::NO_EXECUTE) {
+                panic!(
+                    "{kind} at {:#x} is not executable in the kernel page table",
+                    addr.as_u64()
+                );
+            }
+            let mapped_addr = frame.start_address() + offset;
+            if let Some(phys_addr) = phys_addr.filter(|&phys_addr| phys_addr != mapped_addr) {
+                panic!(
+                    "{kind} at {:#x} is mapped to {:#x} instead of {:#x} in the kernel page table",
+                    addr.as_u64(),
+                    mapped_addr.as_u64(),
+                    phys_addr.as_u64()
+                );
+            }
+        }
+        TranslateResult::NotMapped | TranslateResult::InvalidFrameAddress(_) => panic!(
+            "{kind} at {:#x} is not mapped in the kernel page table",
+            addr.as_u64()
+        ),
+    }
+}
+
 /// Provides access to the page tables of the bootloader and kernel address space.
 pub struct PageTables {
     /// Provides access to the page tables of the bootloader address space.
@@ -626,19 +1309,29 @@ pub struct PageTables {
 
 /// Performs the actual context switch.
 unsafe fn context_switch(addresses: Addresses) -> ! {
+    // The System V ABI requires `rsp` to be 16-byte aligned before a `call`, which then pushes
+    // an 8 byte return address. The stack top is 16-byte aligned, so pushing a zero return
+    // address gives the entry point the same `rsp % 16 == 8` that it would see after a call.
+    debug_assert!(addresses.stack_top.is_aligned(16u64));
     unsafe {
         asm!(
             r#"
             xor rbp, rbp
             mov cr3, {}
+            mov cr4, {}
             mov rsp, {}
             push 0
             jmp {}
             "#,
             in(reg) addresses.page_table.start_address().as_u64(),
+            // `CR4.PCIDE` can only be set when the lower 12 bits of `CR3` are zero,
+            // so we update `CR4` after loading the kernel page table
+            in(reg) addresses.cr4,
             in(reg) addresses.stack_top.as_u64(),
             in(reg) addresses.entry_point.as_u64(),
             in("rdi") addresses.boot_info as *const _ as usize,
+            // lets the kernel detect a `BootInfo` layout mismatch
+            in("rsi") mem::size_of::<BootInfo>(),
         );
     }
     unreachable!();
@@ -647,11 +1340,45 @@ unsafe fn context_switch(addresses: Addresses) -> ! {
 /// Memory addresses required for the context switch.
 struct Addresses {
     page_table: PhysFrame,
+    cr4: u64,
     stack_top: VirtAddr,
     entry_point: VirtAddr,
     boot_info: &'static mut BootInfo,
 }
 
+/// Returns the size of the guard region below each kernel stack, rounded up to at least
+/// one page.
+/// Zeroes the given frame, utilizing the identity mapping of the bootloader.
+///
+/// The frame allocator doesn't zero the frames it hands out, so this must be called for all
+/// frames that are mapped into the kernel address space and not fully overwritten.
+fn zero_frame(frame: PhysFrame) {
+    let ptr = frame.start_address().as_u64() as *mut u8;
+    unsafe { ptr.write_bytes(0, Size4KiB::SIZE.into_usize()) };
+}
+
+/// Returns the size of the guard region below each kernel stack, rounded up to whole pages.
+pub(crate) fn kernel_stack_guard_size(config: &BootloaderConfig) -> u64 {
+    align_up(config.kernel_stack_guard_size, Size4KiB::SIZE).max(Size4KiB::SIZE)
+}
+
+/// Checks that a kernel stack starting at `stack_addr` leaves at least `guard_size` unmapped
+/// bytes between the end of the kernel image and the bottom of the stack.
+///
+/// Returns the lowest valid stack start address if the stack starts inside the kernel image
+/// or too close above it, and `None` otherwise.
+fn kernel_stack_min_start(
+    stack_addr: VirtAddr,
+    kernel_image: &Range<VirtAddr>,
+    guard_size: u64,
+) -> Option<VirtAddr> {
+    if kernel_image.is_empty() {
+        return None;
+    }
+    let min_stack_start = kernel_image.end.align_up(Size4KiB::SIZE) + guard_size;
+    (stack_addr > kernel_image.start && stack_addr < min_stack_start).then_some(min_stack_start)
+}
+
 fn mapping_addr_page_aligned(
     mapping: Mapping,
     size: u64,
@@ -681,6 +1408,53 @@ fn mapping_addr(
     }
 }
 
+/// Determines the virtual address of data that is passed to the kernel, such as the boot info
+/// or the ramdisk.
+///
+/// Works like [`mapping_addr`], but places the data in the higher half if the
+/// `higher_half_boot_data` config option is enabled. In that case, this function panics if a
+/// fixed address is in the lower half or overlaps the kernel segments.
+fn boot_data_mapping_addr(
+    config: &BootloaderConfig,
+    mapping: Mapping,
+    size: u64,
+    alignment: u64,
+    used_entries: &mut UsedLevel4Entries,
+    kernel_image: &Range<VirtAddr>,
+    kind: &str,
+) -> Result<VirtAddr, VirtAddr> {
+    if !config.mappings.higher_half_boot_data {
+        return mapping_addr(mapping, size, alignment, used_entries);
+    }
+
+    let addr = match mapping {
+        Mapping::FixedAddress(addr) => VirtAddr::new(addr),
+        Mapping::Dynamic => used_entries.get_free_higher_half_address(size, alignment),
+    };
+    if u16::from(addr.p4_index()) < 256 {
+        panic!(
+            "{kind} address {:#x} is not in the higher half of the address space",
+            addr.as_u64()
+        );
+    }
+    let end = addr.as_u64().saturating_add(size);
+    if addr < kernel_image.end && kernel_image.start.as_u64() < end {
+        panic!(
+            "{kind} at {:#x}..{:#x} overlaps the kernel segments at {:#x}..{:#x}",
+            addr.as_u64(),
+            end,
+            kernel_image.start.as_u64(),
+            kernel_image.end.as_u64()
+        );
+    }
+
+    if addr.is_aligned(alignment) {
+        Ok(addr)
+    } else {
+        Err(addr)
+    }
+}
+
 fn enable_nxe_bit() {
     use x86_64::registers::control::{Efer, EferFlags};
     unsafe { Efer::update(|efer| *efer |= EferFlags::NO_EXECUTE_ENABLE) }
@@ -690,3 +1464,71 @@ fn enable_write_protect_bit() {
     use x86_64::registers::control::{Cr0, Cr0Flags};
     unsafe { Cr0::update(|cr0| *cr0 |= Cr0Flags::WRITE_PROTECT) };
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn boot_info_layout() {
+        let mut config = BootloaderConfig::new_default();
+        let mut system_info = SystemInfo {
+            framebuffer: None,
+            rsdp_addr: None,
+            smbios_addr: None,
+            ramdisk_addr: None,
+            ramdisk_len: 0,
+            tsc_frequency: None,
+            cpu_count: 4,
+            cmdline: None,
+            graphics_modes: &[],
+            firmware_vendor: None,
+            firmware_revision: 0,
+            boot_timer: BootTimer::start(),
+        };
+        let empty = BootInfoLayout::new(&config, &system_info, 8);
+        assert_eq!(empty.regions, 8);
+        assert_eq!(empty.stacks, 0);
+        assert!(empty.memory_regions_offset >= mem::size_of::<BootInfo>());
+        assert!(
+            empty.layout.size() >= empty.memory_regions_offset + 8 * mem::size_of::<MemoryRegion>()
+        );
+
+        // all additional data is included in the size
+        config.smp_kernel_stacks = true;
+        system_info.cmdline = Some(b"console=ttyS0");
+        system_info.firmware_vendor = Some("EDK II");
+        let layout = BootInfoLayout::new(&config, &system_info, 8);
+        assert_eq!(layout.stacks, 4);
+        assert!(layout.cpu_stacks_offset >= layout.memory_regions_offset);
+        assert!(layout.cmdline_offset >= layout.cpu_stacks_offset + 4 * mem::size_of::<CpuStack>());
+        assert!(layout.firmware_vendor_offset >= layout.cmdline_offset + 13);
+        assert_eq!(layout.layout.size(), layout.firmware_vendor_offset + 6);
+    }
+
+    #[test]
+    fn kernel_stack_guard() {
+        let kernel_image = VirtAddr::new(0x20_0000)..VirtAddr::new(0x20_4800);
+        let guard_size = 0x2000;
+        let check = |addr| kernel_stack_min_start(VirtAddr::new(addr), &kernel_image, guard_size);
+
+        // stacks below the kernel or far enough above it are fine
+        assert_eq!(check(0x10_0000), None);
+        assert_eq!(check(0x20_0000), None);
+        assert_eq!(check(0x20_7000), None);
+        assert_eq!(check(0x40_0000), None);
+
+        // stacks inside the kernel image or within the guard region must start above it
+        let min_start = Some(VirtAddr::new(0x20_7000));
+        assert_eq!(check(0x20_1000), min_start);
+        assert_eq!(check(0x20_5000), min_start);
+        assert_eq!(check(0x20_6000), min_start);
+
+        // an empty kernel image never conflicts
+        let empty = VirtAddr::new(0x20_0000)..VirtAddr::new(0x20_0000);
+        assert_eq!(
+            kernel_stack_min_start(VirtAddr::new(0x20_1000), &empty, guard_size),
+            None
+        );
+    }
+}
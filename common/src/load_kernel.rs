@@ -1,12 +1,20 @@
-use crate::{level_4_entries::UsedLevel4Entries, PAGE_SIZE};
-use bootloader_api::info::TlsTemplate;
-use core::{cmp, iter::Step, mem::size_of, ops::Add};
+use crate::{
+    legacy_memory_region::UsedMemorySlice, level_4_entries::UsedLevel4Entries, mapping, PAGE_SIZE,
+};
+use bootloader_api::{config::Mapping, info::TlsTemplate};
+use core::{
+    cmp,
+    iter::Step,
+    mem::size_of,
+    ops::{Add, Range},
+};
 
 use x86_64::{
     align_up,
     structures::paging::{
         mapper::{MappedFrame, MapperAllSizes, TranslateResult},
-        FrameAllocator, Page, PageSize, PageTableFlags as Flags, PhysFrame, Size4KiB, Translate,
+        FrameAllocator, Page, PageSize, PageTableFlags as Flags, PhysFrame, Size2MiB, Size4KiB,
+        Translate,
     },
     PhysAddr, VirtAddr,
 };
@@ -22,6 +30,9 @@ use super::Kernel;
 /// Used by [`Inner::make_mut`] and [`Inner::clean_copied_flag`].
 const COPIED: Flags = Flags::BIT_9;
 
+/// The number of 4KiB frames in a 2MiB frame.
+const HUGE_PAGE_FRAMES: u64 = Size2MiB::SIZE / Size4KiB::SIZE;
+
 struct Loader<'a, M, F> {
     elf_file: ElfFile<'a>,
     inner: Inner<'a, M, F>,
@@ -32,6 +43,8 @@ struct Inner<'a, M, F> {
     virtual_address_offset: VirtualAddressOffset,
     page_table: &'a mut M,
     frame_allocator: &'a mut F,
+    /// Map all segments writable and executable, see `BootloaderConfig::disable_nx`.
+    disable_nx: bool,
 }
 
 impl<'a, M, F> Loader<'a, M, F>
@@ -52,46 +65,78 @@ where
         }
 
         let elf_file = kernel.elf;
-        for program_header in elf_file.program_iter() {
-            program::sanity_check(program_header, &elf_file)?;
+        check_elf_header(&elf_file)?;
+
+        // Find the lowest and highest virtual memory address and the biggest alignment.
+        let load_program_headers = elf_file
+            .program_iter()
+            .filter(|h| matches!(h.get_type(), Ok(Type::Load)));
+        let max_addr = load_program_headers
+            .clone()
+            .map(|h| h.virtual_addr() + h.mem_size())
+            .max()
+            .unwrap_or(0);
+        let min_addr = load_program_headers
+            .clone()
+            .map(|h| h.virtual_addr())
+            .min()
+            .unwrap_or(0);
+
+        // Consecutive segments may share a page, but their memory must not overlap.
+        let mut previous_end = 0;
+        for header in load_program_headers.clone() {
+            if header.virtual_addr() < previous_end {
+                return Err("kernel segments overlap or are not sorted by virtual address");
+            }
+            previous_end = header.virtual_addr() + header.mem_size();
         }
 
         let virtual_address_offset = match elf_file.header.pt2.type_().as_type() {
-            header::Type::None => unimplemented!(),
-            header::Type::Relocatable => unimplemented!(),
-            header::Type::Executable => VirtualAddressOffset::zero(),
+            header::Type::Executable => {
+                if let Mapping::FixedAddress(base) = kernel.config.mappings.kernel_base {
+                    if base != min_addr {
+                        return Err("kernel base address does not match the link address of \
+                            the non-relocatable kernel");
+                    }
+                }
+                VirtualAddressOffset::zero()
+            }
             header::Type::SharedObject => {
-                // Find the highest virtual memory address and the biggest alignment.
-                let load_program_headers = elf_file
-                    .program_iter()
-                    .filter(|h| matches!(h.get_type(), Ok(Type::Load)));
-                let max_addr = load_program_headers
-                    .clone()
-                    .map(|h| h.virtual_addr() + h.mem_size())
-                    .max()
-                    .unwrap_or(0);
-                let min_addr = load_program_headers
-                    .clone()
-                    .map(|h| h.virtual_addr())
-                    .min()
-                    .unwrap_or(0);
                 let size = max_addr - min_addr;
                 let align = load_program_headers.map(|h| h.align()).max().unwrap_or(1);
 
-                let offset = used_entries.get_free_address(size, align).as_u64();
-                VirtualAddressOffset::new(i128::from(offset) - i128::from(min_addr))
+                let base = match kernel.config.mappings.kernel_base {
+                    Mapping::FixedAddress(base) => {
+                        if base % align.max(1) != 0 {
+                            return Err("kernel base address is not aligned to the alignment \
+                                of the kernel segments");
+                        }
+                        base
+                    }
+                    Mapping::Dynamic => used_entries.get_free_address(size, align).as_u64(),
+                };
+                VirtualAddressOffset::new(i128::from(base) - i128::from(min_addr))
             }
-            header::Type::Core => unimplemented!(),
-            header::Type::ProcessorSpecific(_) => unimplemented!(),
+            // rejected by `check_elf_header`
+            _ => unreachable!(),
         };
         log::info!(
             "virtual_address_offset: {:#x}",
             virtual_address_offset.virtual_address_offset()
         );
+        log::info!(
+            "Kernel base address: {:#x}",
+            virtual_address_offset + min_addr
+        );
 
         used_entries.mark_segments(elf_file.program_iter(), virtual_address_offset);
 
         header::sanity_check(&elf_file)?;
+        if kernel.config.disable_nx {
+            log::warn!(
+                "`disable_nx` is enabled: mapping all kernel segments writable and executable"
+            );
+        }
         let loader = Loader {
             elf_file,
             inner: Inner {
@@ -99,6 +144,7 @@ where
                 virtual_address_offset,
                 page_table,
                 frame_allocator,
+                disable_nx: kernel.config.disable_nx,
             },
         };
 
@@ -172,34 +218,78 @@ where
         let virt_start_addr = VirtAddr::new(self.virtual_address_offset + segment.virtual_addr());
         let start_page: Page = Page::containing_address(virt_start_addr);
 
-        let mut segment_flags = Flags::PRESENT;
-        if !segment.flags().is_execute() {
-            segment_flags |= Flags::NO_EXECUTE;
-        }
-        if segment.flags().is_write() {
+        // Kernel segments are mapped in every address space of the kernel, so mark them as
+        // global to keep their TLB entries across `CR3` reloads.
+        let mut segment_flags = Flags::PRESENT | Flags::GLOBAL;
+        if self.disable_nx {
             segment_flags |= Flags::WRITABLE;
+        } else {
+            if !segment.flags().is_execute() {
+                segment_flags |= mapping::supported_flags(Flags::NO_EXECUTE);
+            }
+            if segment.flags().is_write() {
+                segment_flags |= Flags::WRITABLE;
+            }
         }
 
+        // Use 2MiB pages for read-only segments whose file data and virtual address have the
+        // same offset into a 2MiB page. Writable segments are modified through `make_mut`,
+        // which copies single 4KiB frames, so they always use 4KiB pages. All other segments,
+        // including page-aligned ones with a smaller alignment, fall back to 4KiB pages, as do
+        // the parts of a huge-page segment that don't cover a full 2MiB page.
+        let use_huge_pages = !segment.flags().is_write()
+            && segment.align() >= Size2MiB::SIZE
+            && phys_start_addr.as_u64() % Size2MiB::SIZE
+                == virt_start_addr.as_u64() % Size2MiB::SIZE;
+
         // map all frames of the segment at the desired virtual address
-        for frame in PhysFrame::range_inclusive(start_frame, end_frame) {
-            let offset = frame - start_frame;
-            let page = start_page + offset;
-            let flusher = unsafe {
-                // The parent table flags need to be both readable and writable to
-                // support recursive page tables.
-                // See https://github.com/rust-osdev/bootloader/issues/443#issuecomment-2130010621
-                self.page_table
-                    .map_to_with_table_flags(
-                        page,
-                        frame,
-                        segment_flags,
-                        Flags::PRESENT | Flags::WRITABLE,
-                        self.frame_allocator,
-                    )
-                    .map_err(|_err| "map_to failed")?
-            };
-            // we operate on an inactive page table, so there's no need to flush anything
-            flusher.ignore();
+        let mut frame = start_frame;
+        if self.map_shared_page(start_page, start_frame, segment_flags, &segment)? {
+            frame += 1;
+        }
+        while frame <= end_frame {
+            let page = start_page + (frame - start_frame);
+            let huge_frame = PhysFrame::<Size2MiB>::from_start_address(frame.start_address());
+            let huge_page = Page::<Size2MiB>::from_start_address(page.start_address());
+            // The parent table flags need to be both readable and writable to
+            // support recursive page tables.
+            // See https://github.com/rust-osdev/bootloader/issues/443#issuecomment-2130010621
+            let table_flags = Flags::PRESENT | Flags::WRITABLE;
+            match (huge_frame, huge_page) {
+                (Ok(huge_frame), Ok(huge_page))
+                    if use_huge_pages && end_frame - frame >= HUGE_PAGE_FRAMES - 1 =>
+                {
+                    let flusher = unsafe {
+                        self.page_table
+                            .map_to_with_table_flags(
+                                huge_page,
+                                huge_frame,
+                                segment_flags,
+                                table_flags,
+                                self.frame_allocator,
+                            )
+                            .map_err(|_err| "map_to failed")?
+                    };
+                    // we operate on an inactive page table, so there's no need to flush anything
+                    flusher.ignore();
+                    frame += HUGE_PAGE_FRAMES;
+                }
+                _ => {
+                    let flusher = unsafe {
+                        self.page_table
+                            .map_to_with_table_flags(
+                                page,
+                                frame,
+                                segment_flags,
+                                table_flags,
+                                self.frame_allocator,
+                            )
+                            .map_err(|_err| "map_to failed")?
+                    };
+                    flusher.ignore();
+                    frame += 1;
+                }
+            }
         }
 
         // Handle .bss section (mem_size > file_size)
@@ -211,6 +301,68 @@ where
         Ok(())
     }
 
+    /// Handles the first page of a segment if it is already mapped because the previous
+    /// segment ends in the same page.
+    ///
+    /// The page stays mapped once, with the most permissive flags of both segments. If the
+    /// page is not backed by the frame that contains the data of this segment, e.g. because
+    /// the page was copied to zero the `.bss` part of the previous segment, the data of this
+    /// segment is copied into the mapped frame.
+    ///
+    /// Returns `false` if the page is not mapped yet.
+    fn map_shared_page(
+        &mut self,
+        page: Page,
+        frame: PhysFrame,
+        segment_flags: Flags,
+        segment: &ProgramHeader,
+    ) -> Result<bool, &'static str> {
+        let mapped_frame = match self.page_table.translate(page.start_address()) {
+            TranslateResult::Mapped {
+                frame: MappedFrame::Size4KiB(mapped_frame),
+                ..
+            } => mapped_frame,
+            TranslateResult::Mapped { .. } => {
+                return Err("kernel segment starts in a huge page of the previous segment")
+            }
+            TranslateResult::NotMapped => return Ok(false),
+            TranslateResult::InvalidFrameAddress(_) => unreachable!(),
+        };
+        log::info!("Segment shares its first page with the previous segment");
+
+        if mapped_frame != frame {
+            let mapped_frame = unsafe { self.make_mut(page) };
+            let virt_start_addr = self.virtual_address_offset + segment.virtual_addr();
+            let start = virt_start_addr % Size4KiB::SIZE;
+            let end = cmp::min(Size4KiB::SIZE, start + segment.file_size());
+            // both frames are identity-mapped
+            unsafe {
+                core::ptr::copy_nonoverlapping(
+                    (frame.start_address().as_u64() + start) as *const u8,
+                    (mapped_frame.start_address().as_u64() + start) as *mut u8,
+                    (end - start) as usize,
+                );
+            }
+        }
+
+        // `make_mut` might have changed the flags
+        let flags = match self.page_table.translate(page.start_address()) {
+            TranslateResult::Mapped { flags, .. } => flags,
+            _ => unreachable!(),
+        };
+        let mut shared_flags = flags | segment_flags;
+        if !(flags & segment_flags).contains(Flags::NO_EXECUTE) {
+            shared_flags.remove(Flags::NO_EXECUTE);
+        }
+        unsafe {
+            self.page_table
+                .update_flags(page, shared_flags)
+                .map_err(|_err| "failed to update the flags of a shared page")?
+                .ignore();
+        }
+        Ok(true)
+    }
+
     fn handle_bss_section(
         &mut self,
         segment: &ProgramHeader,
@@ -281,7 +433,10 @@ where
         let end_page = Page::containing_address(zero_end - 1u64);
         for page in Page::range_inclusive(start_page, end_page) {
             // allocate a new unused frame
-            let frame = self.frame_allocator.allocate_frame().unwrap();
+            let frame = self
+                .frame_allocator
+                .allocate_frame()
+                .ok_or("out of physical memory while mapping the zero-filled part of a segment")?;
 
             // zero frame, utilizing identity-mapping
             let frame_ptr = frame.start_address().as_u64() as *mut PageArray;
@@ -325,10 +480,11 @@ where
         let end_inclusive_page = Page::<Size4KiB>::containing_address(end_inclusive_addr);
 
         for page in start_page..=end_inclusive_page {
-            // Translate the virtual page to the physical frame.
+            // Translate the virtual page to the physical frame. The page might be part of a
+            // huge page, so translate its address instead of the 4KiB page.
             let phys_addr = self
                 .page_table
-                .translate_page(page)
+                .translate_addr(page.start_address())
                 .expect("address is not mapped to the kernel's memory space");
 
             // Figure out which address range we want to copy from the frame.
@@ -349,7 +505,7 @@ where
             let copy_len = end_inclusive_offset_in_frame - start_offset_in_frame + 1;
 
             // Calculate the physical addresses.
-            let start_phys_addr = phys_addr.start_address() + start_offset_in_frame;
+            let start_phys_addr = phys_addr + start_offset_in_frame;
 
             // These are the offsets from the start address. These correspond
             // to the destination indices in `buf`.
@@ -457,6 +613,7 @@ where
     /// ## Panics
     /// Panics if the page is not mapped in `self.page_table`.
     unsafe fn make_mut(&mut self, page: Page) -> PhysFrame {
+        unsafe { self.split_huge_page(page) };
         let (frame, flags) = match self.page_table.translate(page.start_address()) {
             TranslateResult::Mapped {
                 frame,
@@ -469,7 +626,7 @@ where
         let frame = if let MappedFrame::Size4KiB(frame) = frame {
             frame
         } else {
-            // We only map 4k pages.
+            // Huge pages were split above.
             unreachable!()
         };
 
@@ -479,7 +636,10 @@ where
         }
 
         // Allocate a new frame and copy the memory, utilizing that both frames are identity mapped.
-        let new_frame = self.frame_allocator.allocate_frame().unwrap();
+        let new_frame = self
+            .frame_allocator
+            .allocate_frame()
+            .expect("out of physical memory while copying a kernel page");
         let frame_ptr = frame.start_address().as_u64() as *const u8;
         let new_frame_ptr = new_frame.start_address().as_u64() as *mut u8;
         unsafe {
@@ -499,6 +659,47 @@ where
         new_frame
     }
 
+    /// Remaps the 2MiB page containing the given page with 4KiB pages, if there is one.
+    ///
+    /// The flags and the underlying frames stay the same, so this only changes the
+    /// granularity of the mapping.
+    ///
+    /// ## Safety
+    ///
+    /// The page table must not be active.
+    unsafe fn split_huge_page(&mut self, page: Page) {
+        let (frame, flags) = match self.page_table.translate(page.start_address()) {
+            TranslateResult::Mapped {
+                frame: MappedFrame::Size2MiB(frame),
+                offset: _,
+                flags,
+            } => (frame, flags),
+            _ => return,
+        };
+        let huge_page = Page::<Size2MiB>::containing_address(page.start_address());
+        self.page_table
+            .unmap(huge_page)
+            .expect("failed to unmap huge page")
+            .1
+            .ignore();
+        let start_frame = PhysFrame::<Size4KiB>::containing_address(frame.start_address());
+        let start_page = Page::<Size4KiB>::containing_address(huge_page.start_address());
+        for i in 0..HUGE_PAGE_FRAMES {
+            unsafe {
+                self.page_table
+                    .map_to_with_table_flags(
+                        start_page + i,
+                        start_frame + i,
+                        flags & !Flags::HUGE_PAGE,
+                        Flags::PRESENT | Flags::WRITABLE,
+                        self.frame_allocator,
+                    )
+                    .expect("failed to split huge page")
+                    .ignore();
+            }
+        }
+    }
+
     /// Cleans up the custom flags set by [`Inner::make_mut`].
     fn remove_copied_flags(&mut self, elf_file: &ElfFile) -> Result<(), &'static str> {
         for program_header in elf_file.program_iter() {
@@ -543,6 +744,7 @@ where
             start_addr: self.virtual_address_offset + segment.virtual_addr(),
             mem_size: segment.mem_size(),
             file_size: segment.file_size(),
+            align: segment.align(),
         })
     }
 
@@ -644,11 +846,9 @@ where
         rela: Rela<u64>,
         elf_file: &ElfFile,
     ) -> Result<(), &'static str> {
-        let symbol_idx = rela.get_symbol_table_index();
-        assert_eq!(
-            symbol_idx, 0,
-            "relocations using the symbol table are not supported"
-        );
+        if rela.get_symbol_table_index() != 0 {
+            return Err("relocations using the symbol table are not supported");
+        }
 
         match rela.get_type() {
             // R_AMD64_RELATIVE
@@ -670,7 +870,10 @@ where
                     self.copy_to(addr, &value.to_ne_bytes());
                 }
             }
-            ty => unimplemented!("relocation type {:x} not supported", ty),
+            ty => {
+                log::error!("relocation type {:#x} not supported", ty);
+                return Err("unsupported relocation type");
+            }
         }
 
         Ok(())
@@ -682,6 +885,9 @@ where
     /// need to be writable while applying relocations, but should never be
     /// written to after relocations have been applied.
     fn handle_relro_segment(&mut self, program_header: ProgramHeader) {
+        if self.disable_nx {
+            return;
+        }
         let start = self.virtual_address_offset + program_header.virtual_addr();
         let end = start + program_header.mem_size();
         let start = VirtAddr::new(start);
@@ -689,6 +895,9 @@ where
         let start_page = Page::containing_address(start);
         let end_page = Page::containing_address(end - 1u64);
         for page in Page::<Size4KiB>::range_inclusive(start_page, end_page) {
+            // The RELRO range doesn't need to be 2MiB aligned.
+            unsafe { self.split_huge_page(page) };
+
             // Translate the page and get the flags.
             let res = self.page_table.translate(page.start_address());
             let flags = match res {
@@ -730,24 +939,235 @@ fn check_is_in_load(elf_file: &ElfFile, virt_offset: u64) -> Result<(), &'static
     Err("offset is not in load segment")
 }
 
-/// Loads the kernel ELF file given in `bytes` in the given `page_table`.
+/// Describes a kernel that was loaded by [`load_kernel`] or [`load_kernel_from_slice`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoadedKernel {
+    /// The virtual address of the kernel entry point.
+    pub entry_point: VirtAddr,
+    /// The thread local storage template of the kernel, if it has a TLS segment.
+    pub tls_template: Option<TlsTemplate>,
+    /// The offset that was added to all virtual addresses of the ELF file.
+    ///
+    /// This is zero for kernels that are not position independent.
+    pub image_offset: VirtAddr,
+    /// The virtual address range of the loaded segments.
+    ///
+    /// The start of the range is the load base of the kernel. The range is empty if the
+    /// kernel has no segments with a non-zero memory size.
+    pub image_range: Range<VirtAddr>,
+    /// The frames allocated while loading the kernel.
+    ///
+    /// These frames back the `.bss` sections and the copied pages of the kernel segments, and
+    /// hold the page tables that map them.
+    pub frames: KernelFrames,
+}
+
+/// The physical frames allocated while loading the kernel, as a list of contiguous ranges.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KernelFrames {
+    ranges: [UsedMemorySlice; Self::MAX_RANGES],
+    len: usize,
+}
+
+impl KernelFrames {
+    /// The maximum number of ranges that are recorded.
+    ///
+    /// The bootloader allocates frames with a bump allocator, so a new range only starts when
+    /// the allocator moves on to the next usable region of the memory map. If there are more
+    /// ranges than this, the last range is extended to cover the remaining frames.
+    pub const MAX_RANGES: usize = 8;
+
+    const fn new() -> Self {
+        Self {
+            ranges: [UsedMemorySlice { start: 0, end: 0 }; Self::MAX_RANGES],
+            len: 0,
+        }
+    }
+
+    /// Returns the recorded ranges in allocation order.
+    pub fn ranges(&self) -> &[UsedMemorySlice] {
+        &self.ranges[..self.len]
+    }
+
+    /// Returns whether the given physical address lies in one of the recorded ranges.
+    pub fn contains(&self, addr: PhysAddr) -> bool {
+        let addr = addr.as_u64();
+        self.ranges()
+            .iter()
+            .any(|range| (range.start..range.end).contains(&addr))
+    }
+
+    fn record(&mut self, frame: PhysFrame) {
+        let start = frame.start_address().as_u64();
+        let end = start + frame.size();
+        if let Some(range) = self.ranges[..self.len]
+            .iter_mut()
+            .find(|range| range.end == start || range.start == end)
+        {
+            range.start = cmp::min(range.start, start);
+            range.end = cmp::max(range.end, end);
+        } else if self.len < Self::MAX_RANGES {
+            self.ranges[self.len] = UsedMemorySlice { start, end };
+            self.len += 1;
+        } else {
+            let last = &mut self.ranges[Self::MAX_RANGES - 1];
+            last.start = cmp::min(last.start, start);
+            last.end = cmp::max(last.end, end);
+        }
+    }
+}
+
+/// Wraps a frame allocator and records the frames that it hands out.
+struct RecordingFrameAllocator<'a, F> {
+    inner: &'a mut F,
+    frames: KernelFrames,
+}
+
+unsafe impl<F: FrameAllocator<Size4KiB>> FrameAllocator<Size4KiB>
+    for RecordingFrameAllocator<'_, F>
+{
+    fn allocate_frame(&mut self) -> Option<PhysFrame<Size4KiB>> {
+        let frame = self.inner.allocate_frame()?;
+        self.frames.record(frame);
+        Some(frame)
+    }
+}
+
+/// Loads the given kernel into the given `page_table`.
 ///
-/// Returns the kernel entry point address, it's thread local storage template (if any),
-/// and a structure describing which level 4 page table entries are in use.  
+/// Marks the level 4 page table entries used by the kernel in `used_entries`. There is no
+/// limit on the number of segments, apart from the 16-bit program header count of the ELF
+/// header. Extended program header numbering (`PN_XNUM`) is not supported.
 pub fn load_kernel(
     kernel: Kernel<'_>,
     page_table: &mut (impl MapperAllSizes + Translate),
     frame_allocator: &mut impl FrameAllocator<Size4KiB>,
     used_entries: &mut UsedLevel4Entries,
-) -> Result<(VirtAddr, VirtAddr, Option<TlsTemplate>), &'static str> {
-    let mut loader = Loader::new(kernel, page_table, frame_allocator, used_entries)?;
+) -> Result<LoadedKernel, &'static str> {
+    let mut frame_allocator = RecordingFrameAllocator {
+        inner: frame_allocator,
+        frames: KernelFrames::new(),
+    };
+    let mut loader = Loader::new(kernel, page_table, &mut frame_allocator, used_entries)?;
     let tls_template = loader.load_segments()?;
 
-    Ok((
-        VirtAddr::new(loader.inner.virtual_address_offset.virtual_address_offset() as u64),
-        loader.entry_point(),
+    let image_offset =
+        VirtAddr::new(loader.inner.virtual_address_offset.virtual_address_offset() as u64);
+    // virtual address range of the loaded segments, without the relocation offset
+    let (start, end) = loader
+        .elf_file
+        .program_iter()
+        .filter(|h| matches!(h.get_type(), Ok(Type::Load)) && h.mem_size() > 0)
+        .fold((u64::MAX, 0), |(start, end), h| {
+            (
+                start.min(h.virtual_addr()),
+                end.max(h.virtual_addr() + h.mem_size()),
+            )
+        });
+    let image_range = if start < end {
+        (image_offset + start)..(image_offset + end)
+    } else {
+        image_offset..image_offset
+    };
+
+    let entry_point = loader.entry_point();
+    Ok(LoadedKernel {
+        entry_point,
         tls_template,
-    ))
+        image_offset,
+        image_range,
+        frames: frame_allocator.frames,
+    })
+}
+
+/// Parses the kernel ELF file in `kernel_slice` and loads it into the given `page_table`.
+///
+/// This is a shorthand for [`Kernel::try_parse`] followed by [`load_kernel`], for front-ends
+/// that have the kernel file in memory, no matter where it came from. The loader maps the
+/// frames of the slice directly instead of copying them, so the slice must be page-aligned
+/// and its virtual addresses must be equal to its physical addresses. Frames for page tables,
+/// `.bss` sections, and copied pages are taken from `frame_allocator`, which must return
+/// identity-mapped frames as well.
+///
+/// ## Example
+///
+/// ```
+/// use bootloader_api::BootloaderConfig;
+/// use bootloader_x86_64_common::{
+///     level_4_entries::UsedLevel4Entries, load_kernel::load_kernel_from_slice,
+/// };
+/// use x86_64::{
+///     structures::paging::{FrameAllocator, OffsetPageTable, PageTable, PhysFrame, Size4KiB},
+///     PhysAddr, VirtAddr,
+/// };
+///
+/// // the kernel file must be page-aligned
+/// #[repr(C, align(4096))]
+/// struct Aligned<T: ?Sized>(T);
+/// static KERNEL: &Aligned<[u8]> = &Aligned(*include_bytes!("../tests/fixtures/kernel.elf"));
+///
+/// # /// Hands out frames of a leaked buffer, which is identity-mapped from the perspective
+/// # /// of an `OffsetPageTable` with offset 0.
+/// # struct BufferFrameAllocator(core::ops::Range<u64>);
+/// # unsafe impl FrameAllocator<Size4KiB> for BufferFrameAllocator {
+/// #     fn allocate_frame(&mut self) -> Option<PhysFrame> {
+/// #         let frame = PhysFrame::from_start_address(PhysAddr::new(self.0.start)).ok()?;
+/// #         self.0.start += 4096;
+/// #         (self.0.start <= self.0.end).then_some(frame)
+/// #     }
+/// # }
+/// # let buffer = Box::leak(Box::new(Aligned([0u8; 64 * 4096])));
+/// # let start = buffer.0.as_ptr() as u64;
+/// # let mut frame_allocator = BufferFrameAllocator(start..start + buffer.0.len() as u64);
+/// let mut page_table =
+///     unsafe { OffsetPageTable::new(Box::leak(Box::new(PageTable::new())), VirtAddr::new(0)) };
+/// let config = BootloaderConfig::new_default();
+/// let mut used_entries = UsedLevel4Entries::new(PhysAddr::new(0x1_0000_0000), 0, None, &config);
+///
+/// let kernel = load_kernel_from_slice(
+///     &KERNEL.0,
+///     &mut page_table,
+///     &mut frame_allocator,
+///     &mut used_entries,
+/// )
+/// .unwrap();
+/// assert_eq!(kernel.entry_point, VirtAddr::new(0x40_0000));
+/// assert_eq!(kernel.image_range.start, kernel.entry_point);
+/// assert_eq!(kernel.tls_template, None);
+/// ```
+pub fn load_kernel_from_slice(
+    kernel_slice: &[u8],
+    page_table: &mut (impl MapperAllSizes + Translate),
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+    used_entries: &mut UsedLevel4Entries,
+) -> Result<LoadedKernel, &'static str> {
+    let kernel = Kernel::try_parse(kernel_slice)?;
+    load_kernel(kernel, page_table, frame_allocator, used_entries)
+}
+
+/// Checks that the given ELF file is an x86_64 executable that can be loaded as kernel.
+///
+/// Only 64-bit little endian executables and shared objects (i.e. position independent
+/// executables) are supported. The program headers are checked for consistency too.
+pub fn check_elf_header(elf_file: &ElfFile) -> Result<(), &'static str> {
+    let header = elf_file.header;
+    if header.pt1.class() != header::Class::SixtyFour {
+        return Err("kernel is not a 64-bit ELF file");
+    }
+    if header.pt1.data() != header::Data::LittleEndian {
+        return Err("kernel ELF file is not little endian");
+    }
+    if header.pt2.machine().as_machine() != header::Machine::X86_64 {
+        return Err("kernel ELF file is not built for x86_64");
+    }
+    match header.pt2.type_().as_type() {
+        header::Type::Executable | header::Type::SharedObject => {}
+        _ => return Err("kernel ELF file is neither an executable nor a shared object"),
+    }
+    for program_header in elf_file.program_iter() {
+        program::sanity_check(program_header, elf_file)?;
+    }
+    Ok(())
 }
 
 /// A helper type used to offset virtual addresses for position independent
@@ -785,3 +1205,657 @@ impl Add<u64> for VirtualAddressOffset {
         .unwrap()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns a minimal 64-bit little endian x86_64 executable ELF header without
+    /// program or section headers.
+    fn elf_header() -> [u8; 64] {
+        let mut header = [0; 64];
+        header[..4].copy_from_slice(&header::MAGIC);
+        header[4] = 2; // class: 64-bit
+        header[5] = 1; // data: little endian
+        header[6] = 1; // version
+        header[16..18].copy_from_slice(&2u16.to_le_bytes()); // type: executable
+        header[18..20].copy_from_slice(&0x3eu16.to_le_bytes()); // machine: x86_64
+        header[20..24].copy_from_slice(&1u32.to_le_bytes()); // version
+        header[52..54].copy_from_slice(&64u16.to_le_bytes()); // header size
+        header[54..56].copy_from_slice(&56u16.to_le_bytes()); // program header size
+        header
+    }
+
+    /// Checks the given header bytes, which are copied into a suitably aligned buffer first.
+    fn check(bytes: &[u8]) -> Result<(), &'static str> {
+        let mut buffer = [0u64; 16];
+        let aligned = unsafe {
+            core::slice::from_raw_parts_mut(buffer.as_mut_ptr().cast::<u8>(), bytes.len())
+        };
+        aligned.copy_from_slice(bytes);
+        check_elf_header(&ElfFile::new(aligned)?)
+    }
+
+    #[test]
+    fn test_valid_header() {
+        assert_eq!(check(&elf_header()), Ok(()));
+    }
+
+    #[test]
+    fn test_bad_magic() {
+        let mut header = elf_header();
+        header[1] = b'X';
+        assert_eq!(check(&header), Err("Did not find ELF magic number"));
+    }
+
+    #[test]
+    fn test_wrong_class() {
+        let mut header = elf_header();
+        header[4] = 1;
+        assert_eq!(check(&header), Err("kernel is not a 64-bit ELF file"));
+    }
+
+    #[test]
+    fn test_wrong_endianness() {
+        let mut header = elf_header();
+        header[5] = 2;
+        assert_eq!(check(&header), Err("kernel ELF file is not little endian"));
+    }
+
+    #[test]
+    fn test_wrong_machine() {
+        let mut header = elf_header();
+        header[18..20].copy_from_slice(&0xb7u16.to_le_bytes()); // AArch64
+        assert_eq!(
+            check(&header),
+            Err("kernel ELF file is not built for x86_64")
+        );
+    }
+
+    #[test]
+    fn test_wrong_type() {
+        let mut header = elf_header();
+        header[16..18].copy_from_slice(&1u16.to_le_bytes()); // relocatable
+        assert_eq!(
+            check(&header),
+            Err("kernel ELF file is neither an executable nor a shared object")
+        );
+    }
+
+    #[test]
+    fn test_segment_out_of_range() {
+        let mut elf = [0u8; 128];
+        elf[..64].copy_from_slice(&elf_header());
+        elf[32..40].copy_from_slice(&64u64.to_le_bytes()); // program header offset
+        elf[56..58].copy_from_slice(&1u16.to_le_bytes()); // program header count
+        let segment = &mut elf[64..120];
+        segment[..4].copy_from_slice(&1u32.to_le_bytes()); // type: load
+        segment[32..40].copy_from_slice(&0x1000u64.to_le_bytes()); // file size
+        assert_eq!(check(&elf), Err("entry point out of range"));
+    }
+
+    /// Allocates frames from a buffer on the heap, which is identity-mapped from the
+    /// perspective of an `OffsetPageTable` with offset 0.
+    struct TestFrameAllocator {
+        next: u64,
+        end: u64,
+    }
+
+    unsafe impl FrameAllocator<Size4KiB> for TestFrameAllocator {
+        fn allocate_frame(&mut self) -> Option<PhysFrame> {
+            if self.next == self.end {
+                return None;
+            }
+            let frame = PhysFrame::from_start_address(PhysAddr::new(self.next)).unwrap();
+            self.next += Size4KiB::SIZE;
+            Some(frame)
+        }
+    }
+
+    /// Allocates a zeroed buffer with the given size and alignment that is never freed.
+    fn leak_buffer(size: usize, align: usize) -> &'static mut [u8] {
+        let layout = std::alloc::Layout::from_size_align(size, align).unwrap();
+        unsafe { core::slice::from_raw_parts_mut(std::alloc::alloc_zeroed(layout), size) }
+    }
+
+    /// Writes a program header with the given values to the `index`th entry of the program
+    /// header table, which starts directly after the ELF header.
+    #[allow(clippy::too_many_arguments)]
+    fn write_segment(
+        elf: &mut [u8],
+        index: usize,
+        flags: u32,
+        offset: u64,
+        addr: u64,
+        file_size: u64,
+        mem_size: u64,
+        align: u64,
+    ) {
+        let start = 64 + index * 56;
+        let segment = &mut elf[start..start + 56];
+        segment[..4].copy_from_slice(&1u32.to_le_bytes()); // type: load
+        segment[4..8].copy_from_slice(&flags.to_le_bytes());
+        segment[8..16].copy_from_slice(&offset.to_le_bytes());
+        segment[16..24].copy_from_slice(&addr.to_le_bytes()); // virtual address
+        segment[24..32].copy_from_slice(&addr.to_le_bytes()); // physical address
+        segment[32..40].copy_from_slice(&file_size.to_le_bytes());
+        segment[40..48].copy_from_slice(&mem_size.to_le_bytes());
+        segment[48..56].copy_from_slice(&align.to_le_bytes());
+    }
+
+    /// Writes an ELF header with the given entry point and number of program headers.
+    fn write_header(elf: &mut [u8], entry_point: u64, segment_count: u16) {
+        elf[..64].copy_from_slice(&elf_header());
+        elf[24..32].copy_from_slice(&entry_point.to_le_bytes());
+        elf[32..40].copy_from_slice(&64u64.to_le_bytes()); // program header offset
+        elf[56..58].copy_from_slice(&segment_count.to_le_bytes());
+    }
+
+    /// Loads the given ELF file into a new page table with offset 0 and returns the page table,
+    /// the range of frames that the frame allocator could hand out, and the loaded kernel.
+    fn load(
+        elf: &'static [u8],
+    ) -> (
+        x86_64::structures::paging::OffsetPageTable<'static>,
+        core::ops::Range<u64>,
+        LoadedKernel,
+    ) {
+        load_with_config(elf, bootloader_api::BootloaderConfig::new_default()).unwrap()
+    }
+
+    /// Like [`load`], but uses the given config for the kernel and returns loading errors.
+    #[allow(clippy::type_complexity)]
+    fn load_with_config(
+        elf: &'static [u8],
+        config: bootloader_api::BootloaderConfig,
+    ) -> Result<
+        (
+            x86_64::structures::paging::OffsetPageTable<'static>,
+            core::ops::Range<u64>,
+            LoadedKernel,
+        ),
+        &'static str,
+    > {
+        use x86_64::structures::paging::{OffsetPageTable, PageTable};
+
+        let kernel = Kernel {
+            elf: ElfFile::new(elf).unwrap(),
+            config,
+            start_address: elf.as_ptr(),
+            len: elf.len(),
+        };
+
+        let frames = leak_buffer(0x10_0000, Size4KiB::SIZE as usize);
+        // The frame allocator doesn't zero frames, so simulate memory that still contains
+        // data from the firmware. Page tables and `.bss` must be zeroed by whoever uses them.
+        frames.fill(0xff);
+        let frames = frames.as_ptr() as u64..frames.as_ptr() as u64 + frames.len() as u64;
+        let mut frame_allocator = TestFrameAllocator {
+            next: frames.start,
+            end: frames.end,
+        };
+        let level_4_table = Box::leak(Box::new(PageTable::new()));
+        let mut page_table = unsafe { OffsetPageTable::new(level_4_table, VirtAddr::new(0)) };
+        let mut used_entries =
+            UsedLevel4Entries::new(PhysAddr::new(0x1_0000_0000), 0, None, &config);
+
+        let kernel = load_kernel(
+            kernel,
+            &mut page_table,
+            &mut frame_allocator,
+            &mut used_entries,
+        )?;
+        Ok((page_table, frames, kernel))
+    }
+
+    /// Returns the frame and flags of the 4KiB page that contains the given address.
+    fn translate_4kib(page_table: &impl Translate, addr: u64) -> (u64, Flags) {
+        match page_table.translate(VirtAddr::new(addr)) {
+            TranslateResult::Mapped {
+                frame: MappedFrame::Size4KiB(frame),
+                flags,
+                ..
+            } => (frame.start_address().as_u64(), flags),
+            other => panic!("{addr:#x} is not mapped with a 4KiB page: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_code_and_data_segments() {
+        const CODE_ADDR: u64 = 0x40_0000;
+        const DATA_ADDR: u64 = 0x60_0000;
+
+        // xmas-elf requires that segments end before the end of the file
+        let elf = leak_buffer(0x3000, Size4KiB::SIZE as usize);
+        write_header(elf, CODE_ADDR, 2);
+        // read + execute
+        write_segment(elf, 0, 5, 0, CODE_ADDR, 0x1000, 0x1000, 0x1000);
+        // read + write, followed by two pages of .bss
+        write_segment(elf, 1, 6, 0x1000, DATA_ADDR, 0x1000, 0x3000, 0x1000);
+        elf[0x1000..0x2000].fill(0xaa);
+        let elf_addr = elf.as_ptr() as u64;
+
+        let (page_table, frames, kernel) = load(elf);
+
+        let (frame, flags) = translate_4kib(&page_table, CODE_ADDR);
+        assert_eq!(frame, elf_addr);
+        assert!(!flags.contains(Flags::WRITABLE));
+        assert!(!flags.contains(Flags::NO_EXECUTE));
+        assert!(flags.contains(Flags::GLOBAL));
+
+        let (frame, flags) = translate_4kib(&page_table, DATA_ADDR);
+        assert_eq!(frame, elf_addr + 0x1000);
+        assert!(flags.contains(Flags::WRITABLE | Flags::NO_EXECUTE | Flags::GLOBAL));
+
+        for addr in [DATA_ADDR + 0x1000, DATA_ADDR + 0x2000] {
+            let (frame, flags) = translate_4kib(&page_table, addr);
+            assert!(frames.contains(&frame), ".bss is not backed by a new frame");
+            assert!(
+                kernel.frames.contains(PhysAddr::new(frame)),
+                ".bss frame is not reported as a kernel frame"
+            );
+            assert!(flags.contains(Flags::WRITABLE | Flags::NO_EXECUTE | Flags::GLOBAL));
+            let bytes = unsafe { core::slice::from_raw_parts(frame as *const u8, 0x1000) };
+            assert!(bytes.iter().all(|&b| b == 0), ".bss is not zeroed");
+        }
+        assert!(page_table
+            .translate_addr(VirtAddr::new(DATA_ADDR + 0x3000))
+            .is_none());
+    }
+
+    #[test]
+    fn test_many_segments() {
+        const BASE_ADDR: u64 = 0x40_0000;
+        const SEGMENT_COUNT: usize = 16;
+
+        let elf = leak_buffer((SEGMENT_COUNT + 2) * 0x1000, Size4KiB::SIZE as usize);
+        write_header(elf, BASE_ADDR, SEGMENT_COUNT as u16);
+        for i in 0..SEGMENT_COUNT {
+            let offset = (i as u64 + 1) * 0x1000;
+            // alternate between read + execute and read + write, with unmapped gaps between
+            // the segments
+            let flags = if i % 2 == 0 { 5 } else { 6 };
+            let addr = BASE_ADDR + i as u64 * 0x2000;
+            write_segment(elf, i, flags, offset, addr, 0x1000, 0x1000, 0x1000);
+        }
+        let elf_addr = elf.as_ptr() as u64;
+
+        let (page_table, _, _) = load(elf);
+
+        for i in 0..SEGMENT_COUNT {
+            let addr = BASE_ADDR + i as u64 * 0x2000;
+            let (frame, flags) = translate_4kib(&page_table, addr);
+            assert_eq!(frame, elf_addr + (i as u64 + 1) * 0x1000);
+            assert_eq!(flags.contains(Flags::WRITABLE), i % 2 == 1);
+            assert!(page_table
+                .translate_addr(VirtAddr::new(addr + 0x1000))
+                .is_none());
+        }
+    }
+
+    #[test]
+    fn test_new_page_tables_are_zeroed() {
+        use x86_64::structures::paging::PageTable;
+
+        const CODE_ADDR: u64 = 0x40_0000;
+
+        let elf = leak_buffer(0x2000, Size4KiB::SIZE as usize);
+        write_header(elf, CODE_ADDR, 1);
+        write_segment(elf, 0, 5, 0, CODE_ADDR, 0x1000, 0x1000, 0x1000);
+
+        let (mut page_table, frames, _) = load(elf);
+
+        // walk the tables of the single mapped page, none of them may contain stale entries
+        let mut table: &PageTable = page_table.level_4_table();
+        let page = Page::<Size4KiB>::containing_address(VirtAddr::new(CODE_ADDR));
+        let indices = [
+            page.p4_index(),
+            page.p3_index(),
+            page.p2_index(),
+            page.p1_index(),
+        ];
+        for (level, index) in indices.into_iter().enumerate() {
+            let used = table.iter().filter(|entry| !entry.is_unused()).count();
+            assert_eq!(used, 1, "stale entries in level {} table", 4 - level);
+            let addr = table[index].addr().as_u64();
+            if level < 3 {
+                assert!(frames.contains(&addr));
+                table = unsafe { &*(addr as *const PageTable) };
+            }
+        }
+    }
+
+    #[test]
+    fn test_fixed_kernel_base() {
+        const CODE_ADDR: u64 = 0x40_0000;
+        const BASE: u64 = 0x1000_0000_0000;
+
+        let elf = leak_buffer(0x2000, Size4KiB::SIZE as usize);
+        write_header(elf, CODE_ADDR, 1);
+        elf[16..18].copy_from_slice(&3u16.to_le_bytes()); // type: shared object
+        write_segment(elf, 0, 5, 0, CODE_ADDR, 0x1000, 0x1000, 0x1000);
+        let elf_addr = elf.as_ptr() as u64;
+
+        let mut config = bootloader_api::BootloaderConfig::new_default();
+        config.mappings.kernel_base = Mapping::FixedAddress(BASE);
+        let (page_table, _, _) = load_with_config(elf, config).unwrap();
+        assert_eq!(translate_4kib(&page_table, BASE).0, elf_addr);
+
+        config.mappings.kernel_base = Mapping::FixedAddress(BASE + 0x800);
+        assert!(load_with_config(elf, config).is_err());
+    }
+
+    #[test]
+    fn test_fixed_kernel_base_executable() {
+        const CODE_ADDR: u64 = 0x40_0000;
+
+        let elf = leak_buffer(0x2000, Size4KiB::SIZE as usize);
+        write_header(elf, CODE_ADDR, 1);
+        write_segment(elf, 0, 5, 0, CODE_ADDR, 0x1000, 0x1000, 0x1000);
+
+        let mut config = bootloader_api::BootloaderConfig::new_default();
+        config.mappings.kernel_base = Mapping::FixedAddress(CODE_ADDR);
+        assert!(load_with_config(elf, config).is_ok());
+
+        config.mappings.kernel_base = Mapping::FixedAddress(CODE_ADDR + 0x1000);
+        assert!(load_with_config(elf, config).is_err());
+    }
+
+    #[test]
+    fn test_disable_nx() {
+        const CODE_ADDR: u64 = 0x40_0000;
+        const DATA_ADDR: u64 = 0x60_0000;
+
+        let elf = leak_buffer(0x3000, Size4KiB::SIZE as usize);
+        write_header(elf, CODE_ADDR, 2);
+        write_segment(elf, 0, 5, 0, CODE_ADDR, 0x1000, 0x1000, 0x1000);
+        write_segment(elf, 1, 6, 0x1000, DATA_ADDR, 0x1000, 0x2000, 0x1000);
+
+        let mut config = bootloader_api::BootloaderConfig::new_default();
+        config.disable_nx = true;
+        let (page_table, _, _) = load_with_config(elf, config).unwrap();
+
+        for addr in [CODE_ADDR, DATA_ADDR, DATA_ADDR + 0x1000] {
+            let (_, flags) = translate_4kib(&page_table, addr);
+            assert!(flags.contains(Flags::WRITABLE));
+            assert!(!flags.contains(Flags::NO_EXECUTE));
+        }
+    }
+
+    #[test]
+    fn test_partial_bss_page() {
+        const CODE_ADDR: u64 = 0x40_0000;
+
+        let elf = leak_buffer(0x2000, Size4KiB::SIZE as usize);
+        write_header(elf, CODE_ADDR, 1);
+        // read + write + execute, the last 0x800 bytes of the page are .bss
+        write_segment(elf, 0, 7, 0x1000, CODE_ADDR, 0x800, 0x1000, 0x1000);
+        elf[0x1000..0x2000].fill(0xaa);
+        let elf_addr = elf.as_ptr() as u64;
+
+        let (page_table, frames, _) = load(elf);
+
+        // the page is copied so that zeroing the .bss doesn't modify the ELF file
+        let (frame, flags) = translate_4kib(&page_table, CODE_ADDR);
+        assert!(frames.contains(&frame));
+        assert!(!flags.contains(Flags::BIT_9), "copied flag was not removed");
+        let bytes = unsafe { core::slice::from_raw_parts(frame as *const u8, 0x1000) };
+        assert!(bytes[..0x800].iter().all(|&b| b == 0xaa));
+        assert!(bytes[0x800..].iter().all(|&b| b == 0));
+        let original =
+            unsafe { core::slice::from_raw_parts((elf_addr + 0x1000) as *const u8, 0x1000) };
+        assert!(original.iter().all(|&b| b == 0xaa));
+    }
+
+    #[test]
+    fn test_tls_template() {
+        const CODE_ADDR: u64 = 0x40_0000;
+        const TLS_ADDR: u64 = 0x40_0100;
+
+        let elf = leak_buffer(0x2000, Size4KiB::SIZE as usize);
+        write_header(elf, CODE_ADDR, 2);
+        write_segment(elf, 0, 4, 0, CODE_ADDR, 0x1000, 0x1000, 0x1000);
+        // 0x20 bytes of `.tdata`, followed by 0x30 bytes of `.tbss`
+        write_segment(elf, 1, 4, 0x100, TLS_ADDR, 0x20, 0x50, 0x10);
+        elf[64 + 56..64 + 56 + 4].copy_from_slice(&7u32.to_le_bytes()); // type: TLS
+
+        let (_, _, kernel) = load(elf);
+        assert_eq!(
+            kernel.tls_template,
+            Some(TlsTemplate {
+                start_addr: TLS_ADDR,
+                file_size: 0x20,
+                mem_size: 0x50,
+                align: 0x10,
+            })
+        );
+    }
+
+    #[test]
+    fn test_huge_page_segment() {
+        const SEGMENT_OFFSET: u64 = 0x20_0000;
+        const SEGMENT_ADDR: u64 = 0x40_0000;
+        // one huge page followed by one 4KiB page
+        const SEGMENT_SIZE: u64 = Size2MiB::SIZE + Size4KiB::SIZE;
+
+        let elf = leak_buffer(0x60_0000, Size2MiB::SIZE as usize);
+        write_header(elf, SEGMENT_ADDR, 1);
+        // read + execute
+        write_segment(
+            elf,
+            0,
+            5,
+            SEGMENT_OFFSET,
+            SEGMENT_ADDR,
+            SEGMENT_SIZE,
+            SEGMENT_SIZE,
+            Size2MiB::SIZE,
+        );
+        let elf_addr = elf.as_ptr() as u64;
+
+        let (page_table, _, _) = load(elf);
+
+        match page_table.translate(VirtAddr::new(SEGMENT_ADDR)) {
+            TranslateResult::Mapped {
+                frame: MappedFrame::Size2MiB(frame),
+                flags,
+                ..
+            } => {
+                assert_eq!(frame.start_address().as_u64(), elf_addr + SEGMENT_OFFSET);
+                assert!(!flags.contains(Flags::WRITABLE));
+                assert!(!flags.contains(Flags::NO_EXECUTE));
+            }
+            other => panic!("segment start is not mapped with a 2MiB page: {other:?}"),
+        }
+        let (frame, _) = translate_4kib(&page_table, SEGMENT_ADDR + Size2MiB::SIZE);
+        assert_eq!(frame, elf_addr + SEGMENT_OFFSET + Size2MiB::SIZE);
+    }
+
+    #[test]
+    fn test_segments_sharing_a_page() {
+        const CODE_ADDR: u64 = 0x40_0000;
+        const RODATA_ADDR: u64 = 0x40_0800;
+
+        let elf = leak_buffer(0x3000, Size4KiB::SIZE as usize);
+        write_header(elf, CODE_ADDR, 2);
+        // `.text` ends and `.rodata` starts in the middle of the same page
+        write_segment(elf, 0, 5, 0x1000, CODE_ADDR, 0x800, 0x800, 0x1000);
+        write_segment(elf, 1, 4, 0x1800, RODATA_ADDR, 0x800, 0x800, 0x1000);
+        let elf_addr = elf.as_ptr() as u64;
+
+        let (page_table, _, _) = load(elf);
+
+        // the page is mapped once and stays executable
+        let (frame, flags) = translate_4kib(&page_table, RODATA_ADDR);
+        assert_eq!(frame, elf_addr + 0x1000);
+        assert!(!flags.contains(Flags::WRITABLE));
+        assert!(!flags.contains(Flags::NO_EXECUTE));
+    }
+
+    #[test]
+    fn test_segment_sharing_a_page_with_bss() {
+        const DATA_ADDR: u64 = 0x40_0000;
+        const RODATA_ADDR: u64 = 0x40_0c00;
+
+        let elf = leak_buffer(0x4000, Size4KiB::SIZE as usize);
+        write_header(elf, DATA_ADDR, 2);
+        // read + write with 0x400 bytes of .bss, followed by read-only data in the same page
+        // whose file data is stored in a different frame
+        write_segment(elf, 0, 6, 0x1000, DATA_ADDR, 0x800, 0xc00, 0x1000);
+        write_segment(elf, 1, 4, 0x2c00, RODATA_ADDR, 0x400, 0x400, 0x1000);
+        elf[0x1000..0x2000].fill(0xaa);
+        elf[0x2000..0x3000].fill(0xbb);
+
+        let (page_table, frames, _) = load(elf);
+
+        let (frame, flags) = translate_4kib(&page_table, RODATA_ADDR);
+        assert!(frames.contains(&frame));
+        assert!(flags.contains(Flags::WRITABLE | Flags::NO_EXECUTE));
+        assert!(!flags.contains(Flags::BIT_9), "copied flag was not removed");
+        let bytes = unsafe { core::slice::from_raw_parts(frame as *const u8, 0x1000) };
+        assert!(bytes[..0x800].iter().all(|&b| b == 0xaa));
+        assert!(bytes[0x800..0xc00].iter().all(|&b| b == 0));
+        assert!(bytes[0xc00..].iter().all(|&b| b == 0xbb));
+    }
+
+    #[test]
+    fn test_overlapping_segments() {
+        const CODE_ADDR: u64 = 0x40_0000;
+
+        let elf = leak_buffer(0x2000, Size4KiB::SIZE as usize);
+        write_header(elf, CODE_ADDR, 2);
+        write_segment(elf, 0, 5, 0, CODE_ADDR, 0x1000, 0x1000, 0x1000);
+        write_segment(elf, 1, 4, 0x800, CODE_ADDR + 0x800, 0x800, 0x800, 0x1000);
+
+        let config = bootloader_api::BootloaderConfig::new_default();
+        assert_eq!(
+            load_with_config(elf, config).err(),
+            Some("kernel segments overlap or are not sorted by virtual address")
+        );
+    }
+
+    const FIXTURE_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/kernel.elf");
+
+    /// Builds the fixture kernel at `tests/fixtures/kernel.elf`, which is used by the example
+    /// of [`load_kernel_from_slice`].
+    ///
+    /// It consists of a single segment at `0x400000` containing an endless `hlt` loop, and a
+    /// `.bootloader-config` section with the default config.
+    fn fixture_kernel() -> Vec<u8> {
+        const CODE_OFFSET: usize = 0x1000;
+        const CONFIG_OFFSET: usize = 0x1010;
+        const SHSTRTAB: &[u8] = b"\0.bootloader-config\0.shstrtab\0";
+
+        let code = [0xf4, 0xeb, 0xfd]; // hlt; jmp -3
+        let config = bootloader_api::BootloaderConfig::new_default().serialize();
+        let shstrtab_offset = CONFIG_OFFSET + config.len();
+        let section_headers_offset = (shstrtab_offset + SHSTRTAB.len()).next_multiple_of(8);
+
+        let mut elf = vec![0; section_headers_offset + 3 * 64];
+        write_header(&mut elf, 0x40_0000, 1);
+        elf[40..48].copy_from_slice(&(section_headers_offset as u64).to_le_bytes());
+        elf[58..60].copy_from_slice(&64u16.to_le_bytes()); // section header size
+        elf[60..62].copy_from_slice(&3u16.to_le_bytes()); // section header count
+        elf[62..64].copy_from_slice(&2u16.to_le_bytes()); // section name table index
+        write_segment(
+            &mut elf,
+            0,
+            0b101,
+            CODE_OFFSET as u64,
+            0x40_0000,
+            3,
+            3,
+            0x1000,
+        );
+        elf[CODE_OFFSET..][..code.len()].copy_from_slice(&code);
+        elf[CONFIG_OFFSET..][..config.len()].copy_from_slice(&config);
+        elf[shstrtab_offset..][..SHSTRTAB.len()].copy_from_slice(SHSTRTAB);
+
+        // the first section header is the null section
+        let sections = [
+            (1, 1, CONFIG_OFFSET, config.len()), // .bootloader-config: progbits
+            (20, 3, shstrtab_offset, SHSTRTAB.len()), // .shstrtab: strtab
+        ];
+        for (i, (name, ty, offset, size)) in sections.into_iter().enumerate() {
+            let header = &mut elf[section_headers_offset + (i + 1) * 64..][..64];
+            header[..4].copy_from_slice(&(name as u32).to_le_bytes());
+            header[4..8].copy_from_slice(&(ty as u32).to_le_bytes());
+            header[24..32].copy_from_slice(&(offset as u64).to_le_bytes());
+            header[32..40].copy_from_slice(&(size as u64).to_le_bytes());
+            header[48..56].copy_from_slice(&1u64.to_le_bytes()); // alignment
+        }
+        elf
+    }
+
+    #[test]
+    fn test_fixture_up_to_date() {
+        let fixture = std::fs::read(FIXTURE_PATH).unwrap();
+        assert!(
+            fixture == fixture_kernel(),
+            "fixture kernel is outdated, regenerate it with \
+            `cargo test -p bootloader-x86_64-common -- --ignored regenerate_fixture`"
+        );
+    }
+
+    #[test]
+    #[ignore]
+    fn regenerate_fixture() {
+        std::fs::create_dir_all(std::path::Path::new(FIXTURE_PATH).parent().unwrap()).unwrap();
+        std::fs::write(FIXTURE_PATH, fixture_kernel()).unwrap();
+    }
+
+    #[test]
+    fn test_try_parse() {
+        let elf = leak_buffer(0x3000, 0x1000);
+        elf.copy_from_slice(&{
+            let mut fixture = fixture_kernel();
+            fixture.resize(0x3000, 0);
+            fixture
+        });
+        let kernel = Kernel::try_parse(elf).unwrap();
+        assert_eq!(
+            kernel.config,
+            bootloader_api::BootloaderConfig::new_default()
+        );
+
+        // without section headers, the config section can't be found
+        elf[60..62].copy_from_slice(&0u16.to_le_bytes());
+        assert_eq!(
+            Kernel::try_parse(elf).err(),
+            Some("bootloader config section not found; kernel must be compiled against bootloader_api")
+        );
+    }
+
+    #[test]
+    fn test_kernel_frames() {
+        let frame = |addr| PhysFrame::containing_address(PhysAddr::new(addr));
+        let range = |start, end| UsedMemorySlice { start, end };
+
+        let mut frames = KernelFrames::new();
+        for addr in [0x1000, 0x2000, 0x5000, 0x6000, 0x3000] {
+            frames.record(frame(addr));
+        }
+        assert_eq!(
+            frames.ranges(),
+            [range(0x1000, 0x4000), range(0x5000, 0x7000)]
+        );
+        assert!(frames.contains(PhysAddr::new(0x3fff)));
+        assert!(!frames.contains(PhysAddr::new(0x4000)));
+
+        // once all ranges are used, the last one is extended to cover further frames
+        let mut frames = KernelFrames::new();
+        for i in 0..KernelFrames::MAX_RANGES as u64 + 1 {
+            frames.record(frame(0x2000 * (i + 1)));
+        }
+        let ranges = frames.ranges();
+        assert_eq!(ranges.len(), KernelFrames::MAX_RANGES);
+        assert_eq!(ranges[0], range(0x2000, 0x3000));
+        assert_eq!(
+            ranges[KernelFrames::MAX_RANGES - 1],
+            range(
+                0x2000 * KernelFrames::MAX_RANGES as u64,
+                0x2000 * (KernelFrames::MAX_RANGES as u64 + 1) + 0x1000
+            )
+        );
+    }
+}
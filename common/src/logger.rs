@@ -2,7 +2,7 @@ use crate::{framebuffer::FrameBufferWriter, serial::SerialPort};
 use bootloader_api::info::FrameBufferInfo;
 use conquer_once::spin::OnceCell;
 use core::fmt::Write;
-use spinning_top::Spinlock;
+use spinning_top::{Spinlock, SpinlockGuard};
 
 /// The global logger instance used for the `log` crate.
 pub static LOGGER: OnceCell<LockedLogger> = OnceCell::uninit();
@@ -16,14 +16,15 @@ pub struct LockedLogger {
 impl LockedLogger {
     /// Create a new instance that logs to the given framebuffer.
     pub fn new(
-        framebuffer: &'static mut [u8],
-        info: FrameBufferInfo,
+        framebuffer: Option<(&'static mut [u8], FrameBufferInfo)>,
         frame_buffer_logger_status: bool,
         serial_logger_status: bool,
     ) -> Self {
-        let framebuffer = match frame_buffer_logger_status {
-            true => Some(Spinlock::new(FrameBufferWriter::new(framebuffer, info))),
-            false => None,
+        let framebuffer = match (framebuffer, frame_buffer_logger_status) {
+            (Some((framebuffer, info)), true) => {
+                Some(Spinlock::new(FrameBufferWriter::new(framebuffer, info)))
+            }
+            _ => None,
         };
 
         let serial = match serial_logger_status {
@@ -37,6 +38,21 @@ impl LockedLogger {
         }
     }
 
+    /// Writes the given string to the serial port, without any log level prefix.
+    ///
+    /// Does nothing if serial logging is disabled. Used for progress output during
+    /// long-running operations.
+    pub fn write_serial(&self, s: &str) {
+        if let Some(mut serial) = self.serial() {
+            serial.write_str(s).unwrap();
+        }
+    }
+
+    /// Locks and returns the serial port, or `None` if serial logging is disabled.
+    pub fn serial(&self) -> Option<SpinlockGuard<'_, SerialPort>> {
+        self.serial.as_ref().map(Spinlock::lock)
+    }
+
     /// Force-unlocks the logger to prevent a deadlock.
     ///
     /// ## Safety
@@ -48,6 +64,10 @@ impl LockedLogger {
         if let Some(serial) = &self.serial {
             unsafe { serial.force_unlock() };
         }
+        #[cfg(feature = "boot-log")]
+        unsafe {
+            crate::boot_log::force_unlock()
+        };
     }
 }
 
@@ -65,6 +85,8 @@ impl log::Log for LockedLogger {
             let mut serial = serial.lock();
             writeln!(serial, "{:5}: {}", record.level(), record.args()).unwrap();
         }
+        #[cfg(feature = "boot-log")]
+        crate::boot_log::record(record);
     }
 
     fn flush(&self) {}
@@ -0,0 +1,219 @@
+pub use bootloader_boot_config::COMPRESSED_KERNEL_MAGIC as MAGIC;
+
+/// Length of the header of a compressed kernel image.
+///
+/// The header consists of the [`MAGIC`] bytes, followed by the length of the decompressed
+/// image as a little-endian `u64`. The rest of the image is a single LZ4 block.
+pub const HEADER_LEN: usize = 12;
+
+/// The minimum match length of the LZ4 block format.
+const MIN_MATCH: usize = 4;
+
+/// Returns the length of the decompressed kernel if `image` is a compressed kernel image.
+///
+/// Returns `None` if the image does not start with the [`MAGIC`] bytes.
+pub fn decompressed_len(image: &[u8]) -> Option<usize> {
+    if image.get(..MAGIC.len())? != MAGIC {
+        return None;
+    }
+    let len = image.get(MAGIC.len()..HEADER_LEN)?;
+    usize::try_from(u64::from_le_bytes(len.try_into().unwrap())).ok()
+}
+
+/// Decompresses the given compressed kernel image into `out`.
+///
+/// The length of `out` must be equal to the length returned by [`decompressed_len`].
+pub fn decompress(image: &[u8], out: &mut [u8]) -> Result<(), &'static str> {
+    if decompressed_len(image) != Some(out.len()) {
+        return Err("decompressed length of the kernel image does not match the output buffer");
+    }
+    let len = decompress_block(&image[HEADER_LEN..], out)?;
+    if len != out.len() {
+        return Err("compressed kernel image is shorter than its decompressed length");
+    }
+    Ok(())
+}
+
+/// Decompresses a single LZ4 block into `out` and returns the number of written bytes.
+fn decompress_block(mut input: &[u8], out: &mut [u8]) -> Result<usize, &'static str> {
+    const TRUNCATED: &str = "compressed kernel image is truncated";
+    const OVERFLOW: &str = "compressed kernel image is longer than its decompressed length";
+
+    let mut pos = 0;
+    loop {
+        let (&token, rest) = input.split_first().ok_or(TRUNCATED)?;
+        input = rest;
+
+        let literals_len = read_length(&mut input, usize::from(token >> 4))?;
+        let literals = input.get(..literals_len).ok_or(TRUNCATED)?;
+        out.get_mut(pos..pos + literals_len)
+            .ok_or(OVERFLOW)?
+            .copy_from_slice(literals);
+        input = &input[literals_len..];
+        pos += literals_len;
+
+        // the last sequence of a block only consists of literals
+        if input.is_empty() {
+            return Ok(pos);
+        }
+
+        let offset = input.get(..2).ok_or(TRUNCATED)?;
+        let offset = usize::from(u16::from_le_bytes(offset.try_into().unwrap()));
+        input = &input[2..];
+        if offset == 0 || offset > pos {
+            return Err("compressed kernel image contains an invalid match offset");
+        }
+        let match_len = read_length(&mut input, usize::from(token & 0xf))? + MIN_MATCH;
+        if pos + match_len > out.len() {
+            return Err(OVERFLOW);
+        }
+        // copy byte by byte because the match may overlap with the bytes it produces
+        for i in pos..pos + match_len {
+            out[i] = out[i - offset];
+        }
+        pos += match_len;
+    }
+}
+
+/// Reads the extension bytes of a literal or match length that starts with the given 4-bit
+/// value from the token.
+fn read_length(input: &mut &[u8], mut len: usize) -> Result<usize, &'static str> {
+    if len == 0xf {
+        loop {
+            let (&byte, rest) = input
+                .split_first()
+                .ok_or("compressed kernel image is truncated")?;
+            *input = rest;
+            len += usize::from(byte);
+            if byte != 0xff {
+                break;
+            }
+        }
+    }
+    Ok(len)
+}
+
+/// The compressor of the disk image builder, so that the tests can check that both sides agree on
+/// the format.
+#[cfg(test)]
+#[path = "../../src/lz4.rs"]
+mod compressor;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Compresses `data` and checks that it decompresses to the same bytes again.
+    fn round_trip(data: &[u8]) -> Vec<u8> {
+        let image = compressor::compress(data);
+        assert_eq!(decompressed_len(&image), Some(data.len()));
+        let mut out = vec![0; data.len()];
+        decompress(&image, &mut out).unwrap();
+        assert_eq!(out, data);
+        image
+    }
+
+    /// Returns `len` pseudo-random bytes, which don't contain any matches.
+    fn noise(len: usize) -> Vec<u8> {
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    fn image(block: &[u8], len: u64) -> Vec<u8> {
+        let mut image = MAGIC.to_vec();
+        image.extend_from_slice(&len.to_le_bytes());
+        image.extend_from_slice(block);
+        image
+    }
+
+    #[test]
+    fn test_decompressed_len() {
+        assert_eq!(decompressed_len(&image(&[], 42)), Some(42));
+        assert_eq!(decompressed_len(b"\x7fELF\x02\x01\x01\0\0\0\0\0"), None);
+        assert_eq!(decompressed_len(&MAGIC), None);
+    }
+
+    #[test]
+    fn test_decompress() {
+        // literals "abc", then a match of length 6 with offset 3, then the literals "xyz"
+        let block = [0x32, b'a', b'b', b'c', 3, 0, 0x30, b'x', b'y', b'z'];
+        let mut out = [0; 12];
+        decompress(&image(&block, 12), &mut out).unwrap();
+        assert_eq!(&out, b"abcabcabcxyz");
+    }
+
+    #[test]
+    fn test_decompress_extended_lengths() {
+        // a single literal followed by an overlapping match of length 4 + 15 + 255 + 1
+        let block = [0x1f, b'a', 1, 0, 0xff, 1, 0x10, b'b'];
+        let mut out = vec![0; 277];
+        decompress(&image(&block, 277), &mut out).unwrap();
+        assert!(out[..276].iter().all(|&b| b == b'a'));
+        assert_eq!(out[276], b'b');
+    }
+
+    #[test]
+    fn test_decompress_invalid() {
+        let mut out = [0; 12];
+        // wrong decompressed length
+        let block = [0x32, b'a', b'b', b'c', 3, 0, 0x30, b'x', b'y', b'z'];
+        assert!(decompress(&image(&block, 11), &mut out).is_err());
+        // match offset before the start of the output
+        let block = [0x32, b'a', b'b', b'c', 4, 0, 0x30, b'x', b'y', b'z'];
+        assert!(decompress(&image(&block, 12), &mut out).is_err());
+        // truncated literals
+        assert!(decompress(&image(&[0x30, b'x'], 12), &mut out).is_err());
+        // output shorter than the decompressed data
+        let mut out = [0; 4];
+        assert!(decompress(&image(&[0x50, b'a', b'b', b'c', b'd', b'e'], 4), &mut out).is_err());
+    }
+
+    #[test]
+    fn test_round_trip_short() {
+        assert_eq!(round_trip(&[]).len(), HEADER_LEN + 1);
+        // shorter than the minimum match length
+        round_trip(b"abc");
+        round_trip(b"aaaaaaaaaaa");
+    }
+
+    #[test]
+    fn test_round_trip_repetitive() {
+        let data = vec![0x42; 100_000];
+        assert!(round_trip(&data).len() < 1_000);
+
+        let data: Vec<u8> = b"kernel".iter().copied().cycle().take(70_000).collect();
+        assert!(round_trip(&data).len() < 1_000);
+    }
+
+    #[test]
+    fn test_round_trip_incompressible() {
+        let data = noise(4096);
+        // the literals are stored with a small overhead for their extended length
+        assert!(round_trip(&data).len() <= HEADER_LEN + data.len() + 20);
+    }
+
+    #[test]
+    fn test_round_trip_extended_lengths() {
+        // runs of pseudo-random literals, each followed by a match that repeats them
+        for len in [15, 19, 300] {
+            let literals = noise(len);
+            let mut data = literals.clone();
+            data.extend_from_slice(&literals);
+            data.extend(noise(len + 20).into_iter().skip(len));
+            let image = round_trip(&data);
+
+            // the literal length always needs an extension byte, the match length from a
+            // match length of 19 on
+            let token = image[HEADER_LEN];
+            assert_eq!(token >> 4, 0xf);
+            assert_eq!(token & 0xf == 0xf, len >= 19);
+        }
+    }
+}
@@ -0,0 +1,280 @@
+use core::{
+    fmt,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use x86_64::{
+    structures::paging::{
+        mapper::MapToError, FrameAllocator, Mapper, Page, PageSize, PageTableFlags, PhysFrame,
+        Size1GiB, Size2MiB, Size4KiB,
+    },
+    PhysAddr, VirtAddr,
+};
+
+/// Whether the CPU supports the `NO_EXECUTE` page table flag.
+static NO_EXECUTE_SUPPORTED: AtomicBool = AtomicBool::new(true);
+
+/// Makes [`supported_flags`] and [`try_map`] drop the `NO_EXECUTE` flag.
+///
+/// Must be called before any mappings are created if the CPU doesn't support the no-execute
+/// bit, because setting it without `EFER.NXE` causes a page fault on every access.
+pub fn disable_no_execute() {
+    NO_EXECUTE_SUPPORTED.store(false, Ordering::Relaxed);
+}
+
+/// Removes the flags from the given set that are not supported by the CPU.
+pub fn supported_flags(flags: PageTableFlags) -> PageTableFlags {
+    if NO_EXECUTE_SUPPORTED.load(Ordering::Relaxed) {
+        flags
+    } else {
+        flags - PageTableFlags::NO_EXECUTE
+    }
+}
+
+/// Maps the given page to the given frame and flushes the TLB entry for it.
+///
+/// In contrast to calling [`Mapper::map_to`] directly, errors are translated into a
+/// [`MapError`] that records the page and frame involved. Flags that are not supported by
+/// the CPU are removed, see [`supported_flags`].
+///
+/// ## Safety
+///
+/// Same as for [`Mapper::map_to`]: the caller must ensure that the mapping doesn't cause
+/// memory safety issues, e.g. by aliasing a frame that is already in use.
+pub unsafe fn try_map<S, M, A>(
+    mapper: &mut M,
+    page: Page<S>,
+    frame: PhysFrame<S>,
+    flags: PageTableFlags,
+    frame_allocator: &mut A,
+) -> Result<(), MapError>
+where
+    S: PageSize,
+    M: Mapper<S>,
+    A: FrameAllocator<Size4KiB>,
+{
+    let flags = supported_flags(flags);
+    match unsafe { mapper.map_to(page, frame, flags, frame_allocator) } {
+        Ok(tlb) => {
+            tlb.flush();
+            Ok(())
+        }
+        Err(err) => Err(MapError::new(page, frame, err)),
+    }
+}
+
+/// Maps the physical memory range `0..max_phys_addr` at the given virtual `offset`.
+///
+/// The end of the range is rounded up to the next 2MiB boundary. If `use_1gib_pages` is set,
+/// 1GiB pages are used wherever the offset allows it, and 2MiB pages for the rest. Since the
+/// range starts at address 0, this is all of the range if the offset is 1GiB-aligned. The
+/// `progress` closure is called with the number of mapped bytes after each page.
+///
+/// ## Safety
+///
+/// The given page table must not be active, since the TLB is not flushed.
+pub unsafe fn map_physical_memory<M, A>(
+    page_table: &mut M,
+    offset: VirtAddr,
+    max_phys_addr: PhysAddr,
+    use_1gib_pages: bool,
+    frame_allocator: &mut A,
+    mut progress: impl FnMut(u64),
+) -> Result<(), MapError>
+where
+    M: Mapper<Size1GiB> + Mapper<Size2MiB>,
+    A: FrameAllocator<Size4KiB>,
+{
+    // the physical memory mapping is typically shared by all address spaces
+    let flags = supported_flags(
+        PageTableFlags::PRESENT
+            | PageTableFlags::WRITABLE
+            | PageTableFlags::NO_EXECUTE
+            | PageTableFlags::GLOBAL,
+    );
+    let end = max_phys_addr.align_up(Size2MiB::SIZE).as_u64();
+    let mut phys_addr = 0;
+    while phys_addr < end {
+        let virt_addr = offset + phys_addr;
+        if use_1gib_pages
+            && virt_addr.is_aligned(Size1GiB::SIZE)
+            && phys_addr % Size1GiB::SIZE == 0
+            && end - phys_addr >= Size1GiB::SIZE
+        {
+            let page = Page::<Size1GiB>::from_start_address(virt_addr).unwrap();
+            let frame = PhysFrame::from_start_address(PhysAddr::new(phys_addr)).unwrap();
+            unsafe { page_table.map_to(page, frame, flags, frame_allocator) }
+                .map_err(|err| MapError::new(page, frame, err))?
+                .ignore();
+            phys_addr += Size1GiB::SIZE;
+        } else {
+            let page = Page::<Size2MiB>::from_start_address(virt_addr).unwrap();
+            let frame = PhysFrame::from_start_address(PhysAddr::new(phys_addr)).unwrap();
+            unsafe { page_table.map_to(page, frame, flags, frame_allocator) }
+                .map_err(|err| MapError::new(page, frame, err))?
+                .ignore();
+            phys_addr += Size2MiB::SIZE;
+        }
+        progress(phys_addr);
+    }
+    Ok(())
+}
+
+/// Error that occurred while mapping a page for the kernel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MapError {
+    /// The start address of the page that should be mapped.
+    pub page: VirtAddr,
+    /// The start address of the frame that the page should be mapped to.
+    ///
+    /// Is `None` if no frame could be allocated for the page.
+    pub frame: Option<PhysAddr>,
+    /// Describes why the mapping failed.
+    pub kind: MapErrorKind,
+}
+
+/// Describes why a [`MapError`] occurred.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapErrorKind {
+    /// No frame was available for the page itself or for creating a new page table.
+    FrameAllocationFailed,
+    /// An entry of a higher level page table already maps a huge page that contains the page.
+    ParentEntryHugePage,
+    /// The page is already mapped to the given frame.
+    PageAlreadyMapped(PhysAddr),
+}
+
+impl MapError {
+    /// Creates a new error from the given [`MapToError`] of the `x86_64` crate.
+    pub fn new<S: PageSize>(page: Page<S>, frame: PhysFrame<S>, err: MapToError<S>) -> Self {
+        let kind = match err {
+            MapToError::FrameAllocationFailed => MapErrorKind::FrameAllocationFailed,
+            MapToError::ParentEntryHugePage => MapErrorKind::ParentEntryHugePage,
+            MapToError::PageAlreadyMapped(frame) => {
+                MapErrorKind::PageAlreadyMapped(frame.start_address())
+            }
+        };
+        Self {
+            page: page.start_address(),
+            frame: Some(frame.start_address()),
+            kind,
+        }
+    }
+
+    /// Creates a new error for a page that couldn't be mapped because no frame was available.
+    pub fn frame_allocation_failed<S: PageSize>(page: Page<S>) -> Self {
+        Self {
+            page: page.start_address(),
+            frame: None,
+            kind: MapErrorKind::FrameAllocationFailed,
+        }
+    }
+}
+
+impl fmt::Display for MapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let page = self.page.as_u64();
+        if self.kind == MapErrorKind::FrameAllocationFailed {
+            return match self.frame {
+                None => write!(f, "out of physical memory while mapping page {page:#x}"),
+                Some(_) => write!(
+                    f,
+                    "out of physical memory while creating a page table for page {page:#x}"
+                ),
+            };
+        }
+        write!(f, "failed to map page {page:#x}")?;
+        if let Some(frame) = self.frame {
+            write!(f, " to frame {:#x}", frame.as_u64())?;
+        }
+        match self.kind {
+            MapErrorKind::PageAlreadyMapped(frame) => {
+                write!(f, ": page is already mapped to frame {:#x}", frame.as_u64())
+            }
+            _ => f.write_str(": a parent page table entry maps a huge page"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use x86_64::structures::paging::{
+        mapper::{MappedFrame, Translate, TranslateResult},
+        OffsetPageTable, PageTable,
+    };
+
+    /// Allocates zeroed frames from the heap, which is identity-mapped from the perspective
+    /// of an `OffsetPageTable` with offset 0.
+    struct HeapFrameAllocator;
+
+    unsafe impl FrameAllocator<Size4KiB> for HeapFrameAllocator {
+        fn allocate_frame(&mut self) -> Option<PhysFrame> {
+            let table = Box::leak(Box::new(PageTable::new()));
+            PhysFrame::from_start_address(PhysAddr::new(table as *mut _ as u64)).ok()
+        }
+    }
+
+    fn map(offset: u64, max_phys_addr: u64, use_1gib_pages: bool) -> OffsetPageTable<'static> {
+        let level_4_table = Box::leak(Box::new(PageTable::new()));
+        let mut page_table = unsafe { OffsetPageTable::new(level_4_table, VirtAddr::new(0)) };
+        unsafe {
+            map_physical_memory(
+                &mut page_table,
+                VirtAddr::new(offset),
+                PhysAddr::new(max_phys_addr),
+                use_1gib_pages,
+                &mut HeapFrameAllocator,
+                |_| {},
+            )
+        }
+        .unwrap();
+        page_table
+    }
+
+    /// Returns the physical address and the mapped frame type for the given address.
+    fn translate(page_table: &OffsetPageTable, addr: u64) -> (u64, MappedFrame) {
+        match page_table.translate(VirtAddr::new(addr)) {
+            TranslateResult::Mapped { frame, offset, .. } => {
+                (frame.start_address().as_u64() + offset, frame)
+            }
+            other => panic!("{addr:#x} is not mapped: {other:?}"),
+        }
+    }
+
+    const OFFSET: u64 = 0xffff_8000_0000_0000;
+
+    #[test]
+    fn test_1gib_pages() {
+        // 2GiB and 4MiB
+        let page_table = map(OFFSET, 0x8040_0000, true);
+        for phys_addr in [0, 0x4000_1234] {
+            let (addr, frame) = translate(&page_table, OFFSET + phys_addr);
+            assert_eq!(addr, phys_addr);
+            assert!(matches!(frame, MappedFrame::Size1GiB(_)));
+        }
+        let (addr, frame) = translate(&page_table, OFFSET + 0x8020_0000);
+        assert_eq!(addr, 0x8020_0000);
+        assert!(matches!(frame, MappedFrame::Size2MiB(_)));
+        assert!(page_table
+            .translate_addr(VirtAddr::new(OFFSET + 0x8040_0000))
+            .is_none());
+    }
+
+    #[test]
+    fn test_1gib_pages_unaligned_offset() {
+        let offset = OFFSET + 0x20_0000;
+        let page_table = map(offset, 0x4000_0000, true);
+        for phys_addr in [0, 0x3fe0_0000] {
+            let (addr, frame) = translate(&page_table, offset + phys_addr);
+            assert_eq!(addr, phys_addr);
+            assert!(matches!(frame, MappedFrame::Size2MiB(_)));
+        }
+    }
+
+    #[test]
+    fn test_1gib_pages_unsupported() {
+        let page_table = map(OFFSET, 0x4000_0000, false);
+        let (_, frame) = translate(&page_table, OFFSET);
+        assert!(matches!(frame, MappedFrame::Size2MiB(_)));
+    }
+}
@@ -0,0 +1,107 @@
+use x86_64::{
+    structures::paging::{OffsetPageTable, PageTable, PageTableFlags, PageTableIndex},
+    VirtAddr,
+};
+
+/// Walks the given page table and logs a warning for every mapping that violates one of the
+/// invariants that the bootloader tries to uphold:
+///
+/// - Writable pages must not be executable (W^X).
+/// - The framebuffer must not be executable.
+///
+/// The recursive level 4 entry is skipped, since it points back to the level 4 table itself.
+/// Returns the number of violations found.
+pub fn check_page_table_flags(
+    page_table: &mut OffsetPageTable,
+    recursive_index: Option<PageTableIndex>,
+    framebuffer: Option<(VirtAddr, u64)>,
+) -> usize {
+    let mut checker = Checker {
+        phys_offset: page_table.phys_offset(),
+        framebuffer,
+        violations: 0,
+    };
+    for (i, entry) in page_table.level_4_table().iter().enumerate() {
+        if recursive_index == Some(PageTableIndex::new(i as u16)) {
+            continue;
+        }
+        // sign-extend the address for entries in the higher half
+        let addr = VirtAddr::new_truncate((i as u64) << 39);
+        checker.check_entry(entry.flags(), entry.addr().as_u64(), addr, 4, None);
+    }
+    checker.violations
+}
+
+struct Checker {
+    phys_offset: VirtAddr,
+    framebuffer: Option<(VirtAddr, u64)>,
+    violations: usize,
+}
+
+impl Checker {
+    /// Checks the given entry and, unless it maps a page, all entries of the table that it
+    /// points to.
+    ///
+    /// The `parent_flags` are the combined flags of all higher level entries. Writes are only
+    /// allowed if all levels are writable, while execution is prevented if any level is NX.
+    fn check_entry(
+        &mut self,
+        flags: PageTableFlags,
+        phys_addr: u64,
+        addr: VirtAddr,
+        level: u8,
+        parent_flags: Option<PageTableFlags>,
+    ) {
+        if !flags.contains(PageTableFlags::PRESENT) {
+            return;
+        }
+        let effective_flags = match parent_flags {
+            Some(parent) => {
+                let writable = parent & flags & PageTableFlags::WRITABLE;
+                let no_execute = (parent | flags) & PageTableFlags::NO_EXECUTE;
+                PageTableFlags::PRESENT | writable | no_execute
+            }
+            None => flags,
+        };
+
+        let is_page = level == 1 || (level < 4 && flags.contains(PageTableFlags::HUGE_PAGE));
+        if is_page {
+            let size = 1u64 << (12 + 9 * (u32::from(level) - 1));
+            self.check_page(addr, size, effective_flags);
+            return;
+        }
+
+        let table: &PageTable = unsafe { &*(self.phys_offset + phys_addr).as_ptr() };
+        for (i, entry) in table.iter().enumerate() {
+            let shift = 12 + 9 * (u32::from(level) - 2);
+            let entry_addr = addr + ((i as u64) << shift);
+            self.check_entry(
+                entry.flags(),
+                entry.addr().as_u64(),
+                entry_addr,
+                level - 1,
+                Some(effective_flags),
+            );
+        }
+    }
+
+    fn check_page(&mut self, addr: VirtAddr, size: u64, flags: PageTableFlags) {
+        let executable = !flags.contains(PageTableFlags::NO_EXECUTE);
+        if executable && flags.contains(PageTableFlags::WRITABLE) {
+            log::warn!(
+                "page at {:#x} (size {:#x}) is both writable and executable",
+                addr.as_u64(),
+                size
+            );
+            self.violations += 1;
+        }
+        if let Some((start, len)) = self.framebuffer {
+            let overlaps =
+                addr < start + len && start.as_u64() < addr.as_u64().saturating_add(size);
+            if executable && overlaps {
+                log::warn!("framebuffer page at {:#x} is executable", addr.as_u64());
+                self.violations += 1;
+            }
+        }
+    }
+}
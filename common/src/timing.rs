@@ -0,0 +1,52 @@
+/// Measures the duration of the boot phases with the time stamp counter (TSC).
+///
+/// The durations are logged at `trace` level in TSC cycles. Use the TSC frequency reported in
+/// [`bootloader_api::info::TscInfo`] to convert them into real time.
+#[derive(Debug, Clone, Copy)]
+pub struct BootTimer {
+    start: u64,
+    last: u64,
+}
+
+impl BootTimer {
+    /// Starts a new timer at the current TSC value.
+    pub fn start() -> Self {
+        let now = read_tsc();
+        Self {
+            start: now,
+            last: now,
+        }
+    }
+
+    /// Returns the TSC value at which the timer was started.
+    pub fn start_tsc(&self) -> u64 {
+        self.start
+    }
+
+    /// Returns the number of cycles since the end of the previous phase and starts a new one.
+    ///
+    /// This is useful for phases that end before the logger is initialized.
+    pub fn lap(&mut self) -> u64 {
+        let now = read_tsc();
+        let cycles = now.wrapping_sub(self.last);
+        self.last = now;
+        cycles
+    }
+
+    /// Logs the duration of the phase that ended now and starts a new one.
+    pub fn phase(&mut self, name: &str) {
+        let cycles = self.lap();
+        log::trace!("{name} took {cycles} cycles");
+    }
+
+    /// Logs the number of cycles since the timer was started.
+    pub fn total(&self) {
+        let cycles = read_tsc().wrapping_sub(self.start);
+        log::trace!("Bootloader took {cycles} cycles in total");
+    }
+}
+
+/// Reads the current value of the time stamp counter.
+pub fn read_tsc() -> u64 {
+    unsafe { core::arch::x86_64::_rdtsc() }
+}
@@ -23,6 +23,8 @@ pub use bios::BiosBoot;
 
 mod fat;
 mod file_data_source;
+#[cfg(feature = "kernel-compression")]
+mod lz4;
 
 use std::{
     borrow::Cow,
@@ -40,6 +42,13 @@ pub use bootloader_boot_config::BootConfig;
 const KERNEL_FILE_NAME: &str = "kernel-x86_64";
 const RAMDISK_FILE_NAME: &str = "ramdisk";
 const CONFIG_FILE_NAME: &str = "boot.json";
+const CMDLINE_FILE_NAME: &str = "cmdline";
+#[cfg(feature = "boot-menu")]
+const BOOT_MENU_FILE_NAME: &str = "boot-menu";
+/// Maximum number of kernels in the boot menu, so that each entry can be selected by a
+/// single number key.
+#[cfg(feature = "boot-menu")]
+const BOOT_MENU_MAX_ENTRIES: usize = 10;
 
 #[cfg(feature = "uefi")]
 const UEFI_BOOTLOADER: &[u8] = include_bytes!(env!("UEFI_BOOTLOADER_PATH"));
@@ -57,6 +66,10 @@ const BIOS_STAGE_4: &[u8] = include_bytes!(env!("BIOS_STAGE_4_PATH"));
 /// It can currently create `MBR` (BIOS), `GPT` (UEFI), and `TFTP` (UEFI) images.
 pub struct DiskImageBuilder {
     files: BTreeMap<Cow<'static, str>, FileDataSource>,
+    #[cfg(feature = "kernel-compression")]
+    compress_kernel: bool,
+    #[cfg(feature = "boot-menu")]
+    boot_menu_entries: Vec<String>,
 }
 
 impl DiskImageBuilder {
@@ -71,6 +84,10 @@ impl DiskImageBuilder {
     pub fn empty() -> Self {
         Self {
             files: BTreeMap::new(),
+            #[cfg(feature = "kernel-compression")]
+            compress_kernel: false,
+            #[cfg(feature = "boot-menu")]
+            boot_menu_entries: Vec::new(),
         }
     }
 
@@ -79,6 +96,53 @@ impl DiskImageBuilder {
         self.set_file_source(KERNEL_FILE_NAME.into(), FileDataSource::File(path))
     }
 
+    #[cfg(feature = "kernel-compression")]
+    /// Compress the kernel with LZ4 before writing it to the disk image.
+    ///
+    /// The UEFI bootloader decompresses the kernel into memory before loading it. Compressed
+    /// kernels are not supported on BIOS systems, so creating a BIOS image fails if this is
+    /// enabled.
+    pub fn set_kernel_compression(&mut self, enabled: bool) -> &mut Self {
+        self.compress_kernel = enabled;
+        self
+    }
+
+    #[cfg(feature = "boot-menu")]
+    /// Add an additional kernel that can be selected in the boot menu of the UEFI bootloader.
+    ///
+    /// The kernel set through [`Self::set_kernel`] is always the first entry of the menu,
+    /// named `default`. The additional kernels follow in the order in which they were added.
+    /// The menu supports at most 10 entries, so that each entry can be selected by pressing
+    /// its number key. The timeout and the entry that is booted by default are configured
+    /// through the `boot_menu` field of the [`BootConfig`]. The menu is only shown on UEFI
+    /// systems; BIOS systems always boot the first entry.
+    pub fn add_boot_menu_kernel(&mut self, name: &str, path: PathBuf) -> &mut Self {
+        assert!(
+            self.boot_menu_entries.len() + 1 < BOOT_MENU_MAX_ENTRIES,
+            "the boot menu supports at most {BOOT_MENU_MAX_ENTRIES} kernels"
+        );
+        assert!(
+            !name.contains('\n'),
+            "boot menu entry names must not contain line breaks"
+        );
+        self.boot_menu_entries.push(name.to_owned());
+        let index = self.boot_menu_entries.len();
+        self.set_file_source(
+            format!("{KERNEL_FILE_NAME}-{index}").into(),
+            FileDataSource::File(path),
+        );
+
+        let mut menu = String::from("default\n");
+        for name in &self.boot_menu_entries {
+            menu += name;
+            menu += "\n";
+        }
+        self.set_file_source(
+            BOOT_MENU_FILE_NAME.into(),
+            FileDataSource::Data(menu.into_bytes()),
+        )
+    }
+
     /// Add or replace a ramdisk to be included in the final image.
     pub fn set_ramdisk(&mut self, path: PathBuf) -> &mut Self {
         self.set_file_source(RAMDISK_FILE_NAME.into(), FileDataSource::File(path))
@@ -90,6 +154,18 @@ impl DiskImageBuilder {
         self.set_file_source(CONFIG_FILE_NAME.into(), FileDataSource::Data(json))
     }
 
+    /// Sets the command line that is passed to the kernel.
+    ///
+    /// The command line is stored as a separate file in the disk image. The bootloader copies
+    /// it into memory and reports it through the `cmdline_addr` and `cmdline_len` fields of
+    /// the boot info.
+    pub fn set_kernel_cmdline(&mut self, cmdline: &str) -> &mut Self {
+        self.set_file_source(
+            CMDLINE_FILE_NAME.into(),
+            FileDataSource::Data(cmdline.as_bytes().to_vec()),
+        )
+    }
+
     /// Add a file with the specified bytes to the disk image
     ///
     /// Note that the bootloader only loads the kernel and ramdisk files into memory on boot.
@@ -111,6 +187,10 @@ impl DiskImageBuilder {
     pub fn create_bios_image(&self, image_path: &Path) -> anyhow::Result<()> {
         const BIOS_STAGE_3_NAME: &str = "boot-stage-3";
         const BIOS_STAGE_4_NAME: &str = "boot-stage-4";
+        #[cfg(feature = "kernel-compression")]
+        if self.compress_kernel {
+            anyhow::bail!("compressed kernels are not supported by the BIOS bootloader");
+        }
         let stage_3 = FileDataSource::Bytes(BIOS_STAGE_3);
         let stage_4 = FileDataSource::Bytes(BIOS_STAGE_4);
         let mut internal_files = BTreeMap::new();
@@ -155,7 +235,7 @@ impl DiskImageBuilder {
     #[cfg(feature = "uefi")]
     /// Create a folder containing the needed files for UEFI TFTP/PXE booting.
     pub fn create_uefi_tftp_folder(&self, tftp_path: &Path) -> anyhow::Result<()> {
-        use std::{fs, ops::Deref};
+        use std::fs;
 
         const UEFI_TFTP_BOOT_FILENAME: &str = "bootloader";
         fs::create_dir_all(tftp_path)
@@ -169,8 +249,8 @@ impl DiskImageBuilder {
             )
         })?;
 
-        for f in &self.files {
-            let to = tftp_path.join(f.0.deref());
+        for f in &self.image_files()? {
+            let to = tftp_path.join(f.0);
 
             let mut new_file = fs::OpenOptions::new()
                 .read(true)
@@ -195,14 +275,46 @@ impl DiskImageBuilder {
         self
     }
 
+    /// Returns the files to include in the disk image, with the kernel compressed if requested.
+    fn image_files(&self) -> anyhow::Result<BTreeMap<&str, Cow<'_, FileDataSource>>> {
+        #[cfg_attr(not(feature = "kernel-compression"), allow(unused_mut))]
+        let mut files: BTreeMap<&str, _> = self
+            .files
+            .iter()
+            .map(|(name, source)| (name.as_ref(), Cow::Borrowed(source)))
+            .collect();
+
+        #[cfg(feature = "kernel-compression")]
+        if self.compress_kernel {
+            if !files.contains_key(KERNEL_FILE_NAME) {
+                anyhow::bail!("no kernel was set for the disk image");
+            }
+            // also compress the additional kernels of the boot menu
+            let kernels = files.iter_mut().filter(|(name, _)| {
+                name.strip_prefix(KERNEL_FILE_NAME)
+                    .map_or(false, |suffix| suffix.is_empty() || suffix.starts_with('-'))
+            });
+            for (name, kernel) in kernels {
+                let mut data = Vec::new();
+                kernel
+                    .copy_to(&mut data)
+                    .with_context(|| format!("failed to read `{name}` for compression"))?;
+                *kernel = Cow::Owned(FileDataSource::Data(lz4::compress(&data)));
+            }
+        }
+
+        Ok(files)
+    }
+
     fn create_fat_filesystem_image(
         &self,
         internal_files: BTreeMap<&str, FileDataSource>,
     ) -> anyhow::Result<NamedTempFile> {
+        let files = self.image_files()?;
         let mut local_map: BTreeMap<&str, _> = BTreeMap::new();
 
-        for (name, source) in &self.files {
-            local_map.insert(name, source);
+        for (name, source) in &files {
+            local_map.insert(name, source.as_ref());
         }
 
         for k in &internal_files {
@@ -0,0 +1,85 @@
+//! A minimal LZ4 block compressor for kernel images.
+//!
+//! The output format must match the decompressor in `bootloader_x86_64_common::lz4`.
+
+use bootloader_boot_config::COMPRESSED_KERNEL_MAGIC as MAGIC;
+
+const MIN_MATCH: usize = 4;
+/// The last match must start at least this many bytes before the end of the block.
+const MF_LIMIT: usize = 12;
+/// The last bytes of a block are always literals.
+const LAST_LITERALS: usize = 5;
+const MAX_OFFSET: usize = u16::MAX as usize;
+const HASH_BITS: u32 = 16;
+
+/// Compresses the given kernel image and prepends the header that is expected by the UEFI
+/// bootloader.
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() / 2);
+    out.extend_from_slice(&MAGIC);
+    out.extend_from_slice(&(data.len() as u64).to_le_bytes());
+
+    let mut table = vec![usize::MAX; 1 << HASH_BITS];
+    let mut anchor = 0;
+    let mut pos = 0;
+    while pos + MF_LIMIT <= data.len() {
+        let sequence = read_u32(data, pos);
+        let hash = (sequence.wrapping_mul(2_654_435_761) >> (32 - HASH_BITS)) as usize;
+        let candidate = std::mem::replace(&mut table[hash], pos);
+
+        if candidate == usize::MAX
+            || pos - candidate > MAX_OFFSET
+            || read_u32(data, candidate) != sequence
+        {
+            pos += 1;
+            continue;
+        }
+
+        let match_limit = data.len() - LAST_LITERALS;
+        let mut match_len = MIN_MATCH;
+        while pos + match_len < match_limit && data[candidate + match_len] == data[pos + match_len]
+        {
+            match_len += 1;
+        }
+
+        write_sequence(
+            &mut out,
+            &data[anchor..pos],
+            Some((pos - candidate, match_len)),
+        );
+        pos += match_len;
+        anchor = pos;
+    }
+    write_sequence(&mut out, &data[anchor..], None);
+    out
+}
+
+/// Writes a sequence consisting of the given literals and an optional `(offset, length)` match.
+fn write_sequence(out: &mut Vec<u8>, literals: &[u8], matched: Option<(usize, usize)>) {
+    let match_len = matched.map_or(0, |(_, len)| len - MIN_MATCH);
+    let token = (literals.len().min(0xf) << 4) | match_len.min(0xf);
+    out.push(token as u8);
+    write_length(out, literals.len());
+    out.extend_from_slice(literals);
+    if let Some((offset, _)) = matched {
+        out.extend_from_slice(&(offset as u16).to_le_bytes());
+        write_length(out, match_len);
+    }
+}
+
+/// Writes the extension bytes of a literal or match length that doesn't fit into the token.
+fn write_length(out: &mut Vec<u8>, len: usize) {
+    if len < 0xf {
+        return;
+    }
+    let mut rest = len - 0xf;
+    while rest >= 0xff {
+        out.push(0xff);
+        rest -= 0xff;
+    }
+    out.push(rest as u8);
+}
+
+fn read_u32(data: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes(data[pos..pos + 4].try_into().unwrap())
+}
@@ -29,6 +29,25 @@ impl UefiBoot {
         self
     }
 
+    /// Compress the kernel with LZ4 before writing it to the image.
+    ///
+    /// The bootloader decompresses the kernel into memory before loading it.
+    #[cfg(feature = "kernel-compression")]
+    pub fn set_kernel_compression(&mut self, enabled: bool) -> &mut Self {
+        self.image_builder.set_kernel_compression(enabled);
+        self
+    }
+
+    /// Add an additional kernel that can be selected in the boot menu.
+    ///
+    /// See [`DiskImageBuilder::add_boot_menu_kernel`] for details.
+    #[cfg(feature = "boot-menu")]
+    pub fn add_boot_menu_kernel(&mut self, name: &str, kernel_path: &Path) -> &mut Self {
+        self.image_builder
+            .add_boot_menu_kernel(name, kernel_path.to_owned());
+        self
+    }
+
     /// Create a bootable UEFI disk image at the given path.
     pub fn create_disk_image(&self, out_path: &Path) -> anyhow::Result<()> {
         self.image_builder.create_uefi_image(out_path)
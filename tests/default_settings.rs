@@ -1,4 +1,4 @@
-use bootloader_test_runner::run_test_kernel;
+use bootloader_test_runner::{run_test_kernel, run_test_kernel_headless};
 
 #[test]
 fn basic_boot() {
@@ -20,3 +20,10 @@ fn check_boot_info() {
         "CARGO_BIN_FILE_TEST_KERNEL_DEFAULT_SETTINGS_check_boot_info"
     ));
 }
+
+#[test]
+fn no_framebuffer() {
+    run_test_kernel_headless(env!(
+        "CARGO_BIN_FILE_TEST_KERNEL_DEFAULT_SETTINGS_no_framebuffer"
+    ));
+}
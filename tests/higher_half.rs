@@ -23,3 +23,10 @@ fn verify_higher_half() {
         "CARGO_BIN_FILE_TEST_KERNEL_HIGHER_HALF_verify_higher_half"
     ));
 }
+
+#[test]
+fn higher_half_boot_data() {
+    run_test_kernel(env!(
+        "CARGO_BIN_FILE_TEST_KERNEL_HIGHER_HALF_higher_half_boot_data"
+    ));
+}
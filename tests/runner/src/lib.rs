@@ -61,6 +61,35 @@ pub fn run_test_kernel_on_uefi(out_gpt_path: &Path) {
     run_qemu(args);
 }
 
+/// Boots the given kernel on UEFI without any graphics device, so that the firmware provides
+/// no `GraphicsOutput` protocol.
+///
+/// BIOS is not tested because the BIOS stages require VESA support.
+#[cfg(feature = "uefi")]
+pub fn run_test_kernel_headless(kernel_binary_path: &str) {
+    let kernel_path = Path::new(kernel_binary_path);
+    let gpt_path = kernel_path.with_extension("gpt");
+    DiskImageBuilder::new(kernel_path.to_owned())
+        .create_uefi_image(&gpt_path)
+        .unwrap();
+
+    let ovmf_pure_efi = ovmf_prebuilt::ovmf_pure_efi();
+    let args = [
+        "-bios",
+        ovmf_pure_efi.to_str().unwrap(),
+        "-drive",
+        &format!("format=raw,file={}", gpt_path.display()),
+        "-vga",
+        "none",
+    ];
+    run_qemu(args);
+}
+
+/// Does nothing if the runner is built without UEFI support, as the headless test is
+/// UEFI-only.
+#[cfg(not(feature = "uefi"))]
+pub fn run_test_kernel_headless(_kernel_binary_path: &str) {}
+
 #[cfg(feature = "bios")]
 pub fn run_test_kernel_on_bios(out_mbr_path: &Path) {
     let args = [
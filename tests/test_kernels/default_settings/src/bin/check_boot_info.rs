@@ -33,6 +33,28 @@ fn kernel_main(boot_info: &'static mut BootInfo) -> ! {
     let rsdp = boot_info.rsdp_addr.into_option().unwrap();
     assert!(rsdp > 0x000E0000);
 
+    // check that we're running on the reported kernel stack
+    let rsp: u64;
+    unsafe { core::arch::asm!("mov {}, rsp", out(reg) rsp) };
+    assert!(rsp >= boot_info.kernel_stack_addr);
+    assert!(rsp < boot_info.kernel_stack_addr + boot_info.kernel_stack_len);
+    assert!(boot_info.kernel_stack_len <= 80 * 1024);
+
+    // PCIDs are not enabled by default
+    assert!(!boot_info.cpu_features.pcid_enabled);
+    // the default CPU model of QEMU supports the no-execute bit
+    assert!(boot_info.cpu_features.nx);
+
+    // the vendor string is only reported on UEFI, but always valid UTF-8
+    let firmware_vendor = unsafe {
+        core::slice::from_raw_parts(
+            boot_info.firmware_vendor_addr as *const u8,
+            boot_info.firmware_vendor_len as usize,
+        )
+    };
+    assert!(core::str::from_utf8(firmware_vendor).is_ok());
+    assert!(firmware_vendor.len() <= BootInfo::FIRMWARE_VENDOR_MAX_LEN);
+
     // the test kernel has no TLS template
     assert_eq!(boot_info.tls_template.into_option(), None);
 
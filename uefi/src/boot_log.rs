@@ -0,0 +1,66 @@
+use crate::{locate_and_open_protocol, BootMode};
+use bootloader_x86_64_common::boot_log;
+use uefi::{
+    prelude::{Boot, Handle, SystemTable},
+    proto::media::{
+        file::{File, FileAttribute, FileMode},
+        fs::SimpleFileSystem,
+    },
+    CStr16,
+};
+
+/// Name of the file on the boot partition that the boot log is written to.
+const BOOT_LOG_FILE_NAME: &str = "bootlog.txt";
+
+/// Writes the buffered boot log messages to the `bootlog.txt` file on the boot partition.
+///
+/// An existing file is replaced. Errors are only logged, as a missing boot log should never
+/// prevent the kernel from booting. The file is written through the Simple File System
+/// protocol, so this must be called before exiting the boot services.
+pub fn write_file(image: Handle, st: &SystemTable<Boot>, boot_mode: BootMode) {
+    if let BootMode::Tftp = boot_mode {
+        log::warn!("Not writing the boot log file because the bootloader was loaded over TFTP");
+        return;
+    }
+
+    let dropped_lines = boot_log::with_contents(|log| log.dropped_lines());
+    if dropped_lines > 0 {
+        log::warn!("The boot log buffer is full, {dropped_lines} older lines were dropped");
+    }
+    log::info!("Writing boot log to {BOOT_LOG_FILE_NAME}");
+    if let Err(err) = try_write_file(image, st) {
+        log::warn!("Failed to write the boot log file: {err}");
+    }
+}
+
+fn try_write_file(image: Handle, st: &SystemTable<Boot>) -> Result<(), &'static str> {
+    let mut file_system = locate_and_open_protocol::<SimpleFileSystem>(image, st)
+        .ok_or("failed to open the boot file system")?;
+    let mut root = file_system
+        .open_volume()
+        .map_err(|_| "failed to open the root directory")?;
+
+    let mut buf = [0u16; 16];
+    let filename = CStr16::from_str_with_buf(BOOT_LOG_FILE_NAME, &mut buf)
+        .expect("Failed to convert string to utf16");
+
+    // opening an existing file with `CreateReadWrite` doesn't truncate it, so delete it first
+    if let Ok(file) = root.open(filename, FileMode::ReadWrite, FileAttribute::empty()) {
+        file.delete()
+            .map_err(|_| "failed to delete the previous boot log file")?;
+    }
+    let mut file = root
+        .open(filename, FileMode::CreateReadWrite, FileAttribute::empty())
+        .map_err(|_| "failed to create the file")?
+        .into_regular_file()
+        .ok_or("a directory with the same name exists")?;
+
+    // no log messages must be written while the buffer is locked
+    boot_log::with_contents(|log| {
+        let (first, second) = log.as_slices();
+        file.write(first)?;
+        file.write(second)
+    })
+    .map_err(|_| "failed to write to the file")?;
+    file.flush().map_err(|_| "failed to flush the file")
+}
@@ -0,0 +1,134 @@
+use crate::{load_file_from_boot_method, load_kernel, BootError, BootMode};
+use bootloader_boot_config::BootMenu;
+use bootloader_x86_64_common::Kernel;
+use core::{fmt::Write, str};
+use uefi::{
+    prelude::{Boot, Handle, Status, SystemTable},
+    proto::console::text::Key,
+    table::boot::{EventType, TimerTrigger, Tpl},
+};
+
+/// Name of the file that lists the names of the boot menu entries, one per line.
+const BOOT_MENU_FILE_NAME: &str = "boot-menu\0";
+/// Each entry is selected by a single number key.
+const MAX_ENTRIES: usize = 10;
+
+/// Shows a boot menu if the boot medium contains multiple kernels and loads the selected one.
+///
+/// The first entry is the `kernel-x86_64` file, which is already loaded and passed as
+/// `kernel`. The other entries are loaded from `kernel-x86_64-<index>` files. The menu is
+/// printed to the UEFI console, so this must be called before exiting the boot services.
+pub fn select_kernel(
+    image: Handle,
+    st: &mut SystemTable<Boot>,
+    boot_mode: BootMode,
+    config: &BootMenu,
+    kernel: Kernel<'static>,
+) -> Result<Kernel<'static>, BootError> {
+    let Some(menu) = load_file_from_boot_method(image, st, BOOT_MENU_FILE_NAME, boot_mode)? else {
+        return Ok(kernel);
+    };
+    let Ok(menu) = str::from_utf8(menu) else {
+        let _ = writeln!(st.stdout(), "Ignoring boot menu that is not valid UTF-8");
+        return Ok(kernel);
+    };
+    let entries = menu.lines().take(MAX_ENTRIES);
+    let count = entries.clone().count();
+    if count < 2 {
+        return Ok(kernel);
+    }
+
+    let default = if config.default_entry < count {
+        config.default_entry
+    } else {
+        let _ = writeln!(
+            st.stdout(),
+            "Default boot menu entry {} does not exist, using entry 0",
+            config.default_entry
+        );
+        0
+    };
+
+    let out = st.stdout();
+    let _ = writeln!(out, "Select the kernel to boot:");
+    for (index, name) in entries.enumerate() {
+        let marker = if index == default { '*' } else { ' ' };
+        let _ = writeln!(out, " {marker} {index}: {name}");
+    }
+    let selected = wait_for_selection(st, count, default, config.timeout_secs)?;
+    if selected == 0 {
+        return Ok(kernel);
+    }
+
+    let mut filename = *b"kernel-x86_64-0\0";
+    filename[filename.len() - 2] = b'0' + selected as u8;
+    let filename = str::from_utf8(&filename).unwrap();
+    let kernel = load_kernel(image, st, filename, boot_mode)?;
+    if kernel.is_none() {
+        let _ = writeln!(st.stdout(), "Kernel file `{filename}` does not exist");
+    }
+    kernel.ok_or((
+        Status::NOT_FOUND,
+        "Failed to load the kernel of the selected boot menu entry",
+    ))
+}
+
+/// Waits until the user presses the number key of an entry or until the timeout expires.
+///
+/// Pressing enter boots the default entry immediately.
+fn wait_for_selection(
+    st: &mut SystemTable<Boot>,
+    count: usize,
+    default: usize,
+    timeout_secs: u64,
+) -> Result<usize, BootError> {
+    if timeout_secs == 0 {
+        return Ok(default);
+    }
+    let _ = writeln!(
+        st.stdout(),
+        "Booting entry {default} in {timeout_secs} seconds. Press a number key to select \
+         an entry or enter to boot the default entry."
+    );
+
+    let timer = unsafe {
+        st.boot_services()
+            .create_event(EventType::TIMER, Tpl::APPLICATION, None, None)
+    }
+    .map_err(|err| (err.status(), "Failed to create boot menu timer"))?;
+    st.boot_services()
+        .set_timer(
+            &timer,
+            // the timer is specified in units of 100ns
+            TimerTrigger::Relative(timeout_secs.saturating_mul(10_000_000)),
+        )
+        .map_err(|err| (err.status(), "Failed to set boot menu timer"))?;
+
+    let selected = loop {
+        let mut events = unsafe {
+            [
+                st.stdin().wait_for_key_event().unsafe_clone(),
+                timer.unsafe_clone(),
+            ]
+        };
+        let index = st
+            .boot_services()
+            .wait_for_event(&mut events)
+            .map_err(|err| (err.status(), "Failed to wait for boot menu input"))?;
+        if index == 1 {
+            break default;
+        }
+        if let Ok(Some(Key::Printable(key))) = st.stdin().read_key() {
+            match char::from(key) {
+                '\r' => break default,
+                key => match key.to_digit(10) {
+                    Some(digit) if (digit as usize) < count => break digit as usize,
+                    _ => {}
+                },
+            }
+        }
+    };
+
+    let _ = st.boot_services().close_event(timer);
+    Ok(selected)
+}
@@ -3,20 +3,26 @@
 #![deny(unsafe_op_in_unsafe_fn)]
 
 use crate::memory_descriptor::UefiMemoryDescriptor;
-use bootloader_api::info::FrameBufferInfo;
-use bootloader_boot_config::BootConfig;
+use bootloader_api::{
+    info::{FrameBufferInfo, GraphicsMode},
+    BootInfo,
+};
+use bootloader_boot_config::{BootConfig, PreferredResolution};
 use bootloader_x86_64_common::{
-    legacy_memory_region::LegacyFrameAllocator, Kernel, RawFrameBufferInfo, SystemInfo,
+    acpi, legacy_memory_region::LegacyFrameAllocator, timing::BootTimer, Kernel,
+    RawFrameBufferInfo, SystemInfo,
 };
 use core::{
     cell::UnsafeCell,
+    convert::Infallible,
+    mem,
     ops::{Deref, DerefMut},
     ptr, slice,
 };
 use uefi::{
     prelude::{entry, Boot, Handle, Status, SystemTable},
     proto::{
-        console::gop::{GraphicsOutput, PixelFormat},
+        console::gop::{GraphicsOutput, ModeInfo, PixelFormat},
         device_path::DevicePath,
         loaded_image::LoadedImage,
         media::{
@@ -27,21 +33,51 @@ use uefi::{
             pxe::{BaseCode, DhcpV4Packet},
             IpAddress,
         },
+        pi::mp::MpServices,
         ProtocolPointer,
     },
-    table::boot::{
-        AllocateType, MemoryType, OpenProtocolAttributes, OpenProtocolParams, ScopedProtocol,
+    table::{
+        boot::{
+            AllocateType, MemoryType, OpenProtocolAttributes, OpenProtocolParams, ScopedProtocol,
+        },
+        cfg,
     },
-    CStr16, CStr8,
+    CStr16, CStr8, Guid,
 };
 use x86_64::{
-    structures::paging::{FrameAllocator, OffsetPageTable, PageTable, PhysFrame, Size4KiB},
+    structures::paging::{
+        FrameAllocator, OffsetPageTable, PageTable, PhysFrame, Size4KiB, Translate,
+    },
     PhysAddr, VirtAddr,
 };
 
+#[cfg(feature = "boot-log")]
+mod boot_log;
+#[cfg(feature = "boot-menu")]
+mod boot_menu;
 mod memory_descriptor;
 
+const KERNEL_FILE_NAME: &str = "kernel-x86_64\0";
+/// How long the error message is shown before resetting the machine on a fatal error.
+#[cfg(feature = "reset-on-error")]
+const RESET_DELAY_MICROS: usize = 5_000_000;
+/// Code passed to the firmware when changing the watchdog timer. The codes up to `0xffff`
+/// are reserved for the firmware.
+const WATCHDOG_CODE: u64 = 0x1_0000;
+
 static SYSTEM_TABLE: RacyCell<Option<SystemTable<Boot>>> = RacyCell::new(None);
+/// A copy of the system table that is kept until the boot services are exited, so that the
+/// panic handler can reset the machine.
+#[cfg(feature = "reset-on-error")]
+static RESET_SYSTEM_TABLE: RacyCell<Option<SystemTable<Boot>>> = RacyCell::new(None);
+/// The UTF-8 encoded firmware vendor string, which must outlive the boot services.
+static FIRMWARE_VENDOR: RacyCell<[u8; BootInfo::FIRMWARE_VENDOR_MAX_LEN]> =
+    RacyCell::new([0; BootInfo::FIRMWARE_VENDOR_MAX_LEN]);
+
+/// A fatal error that occurred before exiting the boot services.
+///
+/// Consists of the status that is reported to the firmware and a description of the error.
+type BootError = (Status, &'static str);
 
 struct RacyCell<T>(UnsafeCell<T>);
 
@@ -63,26 +99,71 @@ impl<T> core::ops::Deref for RacyCell<T> {
 
 #[entry]
 fn efi_main(image: Handle, st: SystemTable<Boot>) -> Status {
-    main_inner(image, st)
+    use core::fmt::Write;
+
+    let Err((status, message)) = main_inner(image, st);
+    // only reached before exiting the boot services, so we can return to the firmware
+    if let Some(mut st) = unsafe { (*SYSTEM_TABLE.get()).take() } {
+        let _ = writeln!(st.stdout(), "{message}");
+    }
+    log::error!("{message}");
+    boot_failed(status)
+}
+
+/// Handles a fatal error that occurred before exiting the boot services.
+///
+/// If the bootloader is built with the `reset-on-error` feature, this resets the machine
+/// after a short delay, so that the error message stays readable for a moment. Otherwise,
+/// the given status is returned so that `efi_main` can pass it to the firmware, which
+/// typically continues with the next boot option.
+fn boot_failed(status: Status) -> Status {
+    #[cfg(feature = "reset-on-error")]
+    if let Some(st) = unsafe { &*RESET_SYSTEM_TABLE.get() } {
+        st.boot_services().stall(RESET_DELAY_MICROS);
+        st.runtime_services()
+            .reset(uefi::table::runtime::ResetType::Cold, status, None);
+    }
+    status
 }
 
-fn main_inner(image: Handle, mut st: SystemTable<Boot>) -> Status {
+/// Loads the kernel and boots it.
+///
+/// Only returns if an error occurs before exiting the boot services. Errors after that point
+/// cause a panic, as there is no way to return to the firmware anymore.
+fn main_inner(image: Handle, mut st: SystemTable<Boot>) -> Result<Infallible, BootError> {
+    let mut boot_timer = BootTimer::start();
+
     // temporarily clone the y table for printing panics
     unsafe {
         *SYSTEM_TABLE.get() = Some(st.unsafe_clone());
     }
+    #[cfg(feature = "reset-on-error")]
+    unsafe {
+        *RESET_SYSTEM_TABLE.get() = Some(st.unsafe_clone());
+    }
+
+    // The firmware arms a 5-minute watchdog timer before starting the bootloader and resets
+    // the system if it expires before `exit_boot_services` is called. Loading a large kernel
+    // over a slow medium or waiting in the boot menu can take longer than that, so disable
+    // the watchdog before doing any work. Exiting the boot services disarms it anyway.
+    let watchdog_disabled = st
+        .boot_services()
+        .set_watchdog_timer(0, WATCHDOG_CODE, None)
+        .map_err(|err| err.status());
 
     let mut boot_mode = BootMode::Disk;
 
-    let mut kernel = load_kernel(image, &mut st, boot_mode);
+    let mut kernel = load_kernel(image, &mut st, KERNEL_FILE_NAME, boot_mode)?;
     if kernel.is_none() {
         // Try TFTP boot
         boot_mode = BootMode::Tftp;
-        kernel = load_kernel(image, &mut st, boot_mode);
+        kernel = load_kernel(image, &mut st, KERNEL_FILE_NAME, boot_mode)?;
     }
-    let kernel = kernel.expect("Failed to load kernel");
+    let kernel = kernel.ok_or((Status::NOT_FOUND, "Failed to load kernel"))?;
+    // the logger isn't initialized yet, so we log this below
+    let load_kernel_cycles = boot_timer.lap();
 
-    let config_file = load_config_file(image, &mut st, boot_mode);
+    let config_file = load_config_file(image, &mut st, boot_mode)?;
     let mut error_loading_config: Option<serde_json_core::de::Error> = None;
     let mut config: BootConfig = match config_file
         .as_deref()
@@ -96,6 +177,9 @@ fn main_inner(image: Handle, mut st: SystemTable<Boot>) -> Status {
         }
     };
 
+    #[cfg(feature = "boot-menu")]
+    let kernel = boot_menu::select_kernel(image, &mut st, boot_mode, &config.boot_menu, kernel)?;
+
     #[allow(deprecated)]
     if config.frame_buffer.minimum_framebuffer_height.is_none() {
         config.frame_buffer.minimum_framebuffer_height =
@@ -106,6 +190,10 @@ fn main_inner(image: Handle, mut st: SystemTable<Boot>) -> Status {
         config.frame_buffer.minimum_framebuffer_width =
             kernel.config.frame_buffer.minimum_framebuffer_width;
     }
+    #[cfg(feature = "boot-log")]
+    if config.boot_log_file {
+        bootloader_x86_64_common::boot_log::enable();
+    }
     let framebuffer = init_logger(image, &st, &config);
 
     unsafe {
@@ -113,6 +201,12 @@ fn main_inner(image: Handle, mut st: SystemTable<Boot>) -> Status {
     }
 
     log::info!("UEFI bootloader started");
+    log::trace!("Loading the kernel took {load_kernel_cycles} cycles");
+    match watchdog_disabled {
+        Ok(()) => log::info!("Disabled the firmware watchdog timer"),
+        Err(status) => log::warn!("Failed to disable the firmware watchdog timer: {status:?}"),
+    }
+    boot_timer.phase("Loading the config and initializing the logger");
 
     if let Some(framebuffer) = framebuffer {
         log::info!("Using framebuffer at {:#x}", framebuffer.addr);
@@ -126,7 +220,7 @@ fn main_inner(image: Handle, mut st: SystemTable<Boot>) -> Status {
 
     log::info!("Trying to load ramdisk via {:?}", boot_mode);
     // Ramdisk must load from same source, or not at all.
-    let ramdisk = load_ramdisk(image, &mut st, boot_mode);
+    let ramdisk = load_ramdisk(image, &mut st, boot_mode)?;
 
     log::info!(
         "{}",
@@ -135,16 +229,51 @@ fn main_inner(image: Handle, mut st: SystemTable<Boot>) -> Status {
             None => "Ramdisk not found.",
         }
     );
+    let cmdline = load_cmdline(image, &mut st, boot_mode)?;
+    boot_timer.phase("Loading the ramdisk and command line");
+
+    let tsc_frequency = calibrate_tsc(&st);
+    // prefer the ACPI 2.0 RSDP and the 64-bit SMBIOS 3.0 entry point over older versions
+    let rsdp_addr = find_config_table(&st, &[cfg::ACPI2_GUID, cfg::ACPI_GUID]);
+    let smbios_addr = find_config_table(&st, &[cfg::SMBIOS3_GUID, cfg::SMBIOS_GUID]);
+    let cpu_count = cpu_count(image, &st, rsdp_addr);
+    let firmware_vendor = firmware_vendor(&st);
+    let firmware_revision = st.firmware_revision();
+    log::info!("Firmware: {firmware_vendor} (revision {firmware_revision:#x})");
+    let graphics_modes = match kernel.config.report_graphics_modes {
+        true => graphics_modes(image, &st),
+        false => &[],
+    };
 
+    boot_timer.phase("Querying the firmware");
+    #[cfg(feature = "boot-log")]
+    if config.boot_log_file {
+        boot_log::write_file(image, &st, boot_mode);
+    }
     log::trace!("exiting boot services");
-    let (system_table, mut memory_map) = st.exit_boot_services();
+    #[cfg(feature = "reset-on-error")]
+    unsafe {
+        *RESET_SYSTEM_TABLE.get() = None;
+    }
+    let (_system_table, mut memory_map) = st.exit_boot_services();
 
+    // The allocator hands out frames in the order of the memory map, so sorting it by
+    // physical address makes the frame assignment deterministic for the same RAM layout,
+    // independent of the order in which the firmware reports the descriptors.
     memory_map.sort();
 
+    let usable_types = config.uefi_memory_types;
     let mut frame_allocator =
-        LegacyFrameAllocator::new(memory_map.entries().copied().map(UefiMemoryDescriptor));
+        LegacyFrameAllocator::new(memory_map.entries().copied().map(|descriptor| {
+            UefiMemoryDescriptor {
+                descriptor,
+                usable_types,
+            }
+        }));
+    frame_allocator.set_min_addr(PhysAddr::new(kernel.config.min_frame_address));
 
     let page_tables = create_page_tables(&mut frame_allocator);
+    boot_timer.phase("Exiting boot services and creating page tables");
     let mut ramdisk_len = 0u64;
     let ramdisk_addr = if let Some(rd) = ramdisk {
         ramdisk_len = rd.len() as u64;
@@ -154,27 +283,101 @@ fn main_inner(image: Handle, mut st: SystemTable<Boot>) -> Status {
     };
     let system_info = SystemInfo {
         framebuffer,
-        rsdp_addr: {
-            use uefi::table::cfg;
-            let mut config_entries = system_table.config_table().iter();
-            // look for an ACPI2 RSDP first
-            let acpi2_rsdp = config_entries.find(|entry| matches!(entry.guid, cfg::ACPI2_GUID));
-            // if no ACPI2 RSDP is found, look for a ACPI1 RSDP
-            let rsdp = acpi2_rsdp
-                .or_else(|| config_entries.find(|entry| matches!(entry.guid, cfg::ACPI_GUID)));
-            rsdp.map(|entry| PhysAddr::new(entry.address as u64))
-        },
+        rsdp_addr,
+        smbios_addr,
         ramdisk_addr,
         ramdisk_len,
+        tsc_frequency,
+        cpu_count,
+        cmdline: cmdline.map(|cmdline| &*cmdline),
+        graphics_modes,
+        firmware_vendor: Some(firmware_vendor),
+        firmware_revision,
+        boot_timer,
     };
 
-    bootloader_x86_64_common::load_and_switch_to_kernel(
+    let Err(err) = bootloader_x86_64_common::load_and_switch_to_kernel(
         kernel,
         config,
         frame_allocator,
         page_tables,
         system_info,
     );
+    // boot services are already exited, so we can't return to the firmware anymore
+    panic!("Failed to set up the kernel address space: {err}");
+}
+
+/// Copies the firmware vendor string of the system table into a static UTF-8 buffer.
+///
+/// The system table is not guaranteed to stay valid after exiting the boot services, so the
+/// string has to be copied before. Strings longer than [`BootInfo::FIRMWARE_VENDOR_MAX_LEN`]
+/// bytes are truncated at a character boundary.
+fn firmware_vendor(st: &SystemTable<Boot>) -> &'static str {
+    let buffer = unsafe { &mut *FIRMWARE_VENDOR.get() };
+    let mut len = 0;
+    for c in st.firmware_vendor().iter().map(|&c| char::from(c)) {
+        if len + c.len_utf8() > buffer.len() {
+            log::warn!("Truncating the firmware vendor string");
+            break;
+        }
+        len += c.encode_utf8(&mut buffer[len..]).len();
+    }
+    core::str::from_utf8(&buffer[..len]).unwrap()
+}
+
+/// Returns the address of the first UEFI configuration table with one of the given GUIDs.
+///
+/// The GUIDs are tried in the given order.
+fn find_config_table(st: &SystemTable<Boot>, guids: &[Guid]) -> Option<PhysAddr> {
+    guids.iter().find_map(|guid| {
+        st.config_table()
+            .iter()
+            .find(|entry| entry.guid == *guid)
+            .map(|entry| PhysAddr::new(entry.address as u64))
+    })
+}
+
+/// Queries the number of enabled CPUs through the MP services protocol.
+///
+/// Counts the processors listed in the ACPI MADT if the protocol is not available.
+fn cpu_count(image: Handle, st: &SystemTable<Boot>, rsdp_addr: Option<PhysAddr>) -> usize {
+    let boot_services = st.boot_services();
+    let mp_services = boot_services
+        .get_handle_for_protocol::<MpServices>()
+        .and_then(|handle| unsafe {
+            boot_services.open_protocol::<MpServices>(
+                OpenProtocolParams {
+                    handle,
+                    agent: image,
+                    controller: None,
+                },
+                OpenProtocolAttributes::GetProtocol,
+            )
+        });
+    match mp_services.and_then(|mp| mp.get_number_of_processors()) {
+        Ok(count) => count.enabled,
+        Err(err) => {
+            log::info!(
+                "Failed to query the number of processors ({:?}), falling back to the MADT",
+                err
+            );
+            acpi::cpu_count(rsdp_addr)
+        }
+    }
+}
+
+/// Measures the TSC frequency in Hz by stalling for a fixed amount of time.
+fn calibrate_tsc(st: &SystemTable<Boot>) -> Option<u64> {
+    const CALIBRATION_TIME_US: usize = 10_000;
+
+    let start = unsafe { core::arch::x86_64::_rdtsc() };
+    st.boot_services().stall(CALIBRATION_TIME_US);
+    let end = unsafe { core::arch::x86_64::_rdtsc() };
+
+    let ticks = end.checked_sub(start)?;
+    let frequency = ticks * (1_000_000 / CALIBRATION_TIME_US as u64);
+    log::info!("Measured TSC frequency of {} MHz", frequency / 1_000_000);
+    Some(frequency)
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -187,33 +390,95 @@ fn load_ramdisk(
     image: Handle,
     st: &mut SystemTable<Boot>,
     boot_mode: BootMode,
-) -> Option<&'static mut [u8]> {
+) -> Result<Option<&'static mut [u8]>, BootError> {
     load_file_from_boot_method(image, st, "ramdisk\0", boot_mode)
 }
 
+fn load_cmdline(
+    image: Handle,
+    st: &mut SystemTable<Boot>,
+    boot_mode: BootMode,
+) -> Result<Option<&'static mut [u8]>, BootError> {
+    load_file_from_boot_method(image, st, "cmdline\0", boot_mode)
+}
+
 fn load_config_file(
     image: Handle,
     st: &mut SystemTable<Boot>,
     boot_mode: BootMode,
-) -> Option<&'static mut [u8]> {
+) -> Result<Option<&'static mut [u8]>, BootError> {
     load_file_from_boot_method(image, st, "boot.json\0", boot_mode)
 }
 
+/// Loads and parses the kernel from the given file.
+///
+/// Returns `None` if the file doesn't exist.
 fn load_kernel(
     image: Handle,
     st: &mut SystemTable<Boot>,
+    filename: &str,
     boot_mode: BootMode,
-) -> Option<Kernel<'static>> {
-    let kernel_slice = load_file_from_boot_method(image, st, "kernel-x86_64\0", boot_mode)?;
-    Some(Kernel::parse(kernel_slice))
+) -> Result<Option<Kernel<'static>>, BootError> {
+    let Some(kernel_slice) = load_file_from_boot_method(image, st, filename, boot_mode)? else {
+        return Ok(None);
+    };
+    #[cfg(feature = "kernel-compression")]
+    let kernel_slice = decompress_kernel(st, kernel_slice)?;
+    let kernel = Kernel::try_parse(kernel_slice).map_err(|err| (Status::LOAD_ERROR, err))?;
+    Ok(Some(kernel))
 }
 
+/// Decompresses the kernel into a new `LOADER_DATA` allocation if it is LZ4-compressed.
+///
+/// This needs to happen before exiting the boot services because the memory is allocated
+/// through them. The pages of the compressed kernel are freed afterwards.
+#[cfg(feature = "kernel-compression")]
+fn decompress_kernel(
+    st: &SystemTable<Boot>,
+    compressed: &'static mut [u8],
+) -> Result<&'static mut [u8], BootError> {
+    use bootloader_x86_64_common::lz4;
+
+    let Some(len) = lz4::decompressed_len(compressed) else {
+        return Ok(compressed);
+    };
+    let ptr = st
+        .boot_services()
+        .allocate_pages(
+            AllocateType::AnyPages,
+            MemoryType::LOADER_DATA,
+            ((len - 1) / 4096) + 1,
+        )
+        .map_err(|err| {
+            (
+                err.status(),
+                "Failed to allocate memory for the decompressed kernel",
+            )
+        })? as *mut u8;
+    let kernel_slice = unsafe { slice::from_raw_parts_mut(ptr, len) };
+    lz4::decompress(compressed, kernel_slice).map_err(|err| (Status::LOAD_ERROR, err))?;
+
+    let compressed_pages = ((compressed.len() - 1) / 4096) + 1;
+    st.boot_services()
+        .free_pages(compressed.as_ptr() as u64, compressed_pages)
+        .map_err(|err| {
+            (
+                err.status(),
+                "Failed to free the memory of the compressed kernel",
+            )
+        })?;
+    Ok(kernel_slice)
+}
+
+/// Loads the given file from the boot medium into newly allocated `LOADER_DATA` memory.
+///
+/// Returns `None` if the file doesn't exist.
 fn load_file_from_boot_method(
     image: Handle,
     st: &mut SystemTable<Boot>,
     filename: &str,
     boot_mode: BootMode,
-) -> Option<&'static mut [u8]> {
+) -> Result<Option<&'static mut [u8]>, BootError> {
     match boot_mode {
         BootMode::Disk => load_file_from_disk(filename, image, st),
         BootMode::Tftp => load_file_from_tftp_boot_server(filename, image, st),
@@ -300,11 +565,15 @@ fn load_file_from_disk(
     name: &str,
     image: Handle,
     st: &SystemTable<Boot>,
-) -> Option<&'static mut [u8]> {
-    let mut file_system_raw = locate_and_open_protocol::<SimpleFileSystem>(image, st)?;
+) -> Result<Option<&'static mut [u8]>, BootError> {
+    let Some(mut file_system_raw) = locate_and_open_protocol::<SimpleFileSystem>(image, st) else {
+        return Ok(None);
+    };
     let file_system = file_system_raw.deref_mut();
 
-    let mut root = file_system.open_volume().unwrap();
+    let mut root = file_system
+        .open_volume()
+        .map_err(|err| (err.status(), "Failed to open the boot partition"))?;
     let mut buf = [0u16; 256];
     assert!(name.len() < 256);
     let filename = CStr16::from_str_with_buf(name.trim_end_matches('\0'), &mut buf)
@@ -313,17 +582,24 @@ fn load_file_from_disk(
     let file_handle_result = root.open(filename, FileMode::Read, FileAttribute::empty());
 
     let file_handle = match file_handle_result {
-        Err(_) => return None,
+        Err(_) => return Ok(None),
         Ok(handle) => handle,
     };
 
-    let mut file = match file_handle.into_type().unwrap() {
+    let mut file = match file_handle
+        .into_type()
+        .map_err(|err| (err.status(), "Failed to open file on the boot partition"))?
+    {
         uefi::proto::media::file::FileType::Regular(f) => f,
-        uefi::proto::media::file::FileType::Dir(_) => panic!(),
+        uefi::proto::media::file::FileType::Dir(_) => {
+            return Err((Status::LOAD_ERROR, "Expected a file but found a directory"))
+        }
     };
 
     let mut buf = [0; 500];
-    let file_info: &mut FileInfo = file.get_info(&mut buf).unwrap();
+    let file_info: &mut FileInfo = file
+        .get_info(&mut buf)
+        .map_err(|err| (err.status(), "Failed to query the size of a file"))?;
     let file_size = usize::try_from(file_info.file_size()).unwrap();
 
     let file_ptr = st
@@ -333,12 +609,18 @@ fn load_file_from_disk(
             MemoryType::LOADER_DATA,
             ((file_size - 1) / 4096) + 1,
         )
-        .unwrap() as *mut u8;
+        .map_err(|err| (err.status(), "Failed to allocate memory for a file"))?
+        as *mut u8;
     unsafe { ptr::write_bytes(file_ptr, 0, file_size) };
     let file_slice = unsafe { slice::from_raw_parts_mut(file_ptr, file_size) };
-    file.read(file_slice).unwrap();
+    file.read(file_slice).map_err(|err| {
+        (
+            err.status(),
+            "Failed to read a file from the boot partition",
+        )
+    })?;
 
-    Some(file_slice)
+    Ok(Some(file_slice))
 }
 
 /// Try to load a kernel from a TFTP boot server.
@@ -346,13 +628,20 @@ fn load_file_from_tftp_boot_server(
     name: &str,
     image: Handle,
     st: &SystemTable<Boot>,
-) -> Option<&'static mut [u8]> {
-    let mut base_code_raw = locate_and_open_protocol::<BaseCode>(image, st)?;
+) -> Result<Option<&'static mut [u8]>, BootError> {
+    let Some(mut base_code_raw) = locate_and_open_protocol::<BaseCode>(image, st) else {
+        return Ok(None);
+    };
     let base_code = base_code_raw.deref_mut();
 
     // Find the TFTP boot server.
     let mode = base_code.mode();
-    assert!(mode.dhcp_ack_received);
+    if !mode.dhcp_ack_received {
+        return Err((
+            Status::NOT_READY,
+            "No DHCP acknowledgement was received for the TFTP boot",
+        ));
+    }
     let dhcpv4: &DhcpV4Packet = mode.dhcp_ack.as_ref();
     let server_ip = IpAddress::new_v4(dhcpv4.bootp_si_addr);
     assert!(name.len() < 256);
@@ -360,7 +649,9 @@ fn load_file_from_tftp_boot_server(
     let filename = CStr8::from_bytes_with_nul(name.as_bytes()).unwrap();
 
     // Determine the kernel file size.
-    let file_size = base_code.tftp_get_file_size(&server_ip, filename).ok()?;
+    let Ok(file_size) = base_code.tftp_get_file_size(&server_ip, filename) else {
+        return Ok(None);
+    };
     let kernel_size = usize::try_from(file_size).expect("The file size should fit into usize");
 
     // Allocate some memory for the kernel file.
@@ -371,35 +662,60 @@ fn load_file_from_tftp_boot_server(
             MemoryType::LOADER_DATA,
             ((kernel_size - 1) / 4096) + 1,
         )
-        .expect("Failed to allocate memory for the file") as *mut u8;
+        .map_err(|err| (err.status(), "Failed to allocate memory for a file"))?
+        as *mut u8;
     let slice = unsafe { slice::from_raw_parts_mut(ptr, kernel_size) };
 
     // Load the kernel file.
     base_code
         .tftp_read_file(&server_ip, filename, Some(slice))
-        .expect("Failed to read kernel file from the TFTP boot server");
+        .map_err(|err| {
+            (
+                err.status(),
+                "Failed to read a file from the TFTP boot server",
+            )
+        })?;
 
-    Some(slice)
+    Ok(Some(slice))
+}
+
+/// Panics with a clear message if the given frame is not identity-mapped in the given page
+/// table, instead of faulting (or silently corrupting memory) on the first access.
+fn check_identity_mapped(page_table: &impl Translate, frame: PhysFrame) {
+    let addr = frame.start_address();
+    match page_table.translate_addr(VirtAddr::new(addr.as_u64())) {
+        Some(mapped) if mapped == addr => {}
+        mapped => panic!(
+            "the UEFI firmware does not identity-map physical memory: virtual address \
+            {addr:#x} is mapped to {mapped:x?}"
+        ),
+    }
 }
 
 /// Creates page table abstraction types for both the bootloader and kernel page tables.
 fn create_page_tables(
     frame_allocator: &mut impl FrameAllocator<Size4KiB>,
 ) -> bootloader_x86_64_common::PageTables {
-    // UEFI identity-maps all memory, so the offset between physical and virtual addresses is 0
+    // The UEFI specification requires that "any memory space defined by the UEFI memory map is
+    // identity mapped (virtual address equals physical address)" on x64 platforms (section
+    // 2.3.4), so the offset between physical and virtual addresses is 0. This includes the
+    // conventional memory that the frame allocator hands out, which we verify below for the
+    // frames of the new level 4 tables before writing to them.
     let phys_offset = VirtAddr::new(0);
 
     // copy the currently active level 4 page table, because it might be read-only
     log::trace!("switching to new level 4 table");
     let bootloader_page_table = {
-        let old_table = {
+        let mut old_table = {
             let frame = x86_64::registers::control::Cr3::read().0;
-            let ptr: *const PageTable = (phys_offset + frame.start_address().as_u64()).as_ptr();
-            unsafe { &*ptr }
+            let ptr: *mut PageTable = (phys_offset + frame.start_address().as_u64()).as_mut_ptr();
+            // only used for lookups, the table is never modified through this reference
+            unsafe { OffsetPageTable::new(&mut *ptr, phys_offset) }
         };
         let new_frame = frame_allocator
             .allocate_frame()
-            .expect("Failed to allocate frame for new level 4 table");
+            .expect("out of physical memory while copying the level 4 page table");
+        check_identity_mapped(&old_table, new_frame);
         let new_table: &mut PageTable = {
             let ptr: *mut PageTable =
                 (phys_offset + new_frame.start_address().as_u64()).as_mut_ptr();
@@ -412,7 +728,7 @@ fn create_page_tables(
 
         // copy the first entry (we don't need to access more than 512 GiB; also, some UEFI
         // implementations seem to create an level 4 table entry 0 in all slots)
-        new_table[0] = old_table[0].clone();
+        new_table[0] = old_table.level_4_table()[0].clone();
 
         // the first level 4 table entry is now identical, so we can just load the new one
         unsafe {
@@ -427,7 +743,10 @@ fn create_page_tables(
     // create a new page table hierarchy for the kernel
     let (kernel_page_table, kernel_level_4_frame) = {
         // get an unused frame for new level 4 page table
-        let frame: PhysFrame = frame_allocator.allocate_frame().expect("no unused frames");
+        let frame: PhysFrame = frame_allocator
+            .allocate_frame()
+            .expect("out of physical memory while creating the kernel page table");
+        check_identity_mapped(&bootloader_page_table, frame);
         log::info!("New page table at: {:#?}", &frame);
         // get the corresponding virtual address
         let addr = phys_offset + frame.start_address().as_u64();
@@ -453,6 +772,40 @@ fn init_logger(
     st: &SystemTable<Boot>,
     config: &BootConfig,
 ) -> Option<RawFrameBufferInfo> {
+    let mut mode_error = None;
+    let (slice, framebuffer) = open_framebuffer(image_handle, st, config, &mut mode_error).unzip();
+
+    // Headless machines might not provide a GOP at all. Log to the serial port instead of
+    // the framebuffer in that case, so that the boot output doesn't disappear silently.
+    let serial_logging =
+        config.serial_logging || (config.frame_buffer_logging && framebuffer.is_none());
+    bootloader_x86_64_common::init_logger(
+        slice.zip(framebuffer.map(|framebuffer| framebuffer.info)),
+        config.log_level,
+        config.frame_buffer_logging,
+        serial_logging,
+        config.unknown_pixel_format,
+    );
+
+    log::info!("UEFI boot");
+
+    if let Some(err) = mode_error {
+        log::warn!("Failed to set the display mode, keeping the current mode: {err:?}");
+    }
+
+    if framebuffer.is_none() && serial_logging {
+        log::warn!("No usable framebuffer found, only logging to serial port");
+    }
+
+    framebuffer
+}
+
+fn open_framebuffer(
+    image_handle: Handle,
+    st: &SystemTable<Boot>,
+    config: &BootConfig,
+    mode_error: &mut Option<uefi::Error>,
+) -> Option<(&'static mut [u8], RawFrameBufferInfo)> {
     let gop_handle = st
         .boot_services()
         .get_handle_for_protocol::<GraphicsOutput>()
@@ -470,7 +823,26 @@ fn init_logger(
             .ok()?
     };
 
-    let mode = {
+    let mode = if let Some(resolution) = config.frame_buffer.preferred_resolution {
+        let modes = gop
+            .modes()
+            .filter(|m| m.info().pixel_format() != PixelFormat::BltOnly);
+        match resolution {
+            PreferredResolution::Exact { width, height } => {
+                let width = usize::try_from(width).unwrap();
+                let height = usize::try_from(height).unwrap();
+                modes.min_by_key(|m| {
+                    let res = m.info().resolution();
+                    res.0.abs_diff(width) + res.1.abs_diff(height)
+                })
+            }
+            PreferredResolution::Highest => modes.max_by_key(|m| {
+                let res = m.info().resolution();
+                res.0 * res.1
+            }),
+            _ => None,
+        }
+    } else {
         let modes = gop.modes();
         match (
             config
@@ -494,41 +866,149 @@ fn init_logger(
         }
     };
     if let Some(mode) = mode {
-        gop.set_mode(&mode)
-            .expect("Failed to apply the desired display mode");
+        if let Err(err) = gop.set_mode(&mode) {
+            *mode_error = Some(err);
+        }
     }
 
     let mode_info = gop.current_mode_info();
+    let pixel_format = convert_pixel_format(&mode_info)?;
     let mut framebuffer = gop.frame_buffer();
     let slice = unsafe { slice::from_raw_parts_mut(framebuffer.as_mut_ptr(), framebuffer.size()) };
     let info = FrameBufferInfo {
         byte_len: framebuffer.size(),
         width: mode_info.resolution().0,
         height: mode_info.resolution().1,
-        pixel_format: match mode_info.pixel_format() {
-            PixelFormat::Rgb => bootloader_api::info::PixelFormat::Rgb,
-            PixelFormat::Bgr => bootloader_api::info::PixelFormat::Bgr,
-            PixelFormat::Bitmask | PixelFormat::BltOnly => {
-                panic!("Bitmask and BltOnly framebuffers are not supported")
-            }
-        },
+        pixel_format,
         bytes_per_pixel: 4,
         stride: mode_info.stride(),
     };
 
-    log::info!("UEFI boot");
-
-    bootloader_x86_64_common::init_logger(
-        slice,
-        info,
-        config.log_level,
-        config.frame_buffer_logging,
-        config.serial_logging,
-    );
-
-    Some(RawFrameBufferInfo {
+    let raw_info = RawFrameBufferInfo {
         addr: PhysAddr::new(framebuffer.as_mut_ptr() as u64),
         info,
+        id: framebuffer_id(image_handle, st, gop_handle),
+        firmware_pixel_format: Some(mode_info.pixel_format() as u32),
+    };
+    Some((slice, raw_info))
+}
+
+/// Converts the pixel format of the given GOP mode.
+///
+/// Returns `None` for modes without a framebuffer that we could write to directly.
+fn convert_pixel_format(mode_info: &ModeInfo) -> Option<bootloader_api::info::PixelFormat> {
+    let pixel_format = match mode_info.pixel_format() {
+        PixelFormat::Rgb => bootloader_api::info::PixelFormat::Rgb,
+        PixelFormat::Bgr => bootloader_api::info::PixelFormat::Bgr,
+        PixelFormat::Bitmask => {
+            let mask = mode_info.pixel_bitmask()?;
+            bootloader_api::info::PixelFormat::Unknown {
+                red_position: mask.red.trailing_zeros() as u8,
+                green_position: mask.green.trailing_zeros() as u8,
+                blue_position: mask.blue.trailing_zeros() as u8,
+            }
+        }
+        PixelFormat::BltOnly => return None,
+    };
+    Some(pixel_format)
+}
+
+/// Queries all graphics modes of the GOP that have a linear framebuffer.
+///
+/// The list is stored in `LOADER_DATA` memory, so it stays valid after exiting the boot
+/// services. Returns an empty list if there is no GOP or if the memory for the list can't be
+/// allocated, as the list is optional.
+fn graphics_modes(image_handle: Handle, st: &SystemTable<Boot>) -> &'static [GraphicsMode] {
+    let Ok(gop_handle) = st
+        .boot_services()
+        .get_handle_for_protocol::<GraphicsOutput>()
+    else {
+        return &[];
+    };
+    let Ok(gop) = (unsafe {
+        st.boot_services().open_protocol::<GraphicsOutput>(
+            OpenProtocolParams {
+                handle: gop_handle,
+                agent: image_handle,
+                controller: None,
+            },
+            OpenProtocolAttributes::GetProtocol,
+        )
+    }) else {
+        return &[];
+    };
+
+    let count = gop.modes().count();
+    if count == 0 {
+        return &[];
+    }
+    let size = count * mem::size_of::<GraphicsMode>();
+    let ptr = match st.boot_services().allocate_pages(
+        AllocateType::AnyPages,
+        MemoryType::LOADER_DATA,
+        ((size - 1) / 4096) + 1,
+    ) {
+        Ok(ptr) => ptr as *mut GraphicsMode,
+        Err(err) => {
+            log::warn!(
+                "Failed to allocate memory for the graphics mode list: {:?}",
+                err.status()
+            );
+            return &[];
+        }
+    };
+    let buffer = unsafe { slice::from_raw_parts_mut(ptr.cast::<mem::MaybeUninit<_>>(), count) };
+
+    let modes = gop.modes().filter_map(|mode| {
+        let info = mode.info();
+        Some(GraphicsMode {
+            width: info.resolution().0,
+            height: info.resolution().1,
+            stride: info.stride(),
+            pixel_format: convert_pixel_format(info)?,
+            bytes_per_pixel: 4,
+        })
+    });
+    let mut len = 0;
+    for (slot, mode) in buffer.iter_mut().zip(modes) {
+        slot.write(mode);
+        len += 1;
+    }
+    log::info!("Found {len} graphics modes with a linear framebuffer");
+    unsafe { slice::from_raw_parts(ptr, len) }
+}
+
+/// Derives a stable framebuffer identifier by hashing the device path of the given
+/// GOP handle with the 64-bit FNV-1a hash function.
+///
+/// Returns `0` if the handle has no device path.
+fn framebuffer_id(image_handle: Handle, st: &SystemTable<Boot>, gop_handle: Handle) -> u64 {
+    let device_path = unsafe {
+        st.boot_services().open_protocol::<DevicePath>(
+            OpenProtocolParams {
+                handle: gop_handle,
+                agent: image_handle,
+                controller: None,
+            },
+            OpenProtocolAttributes::GetProtocol,
+        )
+    };
+    let Ok(device_path) = device_path else {
+        log::warn!("Failed to open DevicePath protocol of GOP handle");
+        return 0;
+    };
+    let device_path: &DevicePath = &device_path;
+    let bytes = unsafe {
+        slice::from_raw_parts(
+            device_path.as_ffi_ptr().cast::<u8>(),
+            mem::size_of_val(device_path),
+        )
+    };
+
+    const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const FNV_PRIME: u64 = 0x0100_0000_01b3;
+    bytes.iter().fold(FNV_OFFSET_BASIS, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(FNV_PRIME)
     })
 }
 
@@ -550,6 +1030,9 @@ fn panic(info: &core::panic::PanicInfo) -> ! {
     };
     log::error!("{}", info);
 
+    // resets the machine if enabled, as long as the boot services are still active
+    let _ = boot_failed(Status::ABORTED);
+
     loop {
         unsafe { asm!("cli; hlt") };
     }
@@ -1,40 +1,45 @@
 use bootloader_api::info::MemoryRegionKind;
+use bootloader_boot_config::UefiMemoryTypes;
 use bootloader_x86_64_common::legacy_memory_region::LegacyMemoryRegion;
 use uefi::table::boot::{MemoryDescriptor, MemoryType};
 use x86_64::PhysAddr;
 
 #[derive(Debug, Copy, Clone)]
-pub struct UefiMemoryDescriptor(pub MemoryDescriptor);
+pub struct UefiMemoryDescriptor {
+    pub descriptor: MemoryDescriptor,
+    /// Specifies which memory types become usable after the bootloader exits.
+    pub usable_types: UefiMemoryTypes,
+}
 
 const PAGE_SIZE: u64 = 4096;
 
 impl LegacyMemoryRegion for UefiMemoryDescriptor {
     fn start(&self) -> PhysAddr {
-        PhysAddr::new(self.0.phys_start)
+        PhysAddr::new(self.descriptor.phys_start)
     }
 
     fn len(&self) -> u64 {
-        self.0.page_count * PAGE_SIZE
+        self.descriptor.page_count * PAGE_SIZE
     }
 
     fn kind(&self) -> MemoryRegionKind {
-        match self.0.ty {
+        match self.descriptor.ty {
             MemoryType::CONVENTIONAL => MemoryRegionKind::Usable,
+            MemoryType::ACPI_RECLAIM => MemoryRegionKind::AcpiReclaimable,
+            MemoryType::ACPI_NON_VOLATILE => MemoryRegionKind::AcpiNvs,
             other => MemoryRegionKind::UnknownUefi(other.0),
         }
     }
 
     fn usable_after_bootloader_exit(&self) -> bool {
-        match self.0.ty {
+        // we don't need this data anymore after the bootloader passes control to the
+        // kernel, so these types are usable unless the boot config says otherwise
+        match self.descriptor.ty {
             MemoryType::CONVENTIONAL => true,
-            MemoryType::LOADER_CODE
-            | MemoryType::LOADER_DATA
-            | MemoryType::BOOT_SERVICES_CODE
-            | MemoryType::BOOT_SERVICES_DATA => {
-                // we don't need this data anymore after the bootloader
-                // passes control to the kernel
-                true
-            }
+            MemoryType::LOADER_CODE => self.usable_types.loader_code,
+            MemoryType::LOADER_DATA => self.usable_types.loader_data,
+            MemoryType::BOOT_SERVICES_CODE => self.usable_types.boot_services_code,
+            MemoryType::BOOT_SERVICES_DATA => self.usable_types.boot_services_data,
             MemoryType::RUNTIME_SERVICES_CODE | MemoryType::RUNTIME_SERVICES_DATA => {
                 // the UEFI standard specifies that these should be presevered
                 // by the bootloader and operating system
@@ -43,4 +48,7 @@ impl LegacyMemoryRegion for UefiMemoryDescriptor {
             _ => false,
         }
     }
+    fn attributes(&self) -> u64 {
+        self.descriptor.att.bits()
+    }
 }